@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use insploray::Vec3;
+use insploray::renderer::{Aov, FilterKind, SamplerKind, TileOrder, ToneMapper};
+
+/// Headless offline renderer for InsploRay scenes
+#[derive(Parser, Debug)]
+#[command(name = "insploray-cli", version)]
+pub struct Args {
+    /// TOML scene file, the flags below override the settings it contains
+    #[arg(long, conflicts_with = "obj")]
+    pub scene: Option<PathBuf>,
+
+    /// Wavefront OBJ to render, the built-in example scene is used when omitted
+    #[arg(long)]
+    pub obj: Option<PathBuf>,
+
+    /// EXR used as skybox and environment light
+    #[arg(long)]
+    pub skybox: Option<PathBuf>,
+
+    /// Output image path, `.exr` keeps linear HDR radiance, `.png` goes through the display transform
+    #[arg(short, long, default_value = "render.png", value_parser = parse_image_path)]
+    pub output: PathBuf,
+
+    /// Image width [default: 1280]
+    #[arg(long)]
+    pub width: Option<u32>,
+
+    /// Image height [default: 720]
+    #[arg(long)]
+    pub height: Option<u32>,
+
+    /// Samples per pixel, every sample is one full render pass [default: 64]
+    #[arg(long)]
+    pub spp: Option<u32>,
+
+    /// [default: 5]
+    #[arg(long)]
+    pub bounces: Option<usize>,
+
+    /// Worker threads [default: available cores]
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// Pixels along the side of a render tile [default: 64]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_size: Option<u32>,
+
+    /// Order tiles are handed to the workers: scanline, spiral or hilbert [default: spiral]
+    #[arg(long)]
+    pub tile_order: Option<TileOrder>,
+
+    /// Sampling pattern: independent, stratified, halton or sobol [default: sobol]
+    #[arg(long)]
+    pub sampler: Option<SamplerKind>,
+
+    /// Pixel reconstruction filter: box, tent, gaussian, mitchell or blackman-harris [default: gaussian]
+    #[arg(long)]
+    pub filter: Option<FilterKind>,
+
+    /// Filter radius in pixels [default: depends on the filter]
+    #[arg(long, value_parser = parse_positive)]
+    pub filter_radius: Option<f32>,
+
+    /// Adaptive sampling: tiles whose noise estimate drops below this stop sampling,
+    /// 0 renders every pixel with all samples [default: 0]
+    #[arg(long)]
+    pub noise_threshold: Option<f32>,
+
+    /// Samples every pixel gets before adaptive sampling may stop it [default: 16]
+    #[arg(long)]
+    pub min_samples: Option<u32>,
+
+    /// Wall-clock budget in seconds, the render stops at whichever of the sample count,
+    /// the time limit or the noise threshold comes first
+    #[arg(long, value_parser = parse_seconds)]
+    pub time_limit: Option<Duration>,
+
+    /// Comma separated AOVs written as extra layers into EXR output: depth, position, normal,
+    /// albedo, material-id, object-id, direct, indirect, emission or sample-count
+    #[arg(long, value_delimiter = ',')]
+    pub aov: Vec<Aov>,
+
+    /// Filters the noise out of the image, guided by the albedo, normal and depth of the first
+    /// hits. EXR output keeps the noisy image as an extra `noisy` layer
+    #[arg(long)]
+    pub denoise: bool,
+
+    /// Tone curve of PNG output: linear, reinhard, aces, agx or hable [default: reinhard]
+    #[arg(long)]
+    pub tone_mapper: Option<ToneMapper>,
+
+    /// Exposure of PNG output in stops, every stop doubles the brightness [default: 0]
+    #[arg(long, allow_negative_numbers = true)]
+    pub exposure: Option<f32>,
+
+    /// Radiance that reinhard maps to white [default: 16]
+    #[arg(long, value_parser = parse_positive)]
+    pub white_point: Option<f32>,
+
+    /// Saves the render state to this file at intervals and at the end, see --resume
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoints
+    #[arg(long, value_parser = parse_seconds, default_value = "300", requires = "checkpoint")]
+    pub checkpoint_interval: Duration,
+
+    /// Continues the render saved in the --checkpoint file if there is one, the scene and
+    /// settings must be the ones it was saved with
+    #[arg(long, requires = "checkpoint")]
+    pub resume: bool,
+
+    /// Instead of rendering, combines checkpoints of the same frame rendered with different
+    /// --seed values, e.g. on several machines, into the output image. The other flags must
+    /// match the ones of the renders
+    #[arg(long, num_args = 1.., conflicts_with_all = ["checkpoint", "resume"])]
+    pub merge: Vec<PathBuf>,
+
+    /// Renders on worker processes instead of this machine, listening for them on this
+    /// address, e.g. 0.0.0.0:7878. Workers may connect at any time
+    #[arg(long, conflicts_with = "merge")]
+    pub coordinator: Option<String>,
+
+    /// Renders tiles for the coordinator at this address, e.g. 192.168.1.10:7878, instead of
+    /// an image of its own. The scene and flags must match the coordinator's
+    #[arg(long, conflicts_with_all = ["coordinator", "merge", "checkpoint"])]
+    pub worker: Option<String>,
+
+    /// Passes a tile job of the coordinator covers, more means fewer round trips [default: 1]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), requires = "coordinator")]
+    pub passes_per_job: Option<u32>,
+
+    /// Also writes a PNG showing how many samples each pixel received
+    #[arg(long)]
+    pub heatmap: Option<PathBuf>,
+
+    /// Seed of the random numbers, equal seeds give identical images [default: 0]
+    #[arg(long)]
+    pub seed: Option<u32>,
+
+    /// Camera position as x,y,z [default: 0,0,2]
+    #[arg(long, value_parser = parse_vec3)]
+    pub camera_position: Option<Vec3>,
+
+    /// Camera euler rotation in radians as x,y,z [default: 0,0,0]
+    #[arg(long, value_parser = parse_vec3)]
+    pub camera_rotation: Option<Vec3>,
+
+    /// Focal length in millimetres [default: 35]
+    #[arg(long)]
+    pub focal_length: Option<f32>,
+
+    /// Sensor size in millimetres [default: 55]
+    #[arg(long)]
+    pub sensor_size: Option<f32>,
+
+    /// Aperture f-number, switches to a thin lens camera with depth of field [default: 2.8]
+    #[arg(long, value_parser = parse_positive)]
+    pub f_stop: Option<f32>,
+
+    /// Distance of the plane in focus, switches to a thin lens camera [default: 2]
+    #[arg(long, value_parser = parse_positive)]
+    pub focus_distance: Option<f32>,
+
+    /// Also prints debug messages of the engine
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+impl Args {
+    /// Any of the flags of the display transform, which only PNG output goes through
+    pub fn has_display_flags(&self) -> bool {
+        self.tone_mapper.is_some() || self.exposure.is_some() || self.white_point.is_some()
+    }
+}
+
+fn parse_positive(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(v),
+        Ok(_) => Err(format!("expected a finite positive value, got '{value}'")),
+        Err(e) => Err(format!("invalid number '{value}': {e}")),
+    }
+}
+
+/// Checked before rendering, finding out when saving could waste hours
+fn parse_image_path(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(e) if e.eq_ignore_ascii_case("exr") || e.eq_ignore_ascii_case("png") => Ok(path),
+        _ => Err(format!(
+            "unsupported image format '{value}', use .exr or .png"
+        )),
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds = parse_positive(value)?;
+    Duration::try_from_secs_f32(seconds).map_err(|e| format!("invalid duration '{value}': {e}"))
+}
+
+fn parse_vec3(value: &str) -> Result<Vec3, String> {
+    let components: Vec<f32> = value
+        .split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("invalid number in '{value}': {e}"))?;
+
+    match components[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!(
+            "expected three comma separated values, got '{value}'"
+        )),
+    }
+}
@@ -0,0 +1,368 @@
+mod args;
+mod logger;
+mod progress;
+
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use insploray::Vec3;
+use insploray::cameras::{Camera, CameraKind, CameraModel};
+use insploray::distributed::{Coordinator, run_worker};
+use insploray::file_formats::{ExrImage, ObjModel, SceneFile};
+use insploray::renderer::{Filter, RayTracer};
+use insploray::scene::{EXAMPLE_SKYBOX_PATH, Scene};
+use insploray::session::{RenderProgress, RenderSession};
+
+use args::Args;
+use progress::ProgressBar;
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    logger::init(args.verbose);
+
+    let mut file = match load_scene(&args) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    apply_overrides(&mut file, &args);
+
+    let exr_output = args
+        .output
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("exr"));
+    if !file.settings.aovs.is_empty() && !exr_output {
+        eprintln!("warning: AOVs are only written to EXR output");
+    }
+    if exr_output && args.has_display_flags() {
+        eprintln!(
+            "warning: EXR output keeps linear radiance, the display transform is not applied"
+        );
+    }
+
+    let SceneFile {
+        scene,
+        camera,
+        settings,
+        width,
+        height,
+        ..
+    } = file;
+    let scene = Arc::new(RwLock::new(scene));
+
+    let mut renderer = RayTracer::with_settings(width, height, settings);
+    renderer.set_active_camera(Arc::new(RwLock::new(camera)));
+
+    if let Some(address) = &args.worker {
+        eprintln!("Rendering tiles for the coordinator at {address}");
+        return match run_worker(address.as_str(), renderer, &scene, width, height) {
+            Ok(jobs) => {
+                eprintln!("Coordinator done after {jobs} jobs");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("error: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let rendered = if !args.merge.is_empty() {
+        merge(renderer, &scene, &args)
+    } else if let Some(address) = &args.coordinator {
+        render_distributed(address, renderer, scene, width, height, &args)
+    } else {
+        render(renderer, scene, width, height, &args)
+    };
+    let mut renderer = match rendered {
+        Ok(renderer) => renderer,
+        Err(code) => return code,
+    };
+
+    if let Err(e) = save_image(&mut renderer, &args.output) {
+        eprintln!("error: failed writing {}: {e}", args.output.display());
+        return ExitCode::FAILURE;
+    }
+
+    eprintln!("Saved {}", args.output.display());
+
+    if let Some(path) = &args.heatmap {
+        if let Err(e) = renderer.save_heatmap_png(&path.to_string_lossy()) {
+            eprintln!("error: failed writing {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+        eprintln!("Saved {}", path.display());
+    }
+    ExitCode::SUCCESS
+}
+
+/// Renders until a stop condition of the settings is met, resuming from and saving
+/// checkpoints when asked to
+fn render(
+    mut renderer: RayTracer,
+    scene: Arc<RwLock<Scene>>,
+    width: u32,
+    height: u32,
+    args: &Args,
+) -> Result<RayTracer, ExitCode> {
+    resume(&mut renderer, &scene, args)?;
+    let checkpoint = args.checkpoint.as_ref().map(|path| path.to_string_lossy());
+    let session = RenderSession::start(renderer, scene, width, height);
+    if let Some(path) = &checkpoint {
+        session.save_checkpoints(path, args.checkpoint_interval);
+    }
+    let progress_bar = ProgressBar::new();
+    let progress = loop {
+        let progress = session.wait_for_progress(Duration::from_millis(250));
+        progress_bar.update(&progress);
+        if progress.is_done() {
+            break progress;
+        }
+    };
+    progress_bar.finish();
+    if let Some(e) = session.take_error() {
+        eprintln!("error: {e}");
+        return Err(ExitCode::FAILURE);
+    }
+    if let Some(reason) = progress.stop_reason {
+        eprintln!("Stopped after {} spp, {reason}", progress.passes);
+    }
+    Ok(session.into_renderer())
+}
+
+/// Hands the passes to the workers connected to `address` until a stop condition of the
+/// settings is met, checkpoints are saved like for a local render
+fn render_distributed(
+    address: &str,
+    mut renderer: RayTracer,
+    scene: Arc<RwLock<Scene>>,
+    width: u32,
+    height: u32,
+    args: &Args,
+) -> Result<RayTracer, ExitCode> {
+    resume(&mut renderer, &scene, args)?;
+    let checkpoint_scene = Arc::clone(&scene);
+    let mut coordinator = match Coordinator::bind(address, renderer, scene, width, height) {
+        Ok(coordinator) => coordinator,
+        Err(e) => {
+            eprintln!("error: failed starting the coordinator on {address}: {e}");
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    coordinator.set_passes_per_job(args.passes_per_job.unwrap_or(1));
+    eprintln!("Waiting for workers on {}", coordinator.local_addr());
+
+    let checkpoint = args.checkpoint.as_ref().map(|path| path.to_string_lossy());
+    let mut last_checkpoint = Instant::now();
+    let progress_bar = ProgressBar::new();
+    let started = Instant::now();
+    let first_pass = coordinator.renderer().get_passes();
+    let stop_reason = loop {
+        let result = coordinator.render_passes();
+        let renderer = coordinator.renderer();
+        if let Some(path) = &checkpoint
+            && (!matches!(result, Ok(None))
+                || last_checkpoint.elapsed() >= args.checkpoint_interval)
+        {
+            if let Err(e) = renderer.save_checkpoint(path, &checkpoint_scene) {
+                eprintln!("warning: checkpoint {path} not saved: {e}");
+            }
+            last_checkpoint = Instant::now();
+        }
+
+        let stop_reason = match result {
+            Ok(stop_reason) => stop_reason,
+            Err(e) => {
+                progress_bar.finish();
+                eprintln!("error: {e}");
+                return Err(ExitCode::FAILURE);
+            }
+        };
+
+        let passes = renderer.get_passes();
+        let target_passes = renderer.get_samples_per_pixel();
+        let elapsed = started.elapsed();
+        // nothing rendered yet when a resumed render was already done
+        let rendered = passes - first_pass;
+        let remaining = target_passes.saturating_sub(passes);
+        progress_bar.update(&RenderProgress {
+            passes,
+            target_passes,
+            rays_per_second: coordinator.get_rays_traced() as f64 / elapsed.as_secs_f64(),
+            elapsed,
+            eta: (rendered > 0).then(|| elapsed.mul_f64(remaining as f64 / rendered as f64)),
+            ..Default::default()
+        });
+        if let Some(reason) = stop_reason {
+            break reason;
+        }
+    };
+    progress_bar.finish();
+    eprintln!(
+        "Stopped after {} spp, {stop_reason}",
+        coordinator.renderer().get_passes()
+    );
+    Ok(coordinator.into_renderer())
+}
+
+/// Loads the --checkpoint file into `renderer` with --resume, when there is one
+fn resume(
+    renderer: &mut RayTracer,
+    scene: &Arc<RwLock<Scene>>,
+    args: &Args,
+) -> Result<(), ExitCode> {
+    let Some(path) = args.checkpoint.as_ref().map(|path| path.to_string_lossy()) else {
+        return Ok(());
+    };
+    if !args.resume {
+        return Ok(());
+    }
+    if !Path::new(path.as_ref()).exists() {
+        eprintln!("No checkpoint at {path}, starting a new render");
+    } else if let Err(e) = renderer.resume_from_checkpoint(&path, scene) {
+        eprintln!("error: failed resuming from {path}: {e}");
+        return Err(ExitCode::FAILURE);
+    } else {
+        eprintln!("Resuming after {} spp from {path}", renderer.get_passes());
+    }
+    Ok(())
+}
+
+/// Sums the --merge checkpoints into `renderer`
+fn merge(
+    mut renderer: RayTracer,
+    scene: &Arc<RwLock<Scene>>,
+    args: &Args,
+) -> Result<RayTracer, ExitCode> {
+    let paths: Vec<_> = args.merge.iter().map(|p| p.to_string_lossy()).collect();
+    let paths: Vec<&str> = paths.iter().map(|p| p.as_ref()).collect();
+    if let Err(e) = renderer.merge_checkpoints(&paths, scene) {
+        eprintln!("error: failed merging checkpoints: {e}");
+        return Err(ExitCode::FAILURE);
+    }
+    eprintln!(
+        "Merged {} checkpoints, {} spp",
+        paths.len(),
+        renderer.get_passes()
+    );
+    Ok(renderer)
+}
+
+fn load_scene(args: &Args) -> Result<SceneFile, String> {
+    let mut file = if let Some(path) = &args.scene {
+        let path = path.to_string_lossy();
+        SceneFile::load_scene_file(&path)
+            .map_err(|e| format!("failed loading scene {path}: {e}"))?
+    } else if let Some(path) = &args.obj {
+        let path = path.to_string_lossy();
+        let model = ObjModel::load_obj_model(&path)
+            .map_err(|e| format!("failed loading OBJ {path}: {e}"))?;
+
+        let mut scene = Scene::default();
+        scene.default_sky_color = Vec3::new(0.6, 0.7, 0.9);
+        scene.add_obj_model(model);
+        SceneFile::from_scene(scene)
+    } else {
+        let mut scene = Scene::get_example_scene();
+        if args.skybox.is_none()
+            && let Err(e) = scene.load_skybox(EXAMPLE_SKYBOX_PATH)
+        {
+            eprintln!(
+                "warning: example skybox {EXAMPLE_SKYBOX_PATH} not loaded, using the sky color: {e}"
+            );
+        }
+        SceneFile::from_scene(scene)
+    };
+
+    if let Some(path) = &args.skybox {
+        let path = path.to_string_lossy();
+        let skybox = ExrImage::load_exr_image(&path)
+            .map_err(|e| format!("failed loading EXR {path}: {e}"))?;
+        file.scene.skybox = Some(skybox);
+    }
+
+    Ok(file)
+}
+
+/// Command line flags win over the values of the scene file
+fn apply_overrides(file: &mut SceneFile, args: &Args) {
+    file.width = args.width.unwrap_or(file.width);
+    file.height = args.height.unwrap_or(file.height);
+    file.settings.samples_per_pixel = args.spp.unwrap_or(file.settings.samples_per_pixel);
+    file.settings.bounces = args.bounces.unwrap_or(file.settings.bounces);
+    file.settings.thread_count = args.threads.unwrap_or(file.settings.thread_count);
+    file.settings.tile_size = args.tile_size.unwrap_or(file.settings.tile_size);
+    file.settings.tile_order = args.tile_order.unwrap_or(file.settings.tile_order);
+    file.settings.seed = args.seed.unwrap_or(file.settings.seed);
+    file.settings.sampler = args.sampler.unwrap_or(file.settings.sampler);
+    file.settings.noise_threshold = args
+        .noise_threshold
+        .unwrap_or(file.settings.noise_threshold);
+    file.settings.min_samples = args.min_samples.unwrap_or(file.settings.min_samples);
+    if !args.aov.is_empty() {
+        file.settings.aovs = args.aov.iter().copied().collect();
+    }
+    file.settings.denoise |= args.denoise;
+    let display = &mut file.settings.display;
+    display.tone_mapper = args.tone_mapper.unwrap_or(display.tone_mapper);
+    display.exposure = args.exposure.unwrap_or(display.exposure);
+    display.white_point = args.white_point.unwrap_or(display.white_point);
+    if let Some(limit) = args.time_limit {
+        file.settings.time_limit = Some(limit);
+    }
+    if let Some(kind) = args.filter {
+        file.settings.filter = Filter::new(kind);
+    }
+    if let Some(radius) = args.filter_radius {
+        file.settings.filter.radius = radius;
+    }
+
+    // any lens flag turns a pinhole camera into a thin lens one
+    if (args.f_stop.is_some() || args.focus_distance.is_some())
+        && let CameraModel::Pinhole(pinhole) = &file.camera
+    {
+        file.camera = CameraModel::new(CameraKind::ThinLens, pinhole.clone());
+    }
+
+    let camera = &mut file.camera;
+    if let CameraModel::ThinLens(lens) = camera {
+        lens.aperture.f_stop = args.f_stop.unwrap_or(lens.aperture.f_stop);
+        lens.aperture.focus_distance = args.focus_distance.unwrap_or(lens.aperture.focus_distance);
+    }
+    if let Some(focal_length) = args.focal_length {
+        camera.pinhole_mut().set_focal_length(focal_length);
+    }
+    if let Some(sensor_size) = args.sensor_size {
+        camera.pinhole_mut().set_sensor_size(sensor_size);
+    }
+    if let Some(position) = args.camera_position {
+        camera.set_position(position);
+    }
+    if let Some(rotation) = args.camera_rotation {
+        camera.set_rotation(rotation);
+    }
+    camera.set_image_resolutions([file.width, file.height]);
+}
+
+/// Picks EXR (linear HDR) or PNG (tone mapped) from the file extension
+fn save_image(renderer: &mut RayTracer, path: &Path) -> Result<(), String> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let path_str = path.to_string_lossy();
+
+    match extension.as_str() {
+        "exr" => renderer.save_exr(&path_str).map_err(|e| e.to_string()),
+        "png" => renderer.save_png(&path_str).map_err(|e| e.to_string()),
+        _ => Err(format!(
+            "unsupported image format '{extension}', use .exr or .png"
+        )),
+    }
+}
@@ -0,0 +1,47 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use insploray::session::RenderProgress;
+
+const BAR_WIDTH: usize = 40;
+
+/// Single line progress bar redrawn in place on stderr
+pub struct ProgressBar {
+    start: Instant,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    pub fn update(&self, progress: &RenderProgress) {
+        let fraction = progress.passes as f32 / progress.target_passes.max(1) as f32;
+        let filled = ((fraction * BAR_WIDTH as f32) as usize).min(BAR_WIDTH);
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {}/{} spp  {:.1} Mrays/s  {} elapsed  ETA {}",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            progress.passes,
+            progress.target_passes,
+            progress.rays_per_second / 1e6,
+            format_duration(self.start.elapsed()),
+            progress.eta.map_or("--:--:--".into(), format_duration),
+        );
+        let _ = stderr.flush();
+    }
+
+    pub fn finish(&self) {
+        eprintln!();
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
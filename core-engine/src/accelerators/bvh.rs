@@ -0,0 +1,340 @@
+use glam::Vec3;
+
+use crate::Ray;
+use crate::scene::{PrimitiveId, Scene};
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self {
+            min: Vec3::splat(f32::MAX),
+            max: Vec3::splat(f32::MIN),
+        }
+    }
+}
+
+impl Aabb {
+    #[inline]
+    pub fn union(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    #[inline]
+    pub fn grow(self, point: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    #[inline]
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let extent = (self.max - self.min).max(Vec3::ZERO);
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    /// Slab test, returns entry distance if the box is hit before t_max
+    #[inline]
+    fn hit(&self, origin: Vec3, inv_direction: Vec3, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - origin) * inv_direction;
+        let t1 = (self.max - origin) * inv_direction;
+
+        let t_near = t0.min(t1).max_element().max(0.0);
+        let t_far = t0.max(t1).min_element();
+
+        (t_near <= t_far && t_near < t_max).then_some(t_near)
+    }
+}
+
+/// Flattened node, first child directly follows its parent in the node array
+#[derive(Debug, Clone, Copy, Default)]
+struct BvhNode {
+    bounds: Aabb,
+    offset: u32, // leaf: first primitive, interior: second child
+    count: u32,  // 0 for interior nodes
+    axis: u8,
+}
+
+struct BuildPrimitive {
+    id: PrimitiveId,
+    bounds: Aabb,
+    centroid: Vec3,
+}
+
+/// Bounding volume hierarchy over every primitive of a scene
+#[derive(Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    primitives: Vec<PrimitiveId>,
+    layout: Vec<usize>, // of the scene it was built for, see `Scene::primitive_layout`
+}
+
+impl Bvh {
+    pub fn build(scene: &Scene) -> Self {
+        let mut build_primitives: Vec<BuildPrimitive> = scene
+            .primitive_ids()
+            .map(|id| {
+                let bounds = primitive_bounds(scene, id);
+                BuildPrimitive {
+                    id,
+                    bounds,
+                    centroid: bounds.centroid(),
+                }
+            })
+            .collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * build_primitives.len()),
+            primitives: Vec::with_capacity(build_primitives.len()),
+            layout: scene.primitive_layout().collect(),
+        };
+
+        if !build_primitives.is_empty() {
+            bvh.build_recursive(&mut build_primitives);
+        }
+
+        bvh
+    }
+
+    /// Sphere count and triangle counts per mesh it was built for,
+    /// the ids of its primitives are only valid while the scene has the same
+    pub fn layout(&self) -> &[usize] {
+        &self.layout
+    }
+
+    /// Recomputes node bounds for moved primitives while keeping the topology
+    pub fn refit(&mut self, scene: &Scene) {
+        // children are always stored after their parent
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            let bounds = if node.count > 0 {
+                let first = node.offset as usize;
+                self.primitives[first..first + node.count as usize]
+                    .iter()
+                    .fold(Aabb::default(), |acc, &id| {
+                        acc.union(primitive_bounds(scene, id))
+                    })
+            } else {
+                self.nodes[i + 1]
+                    .bounds
+                    .union(self.nodes[node.offset as usize].bounds)
+            };
+            self.nodes[i].bounds = bounds;
+        }
+    }
+
+    /// Finds the closest primitive accepted by `intersect_primitive`, which returns
+    /// hit distance along with any extra hit data
+    pub fn intersect<T>(
+        &self,
+        ray: &Ray,
+        t_max: f32,
+        mut intersect_primitive: impl FnMut(PrimitiveId) -> Option<(f32, T)>,
+    ) -> Option<(f32, PrimitiveId, T)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_direction = ray.direction.recip();
+        let direction_negative = [
+            ray.direction.x < 0.0,
+            ray.direction.y < 0.0,
+            ray.direction.z < 0.0,
+        ];
+
+        let mut closest: Option<(f32, PrimitiveId, T)> = None;
+        let mut closest_t = t_max;
+
+        let mut stack: Vec<u32> = Vec::with_capacity(64);
+        let mut current = 0_usize;
+
+        loop {
+            let node = &self.nodes[current];
+
+            if node
+                .bounds
+                .hit(ray.origin, inv_direction, closest_t)
+                .is_some()
+            {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for &id in &self.primitives[first..first + node.count as usize] {
+                        if let Some((t, data)) = intersect_primitive(id)
+                            && t < closest_t
+                        {
+                            closest_t = t;
+                            closest = Some((t, id, data));
+                        }
+                    }
+                } else {
+                    // visit the child closer along the split axis first
+                    let (near, far) = if direction_negative[node.axis as usize] {
+                        (node.offset, current as u32 + 1)
+                    } else {
+                        (current as u32 + 1, node.offset)
+                    };
+                    stack.push(far);
+                    current = near as usize;
+                    continue;
+                }
+            }
+
+            match stack.pop() {
+                Some(next) => current = next as usize,
+                None => break,
+            }
+        }
+
+        closest
+    }
+
+    fn build_recursive(&mut self, primitives: &mut [BuildPrimitive]) -> usize {
+        let node_index = self.nodes.len();
+        self.nodes.push(BvhNode::default());
+
+        let bounds = primitives
+            .iter()
+            .fold(Aabb::default(), |acc, p| acc.union(p.bounds));
+        self.nodes[node_index].bounds = bounds;
+
+        let split = if primitives.len() > MAX_LEAF_SIZE {
+            find_sah_split(primitives, &bounds)
+        } else {
+            None
+        };
+
+        match split {
+            Some((axis, mid)) => {
+                self.nodes[node_index].axis = axis as u8;
+                let (left, right) = primitives.split_at_mut(mid);
+                self.build_recursive(left);
+                let second = self.build_recursive(right);
+                self.nodes[node_index].offset = second as u32;
+            }
+            None => {
+                self.nodes[node_index].offset = self.primitives.len() as u32;
+                self.nodes[node_index].count = primitives.len() as u32;
+                self.primitives.extend(primitives.iter().map(|p| p.id));
+            }
+        }
+
+        node_index
+    }
+}
+
+/// Binned surface area heuristic, partitions `primitives` and returns split axis
+/// and split position, or None when a leaf is cheaper
+fn find_sah_split(primitives: &mut [BuildPrimitive], bounds: &Aabb) -> Option<(usize, usize)> {
+    let centroid_bounds = primitives
+        .iter()
+        .fold(Aabb::default(), |acc, p| acc.grow(p.centroid));
+
+    let leaf_cost = INTERSECTION_COST * primitives.len() as f32;
+    let mut best: Option<(f32, usize, usize)> = None; // cost, axis, bin
+
+    for axis in 0..3 {
+        let axis_min = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - axis_min;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bin_bounds = [Aabb::default(); BIN_COUNT];
+        let mut bin_counts = [0_u32; BIN_COUNT];
+        for p in primitives.iter() {
+            let bin = bin_index(p.centroid[axis], axis_min, extent);
+            bin_bounds[bin] = bin_bounds[bin].union(p.bounds);
+            bin_counts[bin] += 1;
+        }
+
+        // sweep from the right to get the cost of every right hand side
+        let mut right_area = [0.0; BIN_COUNT];
+        let mut right_count = [0_u32; BIN_COUNT];
+        let mut acc = Aabb::default();
+        let mut count = 0;
+        for i in (1..BIN_COUNT).rev() {
+            acc = acc.union(bin_bounds[i]);
+            count += bin_counts[i];
+            right_area[i] = acc.surface_area();
+            right_count[i] = count;
+        }
+
+        let mut acc = Aabb::default();
+        let mut count = 0;
+        for i in 0..BIN_COUNT - 1 {
+            acc = acc.union(bin_bounds[i]);
+            count += bin_counts[i];
+            if count == 0 || right_count[i + 1] == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (acc.surface_area() * count as f32
+                        + right_area[i + 1] * right_count[i + 1] as f32)
+                    / bounds.surface_area().max(f32::MIN_POSITIVE);
+
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    let (cost, axis, bin) = best?;
+    if cost >= leaf_cost && primitives.len() <= 2 * MAX_LEAF_SIZE {
+        return None;
+    }
+
+    let axis_min = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - axis_min;
+
+    // in place partition
+    let mut mid = 0;
+    for i in 0..primitives.len() {
+        if bin_index(primitives[i].centroid[axis], axis_min, extent) <= bin {
+            primitives.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    (mid > 0 && mid < primitives.len()).then_some((axis, mid))
+}
+
+#[inline]
+fn bin_index(value: f32, min: f32, extent: f32) -> usize {
+    (((value - min) / extent * BIN_COUNT as f32) as usize).min(BIN_COUNT - 1)
+}
+
+pub(crate) fn primitive_bounds(scene: &Scene, id: PrimitiveId) -> Aabb {
+    match id {
+        PrimitiveId::Sphere(i) => {
+            let sphere = &scene.spheres[i];
+            let radius = Vec3::splat(sphere.radius.abs());
+            Aabb {
+                min: sphere.position - radius,
+                max: sphere.position + radius,
+            }
+        }
+        PrimitiveId::Triangle { mesh, triangle } => scene.meshes[mesh]
+            .triangle_vertices(triangle)
+            .iter()
+            .fold(Aabb::default(), |acc, &v| acc.grow(v)),
+    }
+}
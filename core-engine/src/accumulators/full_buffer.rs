@@ -1,25 +1,47 @@
-use glam::{Vec4, usize};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use glam::{Vec3, Vec4, usize};
 
 use super::tile_buffer::TileAccumulator;
-use crate::utils::convert_to_argb;
+use crate::aov::{Aov, AovBuffers, AovSet};
+use crate::denoise::{self, DenoiseInput};
+use crate::file_formats::checkpoint::{BinaryReader, BinaryWriter};
+use crate::tonemap::DisplayTransform;
+use crate::utils::{convert_to_argb, heatmap_color};
+
+/// Source of revisions, unique across accumulators so a replaced one never matches
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug, Clone)]
 pub struct Accumulator {
     width: u32,
     height: u32,
     framebuffer: Vec<Vec4>,
-    sample_counts: Vec<u32>,
+    weight_sums: Vec<f32>, // filter weights of the samples splatted onto each pixel
+
+    // the odd passes only, their distance to the full average estimates the noise
+    half_framebuffer: Vec<Vec4>,
+    half_weight_sums: Vec<f32>,
+    sample_counts: Vec<u32>, // samples taken for each pixel, splats of neighbours don't count
+    aovs: AovBuffers,
+    revision: u64, // changes with every merge, tells cached results derived from it apart
 }
 
 impl Accumulator {
-    pub fn new(width: u32, height: u32) -> Self {
+    pub fn new(width: u32, height: u32, aovs: AovSet) -> Self {
         let size = (width * height) as usize;
 
         Self {
             width,
             height,
             framebuffer: vec![Vec4::ZERO; size],
+            weight_sums: vec![0.0; size],
+            half_framebuffer: vec![Vec4::ZERO; size],
+            half_weight_sums: vec![0.0; size],
             sample_counts: vec![0; size],
+            aovs: AovBuffers::new(size, aovs),
+            revision: NEXT_REVISION.fetch_add(1, Ordering::Relaxed),
         }
     }
 
@@ -27,52 +49,52 @@ impl Accumulator {
         [self.width, self.height]
     }
 
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
     pub fn _accumulate(&mut self, x: u32, y: u32, color: Vec4) {
         debug_assert!(x < self.width && y < self.height, "Pixel out of bounds");
 
         let index = (y * self.width + x) as usize;
 
         self.framebuffer[index] += color;
+        self.weight_sums[index] += 1.0;
         self.sample_counts[index] += 1;
+        self.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
     }
 
     pub fn _get_pixel_radiaence(&self, x: u32, y: u32) -> Vec4 {
         debug_assert!(x < self.width && y < self.height, "Pixel out of bounds");
 
-        let index = (y * self.width + x) as usize;
-        let color = self.framebuffer[index];
-        let samples = self.sample_counts[index].max(1);
-
-        color / samples as f32
+        self.get_radiance_pixel((y * self.width + x) as usize)
     }
 
-    pub fn get_argb_pixel(&self, index: usize) -> u32 {
-        let color = self.framebuffer[index];
-        let samples = self.sample_counts[index].max(1);
-
-        let mut averaged = color / samples as f32;
-
-        // Tone mapping (Reinhard)
-        averaged = averaged / (averaged + Vec4::ONE);
-
-        // Gamma correction
-        averaged = averaged.powf(1.0 / 2.2);
+    /// Filter weighted average of the linear radiance of a pixel
+    #[inline]
+    pub fn get_radiance_pixel(&self, index: usize) -> Vec4 {
+        let weight = self.weight_sums[index];
+        // negative lobes can cancel out all weight of a pixel
+        if weight <= 0.0 {
+            return Vec4::ZERO;
+        }
 
-        // Clamp to [0, 1]
-        averaged = averaged.clamp(Vec4::ZERO, Vec4::ONE);
+        self.framebuffer[index] / weight
+    }
 
-        convert_to_argb(&averaged)
+    pub fn get_argb_pixel(&self, index: usize, transform: &DisplayTransform) -> u32 {
+        transform.encode_argb(self.get_radiance_pixel(index))
     }
 
     #[inline]
-    pub fn _get_color_argb(&self, x: u32, y: u32) -> u32 {
+    pub fn _get_color_argb(&self, x: u32, y: u32, transform: &DisplayTransform) -> u32 {
         debug_assert!(x < self.width && y < self.height, "Pixel out of bounds");
 
-        self.get_argb_pixel((y * self.width + x) as usize)
+        self.get_argb_pixel((y * self.width + x) as usize, transform)
     }
 
-    /// Merges two accumulators by summing corresponding pixels and sample counts.
-    pub fn _merge(&mut self, b: Self) {
+    /// Merges two accumulators by summing corresponding pixels and filter weights.
+    pub fn merge(&mut self, b: Self) {
         assert_eq!(
             self.width, b.width,
             "Widths do not match: \nself.width = {:?}\nb.width = {:?}",
@@ -88,38 +110,196 @@ impl Accumulator {
             *c1 += c2;
         }
 
-        for (s1, s2) in self.sample_counts.iter_mut().zip(b.sample_counts) {
-            *s1 += s2;
+        for (w1, w2) in self.weight_sums.iter_mut().zip(b.weight_sums) {
+            *w1 += w2;
+        }
+
+        for (c1, c2) in self.half_framebuffer.iter_mut().zip(b.half_framebuffer) {
+            *c1 += c2;
+        }
+
+        for (w1, w2) in self.half_weight_sums.iter_mut().zip(b.half_weight_sums) {
+            *w1 += w2;
+        }
+
+        for (n1, n2) in self.sample_counts.iter_mut().zip(b.sample_counts) {
+            *n1 += n2;
+        }
+
+        self.aovs.merge(b.aovs);
+        self.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Mean noise estimate of the pixels in a rectangle, infinite until both halves have samples.
+    /// Per pixel it is the distance between the full and the odd pass average relative to the
+    /// square root of the brightness, the same metric Cycles uses for adaptive sampling
+    pub fn region_error(&self, x: u32, y: u32, width: u32, height: u32) -> f32 {
+        let (x_end, y_end) = ((x + width).min(self.width), (y + height).min(self.height));
+        let mut error_sum = 0.0;
+        for py in y..y_end {
+            for px in x..x_end {
+                let index = (py * self.width + px) as usize;
+                let half_weight = self.half_weight_sums[index];
+                if half_weight <= 0.0 || half_weight >= self.weight_sums[index] {
+                    return f32::INFINITY;
+                }
+
+                let full = self.get_radiance_pixel(index).truncate();
+                let half = self.half_framebuffer[index].truncate() / half_weight;
+                error_sum += (full - half).abs().element_sum() / (1e-4 + full.element_sum().sqrt());
+            }
+        }
+
+        let pixel_count = (x_end.saturating_sub(x) * y_end.saturating_sub(y)).max(1);
+        error_sum / pixel_count as f32
+    }
+
+    /// Average samples taken per pixel, below the pass count once adaptive sampling stops tiles
+    pub fn mean_sample_count(&self) -> f32 {
+        let total: u64 = self.sample_counts.iter().map(|&count| count as u64).sum();
+        total as f32 / self.sample_counts.len().max(1) as f32
+    }
+
+    /// Samples taken per pixel as colors, relative to the most sampled pixel
+    pub fn write_to_heatmap_buffer(&self, buffer: &mut Vec<u32>) {
+        let max_count = self.sample_counts.iter().copied().max().unwrap_or(0).max(1);
+        buffer.clear();
+        buffer.extend(
+            self.sample_counts
+                .iter()
+                .map(|&count| convert_to_argb(&heatmap_color(count as f32 / max_count as f32))),
+        );
+    }
+
+    pub fn get_aov_selection(&self) -> AovSet {
+        self.aovs.selected()
+    }
+
+    /// Averaged values of a selected AOV, `aov.channels()` per pixel, bottom row first
+    pub fn get_aov(&self, aov: Aov) -> Option<Vec<f32>> {
+        self.aovs.resolve(aov)
+    }
+
+    /// Sums and counts as checkpoints store them, resolution and AOV selection go in front
+    pub(crate) fn write_state<W: Write>(&self, w: &mut BinaryWriter<W>) -> io::Result<()> {
+        for pixel in self.framebuffer.iter().chain(&self.half_framebuffer) {
+            w.f32s(&pixel.to_array())?;
+        }
+        w.f32s(&self.weight_sums)?;
+        w.f32s(&self.half_weight_sums)?;
+        w.u32s(&self.sample_counts)?;
+        self.aovs.write_state(w)
+    }
+
+    /// Reads what `write_state` wrote into an accumulator of the same resolution and AOVs
+    pub(crate) fn read_state<R: Read>(&mut self, r: &mut BinaryReader<R>) -> io::Result<()> {
+        for pixel in self
+            .framebuffer
+            .iter_mut()
+            .chain(&mut self.half_framebuffer)
+        {
+            let mut values = [0.0; 4];
+            r.f32s(&mut values)?;
+            *pixel = Vec4::from_array(values);
         }
+        r.f32s(&mut self.weight_sums)?;
+        r.f32s(&mut self.half_weight_sums)?;
+        r.u32s(&mut self.sample_counts)?;
+        self.aovs.read_state(r)?;
+        self.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
+        Ok(())
     }
 
-    pub fn write_to_image_buffer(&self, buffer: &mut Vec<u32>) {
+    /// Copy of what the denoiser needs, `None` unless its guide AOVs were recorded.
+    /// The noise estimate is the distance to the odd pass average like `region_error`,
+    /// before there are two halves the whole radiance counts as noise
+    pub(crate) fn denoise_input(&self) -> Option<DenoiseInput> {
+        if !denoise::has_guides(self.aovs.selected()) {
+            return None;
+        }
+        let vectors = |aov| {
+            let values = self.aovs.resolve(aov)?;
+            Some(values.chunks_exact(3).map(Vec3::from_slice).collect())
+        };
+
+        let pixel_count = self.framebuffer.len();
+        let radiance: Vec<_> = (0..pixel_count)
+            .map(|i| self.get_radiance_pixel(i))
+            .collect();
+        let noise = (0..pixel_count)
+            .map(|i| {
+                let full = radiance[i].truncate();
+                let half_weight = self.half_weight_sums[i];
+                match half_weight > 0.0 && half_weight < self.weight_sums[i] {
+                    true => (full - self.half_framebuffer[i].truncate() / half_weight).abs(),
+                    false => full.abs(),
+                }
+            })
+            .collect();
+
+        Some(DenoiseInput {
+            width: self.width as usize,
+            height: self.height as usize,
+            radiance,
+            noise,
+            albedo: vectors(Aov::Albedo)?,
+            normal: vectors(Aov::Normal)?,
+            depth: self.aovs.resolve(Aov::Depth)?,
+        })
+    }
+
+    pub fn write_to_image_buffer(&self, buffer: &mut Vec<u32>, transform: &DisplayTransform) {
         if buffer.len() != self.framebuffer.len() {
             *buffer = vec![0xFF000000_u32; self.framebuffer.len()]
         };
 
         buffer.iter_mut().enumerate().for_each(|(i, pixel)| {
-            *pixel = self.get_argb_pixel(i);
+            *pixel = self.get_argb_pixel(i, transform);
         });
     }
 
-    // TODO: Clean the typecasting in the function
-    pub fn merge_tile(&mut self, tile: TileAccumulator) {
-        for ty in 0..tile.height {
-            for tx in 0..tile.width {
-                let tile_index = (ty * tile.width + tx) as usize;
+    pub fn write_to_radiance_buffer(&self, buffer: &mut Vec<Vec3>) {
+        buffer.clear();
+        buffer.extend((0..self.framebuffer.len()).map(|i| self.get_radiance_pixel(i).truncate()));
+    }
 
-                let global_x = tile.offset_x + tx;
-                let global_y = tile.offset_y + ty;
+    /// Adds the splats of a tile, parts of its margin outside the image are dropped.
+    /// Odd passes also go to the half buffer used for the noise estimate
+    pub fn merge_tile(&mut self, tile: TileAccumulator, sample_index: u32) {
+        let odd_pass = sample_index % 2 == 1;
+        self.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
 
-                // Safety check (optional)
-                debug_assert!(global_x < self.width);
-                debug_assert!(global_y < self.height);
+        for ty in 0..tile.height {
+            let global_y = tile.offset_y + ty as i32;
+            if global_y < 0 || global_y >= self.height as i32 {
+                continue;
+            }
 
-                let global_index = (global_y * self.width + global_x) as usize;
+            for tx in 0..tile.width {
+                let global_x = tile.offset_x + tx as i32;
+                if global_x < 0 || global_x >= self.width as i32 {
+                    continue;
+                }
+
+                let tile_index = (ty * tile.width + tx) as usize;
+                let global_index = (global_y as u32 * self.width + global_x as u32) as usize;
 
                 self.framebuffer[global_index] += tile.framebuffer[tile_index];
-                self.sample_counts[global_index] += tile.sample_counts[tile_index];
+                self.weight_sums[global_index] += tile.weight_sums[tile_index];
+                if odd_pass {
+                    self.half_framebuffer[global_index] += tile.framebuffer[tile_index];
+                    self.half_weight_sums[global_index] += tile.weight_sums[tile_index];
+                }
+
+                let margin = tile.margin;
+                if (margin..tile.width - margin).contains(&tx)
+                    && (margin..tile.height - margin).contains(&ty)
+                {
+                    self.sample_counts[global_index] += 1;
+                }
+                if let Some(Some(sample)) = tile.aov_samples.get(tile_index) {
+                    self.aovs.add_sample(global_index, sample);
+                }
             }
         }
     }
@@ -1,29 +1,126 @@
-use glam::Vec4;
+use std::io::{self, Read, Write};
 
+use glam::{Vec2, Vec4};
+
+use crate::aov::AovSample;
+use crate::file_formats::checkpoint::{BinaryReader, BinaryWriter};
+use crate::filters::Filter;
+
+/// Filtered samples of one render tile. The buffer is larger than the tile by
+/// the filter margin, splats that leave the tile are merged into the neighbours
 pub struct TileAccumulator {
-    pub offset_x: u32,
-    pub offset_y: u32,
+    pub offset_x: i32, // may be negative when the margin leaves the image
+    pub offset_y: i32,
     pub width: u32,
     pub height: u32,
     pub framebuffer: Vec<Vec4>,
-    pub sample_counts: Vec<u32>,
+    pub weight_sums: Vec<f32>,
+    pub margin: u32, // around the pixels the tile takes samples for
+    pub rays_traced: u64,
+    pub aov_samples: Vec<Option<AovSample>>, // per pixel like `framebuffer`, empty without AOVs
+    filter: Filter,
 }
 
 impl TileAccumulator {
-    pub fn new(offset_x: u32, offset_y: u32, width: u32, height: u32) -> Self {
+    pub fn new(
+        offset_x: u32,
+        offset_y: u32,
+        width: u32,
+        height: u32,
+        filter: Filter,
+        record_aovs: bool,
+    ) -> Self {
+        let margin = filter.margin();
+        let (width, height) = (width + 2 * margin, height + 2 * margin);
+        let aov_pixels = match record_aovs {
+            true => (width * height) as usize,
+            false => 0,
+        };
         Self {
-            offset_x,
-            offset_y,
+            offset_x: offset_x as i32 - margin as i32,
+            offset_y: offset_y as i32 - margin as i32,
             width,
             height,
             framebuffer: vec![Vec4::ZERO; (width * height) as usize],
-            sample_counts: vec![0; (width * height) as usize],
+            weight_sums: vec![0.0; (width * height) as usize],
+            margin,
+            rays_traced: 0,
+            aov_samples: vec![None; aov_pixels],
+            filter,
         }
     }
 
-    pub fn accumulate(&mut self, local_x: u32, local_y: u32, color: Vec4) {
-        let index = (local_y * self.width + local_x) as usize;
-        self.framebuffer[index] += color;
-        self.sample_counts[index] += 1;
+    /// Splats a sample at `position` in image space, pixel (x, y) spans [x, x + 1) x [y, y + 1)
+    pub fn add_sample(&mut self, position: Vec2, color: Vec4) {
+        let radius = self.filter.radius;
+        let origin = Vec2::new(self.offset_x as f32, self.offset_y as f32);
+        // pixel centers within the radius, relative to the buffer
+        let first = (position - radius - 0.5 - origin).ceil().max(Vec2::ZERO);
+        let last = (position + radius - 0.5 - origin)
+            .floor()
+            .min(Vec2::new(self.width as f32 - 1.0, self.height as f32 - 1.0));
+        if last.x < first.x || last.y < first.y {
+            return;
+        }
+
+        for y in first.y as u32..=last.y as u32 {
+            for x in first.x as u32..=last.x as u32 {
+                let center = origin + Vec2::new(x as f32, y as f32) + 0.5;
+                let weight = self.filter.evaluate(center - position);
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let index = (y * self.width + x) as usize;
+                self.framebuffer[index] += color * weight;
+                self.weight_sums[index] += weight;
+            }
+        }
+    }
+
+    /// Keeps the AOVs of the sample taken for pixel (x, y) in image space
+    pub fn add_aov_sample(&mut self, x: u32, y: u32, sample: AovSample) {
+        let (tx, ty) = (x as i32 - self.offset_x, y as i32 - self.offset_y);
+        let index = (ty as u32 * self.width + tx as u32) as usize;
+        if let Some(slot) = self.aov_samples.get_mut(index) {
+            *slot = Some(sample);
+        }
+    }
+
+    /// Sums and samples as remote workers send them back, the coordinator knows the
+    /// tile and reads them into an accumulator made for it
+    pub(crate) fn write_state<W: Write>(&self, w: &mut BinaryWriter<W>) -> io::Result<()> {
+        w.u64(self.rays_traced)?;
+        for pixel in &self.framebuffer {
+            w.f32s(&pixel.to_array())?;
+        }
+        w.f32s(&self.weight_sums)?;
+        for sample in &self.aov_samples {
+            match sample {
+                Some(sample) => {
+                    w.u32(1)?;
+                    sample.write(w)?;
+                }
+                None => w.u32(0)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_state<R: Read>(&mut self, r: &mut BinaryReader<R>) -> io::Result<()> {
+        self.rays_traced = r.u64()?;
+        for pixel in &mut self.framebuffer {
+            let mut values = [0.0; 4];
+            r.f32s(&mut values)?;
+            *pixel = Vec4::from_array(values);
+        }
+        r.f32s(&mut self.weight_sums)?;
+        for sample in &mut self.aov_samples {
+            *sample = match r.u32()? {
+                0 => None,
+                _ => Some(AovSample::read(r)?),
+            };
+        }
+        Ok(())
     }
 }
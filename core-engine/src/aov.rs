@@ -0,0 +1,467 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use glam::{Vec3, Vec4};
+
+use crate::file_formats::checkpoint::{BinaryReader, BinaryWriter};
+use crate::tonemap::DisplayTransform;
+use crate::utils::{convert_to_argb, heatmap_color};
+
+/// Arbitrary output variable, a render pass recorded next to the beauty image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Distance from the camera to the first hit, infinite where nothing was hit
+    Depth,
+    /// World position of the first hit
+    Position,
+    /// Shading normal of the first hit, facing the camera
+    Normal,
+    /// Material albedo of the first hit
+    Albedo,
+    /// Material id of the first hit, -1 for the default material and the background
+    MaterialId,
+    /// Sphere index, or sphere count plus mesh index, of the first hit, -1 for the background
+    ObjectId,
+    /// Light reflected once before reaching the camera
+    Direct,
+    /// Light reflected more than once
+    Indirect,
+    /// Emitters and the sky seen directly, with `direct` and `indirect` it sums up to the beauty
+    Emission,
+    /// Samples taken per pixel
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 10] = [
+        Aov::Depth,
+        Aov::Position,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Emission,
+        Aov::SampleCount,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Depth => "depth",
+            Self::Position => "position",
+            Self::Normal => "normal",
+            Self::Albedo => "albedo",
+            Self::MaterialId => "material-id",
+            Self::ObjectId => "object-id",
+            Self::Direct => "direct",
+            Self::Indirect => "indirect",
+            Self::Emission => "emission",
+            Self::SampleCount => "sample-count",
+        }
+    }
+
+    /// Channel names in the EXR layer of the pass
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Self::Depth => &["Z"],
+            Self::Position | Self::Normal => &["X", "Y", "Z"],
+            Self::Albedo | Self::Direct | Self::Indirect | Self::Emission => &["R", "G", "B"],
+            Self::MaterialId | Self::ObjectId | Self::SampleCount => &["V"],
+        }
+    }
+
+    /// Hit data is averaged over the samples that hit something, ids are taken from the
+    /// first sample so they stay exact, light is averaged over all samples
+    fn is_surface_data(&self) -> bool {
+        matches!(
+            self,
+            Self::Depth | Self::Position | Self::Normal | Self::Albedo
+        )
+    }
+
+    fn is_id(&self) -> bool {
+        matches!(self, Self::MaterialId | Self::ObjectId)
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|aov| aov.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Aov::as_str).collect();
+                format!("unknown AOV '{s}', expected one of {}", names.join(", "))
+            })
+    }
+}
+
+/// Selection of AOVs, iterates in the order of `Aov::ALL`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AovSet(u16);
+
+impl AovSet {
+    pub fn insert(&mut self, aov: Aov) {
+        self.0 |= 1 << aov as u16;
+    }
+
+    pub fn remove(&mut self, aov: Aov) {
+        self.0 &= !(1 << aov as u16);
+    }
+
+    pub fn contains(&self, aov: Aov) -> bool {
+        self.0 & (1 << aov as u16) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Aov> + '_ {
+        Aov::ALL.into_iter().filter(|&aov| self.contains(aov))
+    }
+
+    /// One bit per AOV in the order of `Aov::ALL`, how checkpoints store the selection
+    pub(crate) fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub(crate) fn from_bits(bits: u16) -> Self {
+        Self(bits & ((1 << Aov::ALL.len()) - 1))
+    }
+}
+
+impl FromIterator<Aov> for AovSet {
+    fn from_iter<I: IntoIterator<Item = Aov>>(iter: I) -> Self {
+        let mut set = Self::default();
+        iter.into_iter().for_each(|aov| set.insert(aov));
+        set
+    }
+}
+
+/// What a camera sample found, recorded for every sample while AOVs are selected
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AovSample {
+    pub hit: bool,
+    pub depth: f32,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+    pub material_id: i32,
+    pub object_id: i32,
+    pub direct: Vec3,
+    pub indirect: Vec3,
+    pub emission: Vec3,
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        Self {
+            hit: false,
+            depth: f32::INFINITY,
+            position: Vec3::ZERO,
+            normal: Vec3::ZERO,
+            albedo: Vec3::ZERO,
+            material_id: -1,
+            object_id: -1,
+            direct: Vec3::ZERO,
+            indirect: Vec3::ZERO,
+            emission: Vec3::ZERO,
+        }
+    }
+}
+
+impl AovSample {
+    /// Light gathered after `reflections` surface interactions
+    pub fn add_light(&mut self, reflections: usize, light: Vec3) {
+        match reflections {
+            0 => self.emission += light,
+            1 => self.direct += light,
+            _ => self.indirect += light,
+        }
+    }
+
+    /// Field by field, for the tiles remote workers send back
+    pub fn write<W: Write>(&self, w: &mut BinaryWriter<W>) -> io::Result<()> {
+        w.u32(self.hit as u32)?;
+        w.u32(self.depth.to_bits())?;
+        for v in [self.position, self.normal, self.albedo] {
+            w.f32s(&v.to_array())?;
+        }
+        w.u32(self.material_id as u32)?;
+        w.u32(self.object_id as u32)?;
+        for v in [self.direct, self.indirect, self.emission] {
+            w.f32s(&v.to_array())?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut BinaryReader<R>) -> io::Result<Self> {
+        fn vec3<R: Read>(r: &mut BinaryReader<R>) -> io::Result<Vec3> {
+            let mut values = [0.0; 3];
+            r.f32s(&mut values)?;
+            Ok(Vec3::from_array(values))
+        }
+        let hit = r.u32()? != 0;
+        let depth = f32::from_bits(r.u32()?);
+        let (position, normal, albedo) = (vec3(r)?, vec3(r)?, vec3(r)?);
+        let (material_id, object_id) = (r.u32()? as i32, r.u32()? as i32);
+        let (direct, indirect, emission) = (vec3(r)?, vec3(r)?, vec3(r)?);
+        Ok(Self {
+            hit,
+            depth,
+            position,
+            normal,
+            albedo,
+            material_id,
+            object_id,
+            direct,
+            indirect,
+            emission,
+        })
+    }
+
+    fn values(&self, aov: Aov) -> [f32; 3] {
+        match aov {
+            Aov::Depth => [self.depth, 0.0, 0.0],
+            Aov::Position => self.position.to_array(),
+            Aov::Normal => self.normal.to_array(),
+            Aov::Albedo => self.albedo.to_array(),
+            Aov::MaterialId => [self.material_id as f32, 0.0, 0.0],
+            Aov::ObjectId => [self.object_id as f32, 0.0, 0.0],
+            Aov::Direct => self.direct.to_array(),
+            Aov::Indirect => self.indirect.to_array(),
+            Aov::Emission => self.emission.to_array(),
+            Aov::SampleCount => [0.0; 3],
+        }
+    }
+}
+
+/// Sums of the selected AOVs over the image, one sample per pixel and pass.
+/// Samples land in the pixel they were taken for, the reconstruction filter
+/// would blend ids and depths across edges
+#[derive(Debug, Clone)]
+pub(crate) struct AovBuffers {
+    selected: AovSet,
+    samples: Vec<u32>,
+    hits: Vec<u32>,
+    layers: [Vec<f32>; Aov::ALL.len()], // empty unless selected, channels per pixel
+}
+
+impl AovBuffers {
+    pub fn new(pixel_count: usize, selected: AovSet) -> Self {
+        let layers = Aov::ALL.map(|aov| {
+            if !selected.contains(aov) || aov == Aov::SampleCount {
+                return vec![];
+            }
+            let fill = if aov.is_id() { -1.0 } else { 0.0 };
+            vec![fill; pixel_count * aov.channels().len()]
+        });
+        let counts = match selected.is_empty() {
+            true => 0,
+            false => pixel_count,
+        };
+
+        Self {
+            selected,
+            samples: vec![0; counts],
+            hits: vec![0; counts],
+            layers,
+        }
+    }
+
+    pub fn selected(&self) -> AovSet {
+        self.selected
+    }
+
+    pub fn add_sample(&mut self, index: usize, sample: &AovSample) {
+        if self.selected.is_empty() {
+            return;
+        }
+
+        let first = self.samples[index] == 0;
+        self.samples[index] += 1;
+        self.hits[index] += sample.hit as u32;
+
+        for aov in self.selected.iter() {
+            let values = sample.values(aov);
+            let layer = &mut self.layers[aov as usize];
+            let channels = aov.channels().len();
+            let Some(pixel) = layer.get_mut(index * channels..(index + 1) * channels) else {
+                continue;
+            };
+
+            if aov.is_id() {
+                if first {
+                    pixel[0] = values[0];
+                }
+            } else if !aov.is_surface_data() || sample.hit {
+                pixel.iter_mut().zip(values).for_each(|(sum, v)| *sum += v);
+            }
+        }
+    }
+
+    /// Adds the sums of another image of the same size and selection,
+    /// ids of pixels this one has samples for are kept
+    pub fn merge(&mut self, other: Self) {
+        for aov in self.selected.iter().filter(|aov| aov.is_id()) {
+            let channels = aov.channels().len();
+            let ids = self.layers[aov as usize].chunks_exact_mut(channels);
+            let other_ids = other.layers[aov as usize].chunks_exact(channels);
+            for ((id, other_id), &samples) in ids.zip(other_ids).zip(&self.samples) {
+                if samples == 0 {
+                    id.copy_from_slice(other_id);
+                }
+            }
+        }
+        for aov in self.selected.iter().filter(|aov| !aov.is_id()) {
+            let layer = &mut self.layers[aov as usize];
+            layer
+                .iter_mut()
+                .zip(&other.layers[aov as usize])
+                .for_each(|(sum, v)| *sum += v);
+        }
+        for (n1, n2) in self.samples.iter_mut().zip(other.samples) {
+            *n1 += n2;
+        }
+        for (n1, n2) in self.hits.iter_mut().zip(other.hits) {
+            *n1 += n2;
+        }
+    }
+
+    /// Counts and the sums of the selected layers, for checkpoints
+    pub fn write_state<W: Write>(&self, w: &mut BinaryWriter<W>) -> io::Result<()> {
+        w.u32s(&self.samples)?;
+        w.u32s(&self.hits)?;
+        self.layers.iter().try_for_each(|layer| w.f32s(layer))
+    }
+
+    pub fn read_state<R: Read>(&mut self, r: &mut BinaryReader<R>) -> io::Result<()> {
+        r.u32s(&mut self.samples)?;
+        r.u32s(&mut self.hits)?;
+        self.layers.iter_mut().try_for_each(|layer| r.f32s(layer))
+    }
+
+    /// Averaged values of a selected AOV, `aov.channels()` per pixel, bottom row first
+    pub fn resolve(&self, aov: Aov) -> Option<Vec<f32>> {
+        if !self.selected.contains(aov) {
+            return None;
+        }
+        if aov == Aov::SampleCount {
+            return Some(self.samples.iter().map(|&count| count as f32).collect());
+        }
+
+        let channels = aov.channels().len();
+        let layer = &self.layers[aov as usize];
+        let resolved = layer
+            .chunks_exact(channels)
+            .enumerate()
+            .flat_map(|(i, pixel)| {
+                let count = match aov.is_surface_data() {
+                    true => self.hits[i],
+                    false => self.samples[i],
+                };
+                pixel.iter().map(move |&sum| match (aov, count) {
+                    (Aov::MaterialId | Aov::ObjectId, _) => sum,
+                    (Aov::Depth, 0) => f32::INFINITY,
+                    (_, 0) => 0.0,
+                    _ => sum / count as f32,
+                })
+            })
+            .collect();
+        Some(resolved)
+    }
+}
+
+/// False color image of resolved AOV values for the viewport, depth and sample counts
+/// relative to their maximum, ids as random colors, light through the display transform of the beauty
+pub(crate) fn preview(aov: Aov, values: &[f32], transform: &DisplayTransform) -> Vec<u32> {
+    let channels = aov.channels().len();
+    let max = values
+        .iter()
+        .copied()
+        .filter(|v| v.is_finite())
+        .fold(0.0, f32::max)
+        .max(1e-6);
+
+    values
+        .chunks_exact(channels)
+        .map(|pixel| {
+            let mut v = Vec3::ZERO;
+            pixel
+                .iter()
+                .enumerate()
+                .for_each(|(c, &value)| v[c] = value);
+            let color = match aov {
+                Aov::Depth if !v.x.is_finite() => Vec3::ZERO,
+                Aov::Depth => Vec3::splat(1.0 - v.x / max),
+                Aov::Position => v.fract_gl(),
+                Aov::Normal => v * 0.5 + 0.5,
+                Aov::Albedo => v,
+                Aov::MaterialId | Aov::ObjectId if v.x < 0.0 => Vec3::ZERO,
+                Aov::MaterialId | Aov::ObjectId => id_color(v.x as u32),
+                Aov::Direct | Aov::Indirect | Aov::Emission => transform.apply(v),
+                Aov::SampleCount => heatmap_color(v.x / max).truncate(),
+            };
+            convert_to_argb(&Vec4::from((color.clamp(Vec3::ZERO, Vec3::ONE), 1.0)))
+        })
+        .collect()
+}
+
+/// Well spread hue for consecutive ids
+fn id_color(id: u32) -> Vec3 {
+    let hash = id.wrapping_add(1).wrapping_mul(0x9E37_79B9);
+    Vec3::new(
+        (hash >> 24) as f32 / 255.0,
+        ((hash >> 16) & 0xFF) as f32 / 255.0,
+        ((hash >> 8) & 0xFF) as f32 / 255.0,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn surface_data_averages_hits_and_ids_keep_the_first_sample() {
+        let selected: AovSet = [Aov::Depth, Aov::ObjectId, Aov::Emission]
+            .into_iter()
+            .collect();
+        let mut buffers = AovBuffers::new(2, selected);
+
+        let mut sample = AovSample {
+            hit: true,
+            depth: 2.0,
+            object_id: 3,
+            ..Default::default()
+        };
+        sample.add_light(0, Vec3::ONE);
+        buffers.add_sample(0, &sample);
+        buffers.add_sample(0, &AovSample::default());
+        buffers.add_sample(
+            0,
+            &AovSample {
+                hit: true,
+                depth: 4.0,
+                object_id: 5,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(buffers.resolve(Aov::Depth).unwrap(), [3.0, f32::INFINITY]);
+        assert_eq!(buffers.resolve(Aov::ObjectId).unwrap(), [3.0, -1.0]);
+        assert_eq!(buffers.resolve(Aov::Emission).unwrap()[..3], [1.0 / 3.0; 3]);
+        assert_eq!(buffers.resolve(Aov::Normal), None);
+        assert_eq!("Material-ID".parse(), Ok(Aov::MaterialId));
+    }
+}
@@ -0,0 +1,409 @@
+use std::f32::consts::PI;
+
+use glam::{Vec2, Vec3};
+
+use crate::sampler::sample_hemisphere_cosine_weighted;
+use crate::scene::{MaterialType, Matrial};
+
+/// Reflectance of dielectrics at normal incidence
+const DIELECTRIC_F0: f32 = 0.04;
+/// Keeps perfectly smooth surfaces numerically stable
+const MIN_ALPHA: f32 = 1e-3;
+
+/// Orthonormal basis around the shading normal, local z is the normal
+pub(crate) struct ShadingFrame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl ShadingFrame {
+    pub fn from_normal(normal: Vec3) -> Self {
+        let up = if normal.z.abs() < 0.999 {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let tangent = up.cross(normal).normalize();
+        let bitangent = normal.cross(tangent);
+
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    #[inline]
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            v.dot(self.tangent),
+            v.dot(self.bitangent),
+            v.dot(self.normal),
+        )
+    }
+
+    #[inline]
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x * self.tangent + v.y * self.bitangent + v.z * self.normal
+    }
+}
+
+pub(crate) struct BsdfSample {
+    pub direction: Vec3, // world space
+    pub weight: Vec3,    // bsdf * cos / pdf
+    pub pdf: f32,        // solid angle pdf, meaningless for delta lobes
+    pub is_delta: bool,  // perfectly specular, can't be hit by light sampling
+}
+
+/// Metallic-roughness BRDF: Lambertian diffuse plus GGX specular with
+/// height correlated Smith masking and Schlick Fresnel
+struct MetallicRoughness {
+    albedo: Vec3,
+    metallic: f32,
+    alpha: f32,
+    f0: Vec3,
+}
+
+impl MetallicRoughness {
+    fn new(material: &Matrial) -> Self {
+        let roughness = material.roughness.clamp(0.0, 1.0);
+        let metallic = material.metalic.clamp(0.0, 1.0);
+
+        Self {
+            albedo: material.albedo,
+            metallic,
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+            f0: Vec3::splat(DIELECTRIC_F0).lerp(material.albedo, metallic),
+        }
+    }
+
+    /// Probability of picking the specular lobe for outgoing direction `wo`
+    fn specular_probability(&self, wo: Vec3) -> f32 {
+        let fresnel = fresnel_schlick(self.f0, wo.z);
+        let specular = luminance(fresnel);
+        let diffuse = luminance(self.albedo) * (1.0 - self.metallic) * (1.0 - specular);
+
+        if specular + diffuse <= 0.0 {
+            return 1.0;
+        }
+        (specular / (specular + diffuse)).clamp(0.05, 1.0)
+    }
+
+    /// Returns bsdf value (without cosine) and solid angle pdf, directions in local space
+    fn evaluate(&self, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (Vec3::ZERO, 0.0);
+        }
+
+        let h = (wo + wi).normalize();
+        let d = ggx_d(h.z, self.alpha);
+        let g = smith_g2(wo, wi, self.alpha);
+        let fresnel = fresnel_schlick(self.f0, wo.dot(h));
+
+        let specular = fresnel * d * g / (4.0 * wo.z * wi.z);
+        let diffuse = (Vec3::ONE - fresnel) * (1.0 - self.metallic) * self.albedo / PI;
+
+        let p_specular = self.specular_probability(wo);
+        let specular_pdf = smith_g1(wo, self.alpha) * d / (4.0 * wo.z);
+        let diffuse_pdf = wi.z / PI;
+        let pdf = p_specular * specular_pdf + (1.0 - p_specular) * diffuse_pdf;
+
+        (specular + diffuse, pdf)
+    }
+
+    /// `u_lobe` picks specular or diffuse, `u` samples the direction of that lobe
+    fn sample(&self, wo: Vec3, frame: &ShadingFrame, u_lobe: f32, u: Vec2) -> Option<Vec3> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let wi = if u_lobe < self.specular_probability(wo) {
+            let h = sample_ggx_vndf(wo, self.alpha, u);
+            reflect(wo, h)
+        } else {
+            frame.to_local(sample_hemisphere_cosine_weighted(frame.normal, u))
+        };
+
+        (wi.z > 0.0).then_some(wi)
+    }
+}
+
+/// Smooth dielectric interface with exact Fresnel reflectance
+struct Dielectric {
+    tint: Vec3,
+    eta: f32, // relative index of refraction, incident over transmitted side
+}
+
+impl Dielectric {
+    fn new(material: &Matrial, front_face: bool) -> Self {
+        let ior = material.ior.max(1.0);
+        Self {
+            tint: material.albedo,
+            eta: if front_face { 1.0 / ior } else { ior },
+        }
+    }
+
+    /// Picks reflection or refraction proportional to Fresnel, `wo` in local space.
+    /// Returns local direction and throughput weight
+    fn sample(&self, wo: Vec3, u: f32) -> Option<(Vec3, Vec3)> {
+        let cos_i = wo.z;
+        if cos_i <= 0.0 {
+            return None;
+        }
+
+        let sin2_t = self.eta * self.eta * (1.0 - cos_i * cos_i).max(0.0);
+        // total internal reflection
+        if sin2_t >= 1.0 {
+            return Some((reflect(wo, Vec3::Z), Vec3::ONE));
+        }
+
+        let cos_t = (1.0 - sin2_t).sqrt();
+        if u < fresnel_dielectric(cos_i, cos_t, self.eta) {
+            Some((reflect(wo, Vec3::Z), Vec3::ONE))
+        } else {
+            let wt = Vec3::new(-self.eta * wo.x, -self.eta * wo.y, -cos_t);
+            Some((wt.normalize(), self.tint))
+        }
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface
+fn fresnel_dielectric(cos_i: f32, cos_t: f32, eta: f32) -> f32 {
+    let r_parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let r_perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// `normal` must face `wo`, `front_face` tells if `wo` is on the outside of the surface.
+/// `u_lobe` chooses between lobes (or reflection and refraction), `u` samples the direction
+pub(crate) fn sample_bsdf(
+    material: &Matrial,
+    normal: Vec3,
+    wo: Vec3,
+    front_face: bool,
+    u_lobe: f32,
+    u: Vec2,
+) -> Option<BsdfSample> {
+    let frame = ShadingFrame::from_normal(normal);
+    let wo_local = frame.to_local(wo);
+
+    if material.material_type == MaterialType::Dielectric {
+        let dielectric = Dielectric::new(material, front_face);
+        let (wi_local, weight) = dielectric.sample(wo_local, u_lobe)?;
+        return Some(BsdfSample {
+            direction: frame.to_world(wi_local),
+            weight,
+            pdf: 0.0,
+            is_delta: true,
+        });
+    }
+
+    let brdf = MetallicRoughness::new(material);
+    let wi_local = brdf.sample(wo_local, &frame, u_lobe, u)?;
+
+    let (value, pdf) = brdf.evaluate(wo_local, wi_local);
+    if pdf <= 0.0 {
+        return None;
+    }
+
+    Some(BsdfSample {
+        direction: frame.to_world(wi_local),
+        weight: value * wi_local.z / pdf,
+        pdf,
+        is_delta: false,
+    })
+}
+
+/// Returns bsdf value times cosine and the pdf of sampling `wi`, zero for delta materials
+pub(crate) fn evaluate_bsdf(material: &Matrial, normal: Vec3, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+    if material.material_type == MaterialType::Dielectric {
+        return (Vec3::ZERO, 0.0);
+    }
+
+    let frame = ShadingFrame::from_normal(normal);
+    let wi_local = frame.to_local(wi);
+    let (value, pdf) = MetallicRoughness::new(material).evaluate(frame.to_local(wo), wi_local);
+    (value * wi_local.z.max(0.0), pdf)
+}
+
+#[inline]
+fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+#[inline]
+fn reflect(v: Vec3, normal: Vec3) -> Vec3 {
+    2.0 * v.dot(normal) * normal - v
+}
+
+#[inline]
+fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    let m = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    f0 + (Vec3::ONE - f0) * m
+}
+
+/// Trowbridge-Reitz normal distribution
+#[inline]
+fn ggx_d(cos_theta_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let denom = cos_theta_h * cos_theta_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * denom * denom)
+}
+
+#[inline]
+fn smith_lambda(v: Vec3, alpha: f32) -> f32 {
+    let cos2 = v.z * v.z;
+    let tan2 = (1.0 - cos2).max(0.0) / cos2.max(f32::MIN_POSITIVE);
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+#[inline]
+fn smith_g1(v: Vec3, alpha: f32) -> f32 {
+    1.0 / (1.0 + smith_lambda(v, alpha))
+}
+
+/// Height correlated masking-shadowing
+#[inline]
+fn smith_g2(wo: Vec3, wi: Vec3, alpha: f32) -> f32 {
+    1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha))
+}
+
+/// Samples visible normals (Heitz 2018), `wo` in local space
+fn sample_ggx_vndf(wo: Vec3, alpha: f32, u: Vec2) -> Vec3 {
+    // stretch view to the hemisphere configuration
+    let vh = Vec3::new(alpha * wo.x, alpha * wo.y, wo.z).normalize();
+
+    let len_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len_sq > 0.0 {
+        Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt()
+    } else {
+        Vec3::X
+    };
+    let t2 = vh.cross(t1);
+
+    let r = u.x.sqrt();
+    let phi = 2.0 * PI * u.y;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+    Vec3::new(alpha * nh.x, alpha * nh.y, nh.z.max(0.0)).normalize()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sampler::{IndependentSampler, Sampler};
+
+    #[test]
+    fn importance_sampling_matches_uniform_estimate() {
+        // both estimate the directional albedo, a mismatch means the pdf is off
+        let mut sampler = IndependentSampler::new(0);
+        let normal = Vec3::Z;
+        let frame = ShadingFrame::from_normal(normal);
+        let wo = Vec3::new(0.5, 0.1, 0.6).normalize();
+        let count = 100_000;
+
+        for (roughness, metalic) in [(0.3, 1.0), (0.6, 0.0), (0.8, 0.5)] {
+            let material = Matrial {
+                roughness,
+                metalic,
+                albedo: Vec3::new(0.8, 0.6, 0.4),
+                ..Default::default()
+            };
+            let brdf = MetallicRoughness::new(&material);
+
+            let mut importance = Vec3::ZERO;
+            let mut uniform = Vec3::ZERO;
+            for _ in 0..count {
+                if let Some(sample) = sample_bsdf(
+                    &material,
+                    normal,
+                    wo,
+                    true,
+                    sampler.next_1d(),
+                    sampler.next_2d(),
+                ) {
+                    importance += sample.weight;
+                }
+
+                let u = sampler.next_2d();
+                let phi = 2.0 * PI * u.y;
+                let sin_theta = (1.0 - u.x * u.x).sqrt();
+                let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), u.x);
+                let (value, _) = brdf.evaluate(frame.to_local(wo), wi);
+                uniform += value * wi.z * 2.0 * PI;
+            }
+
+            let importance = importance / count as f32;
+            let uniform = uniform / count as f32;
+            assert!(
+                importance.abs_diff_eq(uniform, 0.03),
+                "importance {importance} uniform {uniform}"
+            );
+        }
+    }
+
+    #[test]
+    fn white_metal_does_not_gain_energy() {
+        let mut sampler = IndependentSampler::new(0);
+        let normal = Vec3::Z;
+        let material = Matrial {
+            roughness: 0.6,
+            metalic: 1.0,
+            albedo: Vec3::ONE,
+            ..Default::default()
+        };
+
+        for wo in [Vec3::Z, Vec3::new(0.7, 0.0, 0.7).normalize()] {
+            let count = 20000;
+            let mut sum = Vec3::ZERO;
+            for _ in 0..count {
+                if let Some(sample) = sample_bsdf(
+                    &material,
+                    normal,
+                    wo,
+                    true,
+                    sampler.next_1d(),
+                    sampler.next_2d(),
+                ) {
+                    sum += sample.weight;
+                }
+            }
+            let albedo = sum / count as f32;
+            assert!(albedo.max_element() <= 1.01, "albedo {albedo}");
+            assert!(albedo.min_element() > 0.7, "albedo {albedo}");
+        }
+    }
+
+    #[test]
+    fn dielectric_fresnel_and_total_internal_reflection() {
+        let glass = Matrial {
+            material_type: MaterialType::Dielectric,
+            ior: 1.5,
+            ..Default::default()
+        };
+
+        // 4% reflectance at normal incidence for ior 1.5
+        let outside = Dielectric::new(&glass, true);
+        assert!((fresnel_dielectric(1.0, 1.0, outside.eta) - 0.04).abs() < 1e-4);
+
+        // refraction bends toward the normal when entering
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let (wi, _) = outside.sample(wo, 1.0).unwrap();
+        assert!(wi.z < 0.0 && wi.x.abs() < wo.x);
+        assert!((wi.x / wo.x + outside.eta).abs() < 1e-5);
+
+        // beyond the critical angle everything reflects when leaving
+        let inside = Dielectric::new(&glass, false);
+        let grazing = Vec3::new(0.9, 0.0, 0.1).normalize();
+        for u in [0.0, 0.5, 0.999] {
+            let (wi, weight) = inside.sample(grazing, u).unwrap();
+            assert!(wi.abs_diff_eq(Vec3::new(-grazing.x, 0.0, grazing.z), 1e-5));
+            assert_eq!(weight, Vec3::ONE);
+        }
+    }
+}
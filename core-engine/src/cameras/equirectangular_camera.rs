@@ -0,0 +1,77 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::{Vec2, Vec3};
+
+pub use super::Camera;
+use super::PinholeCamera;
+use crate::Ray;
+
+/// Full 360° panorama, longitude along x and latitude along y. The center of
+/// the image looks along the view direction
+#[derive(Debug, Default, Clone)]
+pub struct EquirectangularCamera {
+    pub pinhole: PinholeCamera, // placement, its field of view is unused
+    /// Over/under stereo for VR, left eye on top, each eye gets half of the rows
+    pub stereo: bool,
+    pub eye_separation: f32, // interpupillary distance in scene units
+}
+
+impl EquirectangularCamera {
+    pub fn new(pinhole: PinholeCamera, stereo: bool, eye_separation: f32) -> Self {
+        Self {
+            pinhole,
+            stereo,
+            eye_separation,
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, x: u32, y: u32, offset: Vec2, _lens: Vec2) -> Option<Ray> {
+        let film = self.pinhole.film_position(x, y, offset);
+
+        // rows start at the bottom, so the upper half is the left eye
+        let (v, eye) = match self.stereo {
+            false => (film.y, 0.0),
+            true if film.y >= 0.5 => (film.y * 2.0 - 1.0, -1.0),
+            true => (film.y * 2.0, 1.0),
+        };
+
+        let phi = (film.x - 0.5) * TAU; // longitude, positive to the right
+        let theta = (v - 0.5) * PI; // latitude, positive up
+        let local = Vec3::new(
+            phi.sin() * theta.cos(),
+            theta.sin(),
+            -phi.cos() * theta.cos(),
+        );
+
+        // omni-directional stereo, the eyes sit on a circle and look tangent to it
+        let eye_offset = eye * 0.5 * self.eye_separation * Vec3::new(phi.cos(), 0.0, phi.sin());
+
+        let camera_to_world = self.pinhole.get_camera_to_world_matrix();
+        Some(Ray {
+            origin: camera_to_world.transform_point3(eye_offset),
+            direction: camera_to_world.transform_vector3(local).normalize(),
+        })
+    }
+
+    fn set_position(&mut self, position: Vec3) {
+        self.pinhole.set_position(position);
+    }
+
+    fn set_rotation(&mut self, rotation: Vec3) {
+        self.pinhole.set_rotation(rotation);
+    }
+
+    fn set_image_resolutions(&mut self, image_resolution: [u32; 2]) {
+        self.pinhole.set_image_resolutions(image_resolution);
+    }
+
+    fn compute_transformation_matrix(&mut self) {
+        self.pinhole.compute_transformation_matrix();
+    }
+
+    fn on_update(&mut self) {
+        self.pinhole.on_update();
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::str::FromStr;
+
+use glam::{Vec2, Vec3};
+
+pub use super::Camera;
+use super::PinholeCamera;
+use crate::Ray;
+
+/// How the angle from the view direction maps to the distance from the image center
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle
+    #[default]
+    Equidistant,
+    /// Equal solid angles cover equal image areas
+    Equisolid,
+}
+
+impl FisheyeMapping {
+    pub const ALL: [FisheyeMapping; 2] = [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Equidistant => "equidistant",
+            Self::Equisolid => "equisolid",
+        }
+    }
+}
+
+impl fmt::Display for FisheyeMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FisheyeMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mapping| mapping.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                format!("unknown fisheye mapping '{s}', expected equidistant or equisolid")
+            })
+    }
+}
+
+/// Circular fisheye, the image circle fits the shorter image side and pixels
+/// outside of it get no rays
+#[derive(Debug, Default, Clone)]
+pub struct FisheyeCamera {
+    pub pinhole: PinholeCamera, // placement, its field of view is unused
+    pub fov: f32,               // across the image circle in radians, up to a full turn
+    pub mapping: FisheyeMapping,
+}
+
+impl FisheyeCamera {
+    pub fn new(pinhole: PinholeCamera, fov: f32, mapping: FisheyeMapping) -> Self {
+        Self {
+            pinhole,
+            fov,
+            mapping,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, x: u32, y: u32, offset: Vec2, _lens: Vec2) -> Option<Ray> {
+        let size = Vec2::from(self.pinhole.image_size.map(|v| v as f32));
+        let film =
+            (self.pinhole.film_position(x, y, offset) - 0.5) * size / (0.5 * size.min_element());
+
+        let r = film.length();
+        if r > 1.0 {
+            return None;
+        }
+
+        // angle from the view direction
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.fov / 2.0,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.fov / 4.0).sin()).asin(),
+        };
+        let phi = film.y.atan2(film.x);
+        let local = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            -theta.cos(),
+        );
+
+        Some(Ray {
+            origin: self.pinhole.position,
+            direction: self
+                .pinhole
+                .get_camera_to_world_matrix()
+                .transform_vector3(local)
+                .normalize(),
+        })
+    }
+
+    fn set_position(&mut self, position: Vec3) {
+        self.pinhole.set_position(position);
+    }
+
+    fn set_rotation(&mut self, rotation: Vec3) {
+        self.pinhole.set_rotation(rotation);
+    }
+
+    fn set_image_resolutions(&mut self, image_resolution: [u32; 2]) {
+        self.pinhole.set_image_resolutions(image_resolution);
+    }
+
+    fn compute_transformation_matrix(&mut self) {
+        self.pinhole.compute_transformation_matrix();
+    }
+
+    fn on_update(&mut self) {
+        self.pinhole.on_update();
+    }
+}
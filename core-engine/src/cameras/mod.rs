@@ -1,11 +1,16 @@
+use std::fmt;
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 
 use crate::ray::Ray;
 
 pub trait Camera {
-    fn get_ray(&self, x: u32, y: u32) -> Ray;
+    /// `offset` is the position inside the pixel, [0.5, 0.5] is its center.
+    /// `lens` picks the point on the aperture, [0.5, 0.5] is the center of the lens.
+    /// Returns `None` for pixels the projection doesn't cover, they stay black
+    fn get_ray(&self, x: u32, y: u32, offset: Vec2, lens: Vec2) -> Option<Ray>;
     fn set_position(&mut self, position: Vec3);
     fn set_rotation(&mut self, rotation: Vec3);
     fn set_image_resolutions(&mut self, image_resolution: [u32; 2]);
@@ -16,5 +21,270 @@ pub trait Camera {
 
 pub type SharedCamera = Arc<RwLock<dyn Camera + Send + Sync>>;
 
+/// Projection of a `CameraModel`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CameraKind {
+    #[default]
+    Pinhole,
+    ThinLens,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+impl CameraKind {
+    pub const ALL: [CameraKind; 5] = [
+        CameraKind::Pinhole,
+        CameraKind::ThinLens,
+        CameraKind::Orthographic,
+        CameraKind::Fisheye,
+        CameraKind::Equirectangular,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pinhole => "pinhole",
+            Self::ThinLens => "thin-lens",
+            Self::Orthographic => "orthographic",
+            Self::Fisheye => "fisheye",
+            Self::Equirectangular => "equirectangular",
+        }
+    }
+}
+
+impl fmt::Display for CameraKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CameraKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(CameraKind::as_str).collect();
+                format!(
+                    "unknown camera type '{s}', expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Any of the camera types, lets scene files and the frontend switch between them
+#[derive(Debug, Clone)]
+pub enum CameraModel {
+    Pinhole(PinholeCamera),
+    ThinLens(ThinLensCamera),
+    Orthographic(OrthographicCamera),
+    Fisheye(FisheyeCamera),
+    Equirectangular(EquirectangularCamera),
+}
+
+impl Default for CameraModel {
+    fn default() -> Self {
+        Self::Pinhole(PinholeCamera::default())
+    }
+}
+
+impl CameraModel {
+    /// Camera of the given type at the placement of `pinhole`, with default parameters
+    pub fn new(kind: CameraKind, pinhole: PinholeCamera) -> Self {
+        match kind {
+            CameraKind::Pinhole => Self::Pinhole(pinhole),
+            CameraKind::ThinLens => {
+                Self::ThinLens(ThinLensCamera::new(pinhole, Aperture::default()))
+            }
+            CameraKind::Orthographic => Self::Orthographic(OrthographicCamera::new(pinhole, 4.0)),
+            CameraKind::Fisheye => Self::Fisheye(FisheyeCamera::new(
+                pinhole,
+                std::f32::consts::PI,
+                FisheyeMapping::default(),
+            )),
+            CameraKind::Equirectangular => {
+                Self::Equirectangular(EquirectangularCamera::new(pinhole, false, 0.064))
+            }
+        }
+    }
+
+    pub fn kind(&self) -> CameraKind {
+        match self {
+            Self::Pinhole(_) => CameraKind::Pinhole,
+            Self::ThinLens(_) => CameraKind::ThinLens,
+            Self::Orthographic(_) => CameraKind::Orthographic,
+            Self::Fisheye(_) => CameraKind::Fisheye,
+            Self::Equirectangular(_) => CameraKind::Equirectangular,
+        }
+    }
+
+    /// Placement of every camera type, its field of view only applies
+    /// to pinhole and thin lens cameras
+    pub fn pinhole(&self) -> &PinholeCamera {
+        match self {
+            Self::Pinhole(camera) => camera,
+            Self::ThinLens(camera) => &camera.pinhole,
+            Self::Orthographic(camera) => &camera.pinhole,
+            Self::Fisheye(camera) => &camera.pinhole,
+            Self::Equirectangular(camera) => &camera.pinhole,
+        }
+    }
+
+    pub fn pinhole_mut(&mut self) -> &mut PinholeCamera {
+        match self {
+            Self::Pinhole(camera) => camera,
+            Self::ThinLens(camera) => &mut camera.pinhole,
+            Self::Orthographic(camera) => &mut camera.pinhole,
+            Self::Fisheye(camera) => &mut camera.pinhole,
+            Self::Equirectangular(camera) => &mut camera.pinhole,
+        }
+    }
+
+    fn as_camera(&self) -> &dyn Camera {
+        match self {
+            Self::Pinhole(camera) => camera,
+            Self::ThinLens(camera) => camera,
+            Self::Orthographic(camera) => camera,
+            Self::Fisheye(camera) => camera,
+            Self::Equirectangular(camera) => camera,
+        }
+    }
+
+    fn as_camera_mut(&mut self) -> &mut dyn Camera {
+        match self {
+            Self::Pinhole(camera) => camera,
+            Self::ThinLens(camera) => camera,
+            Self::Orthographic(camera) => camera,
+            Self::Fisheye(camera) => camera,
+            Self::Equirectangular(camera) => camera,
+        }
+    }
+}
+
+impl Camera for CameraModel {
+    fn get_ray(&self, x: u32, y: u32, offset: Vec2, lens: Vec2) -> Option<Ray> {
+        self.as_camera().get_ray(x, y, offset, lens)
+    }
+
+    fn set_position(&mut self, position: Vec3) {
+        self.as_camera_mut().set_position(position);
+    }
+
+    fn set_rotation(&mut self, rotation: Vec3) {
+        self.as_camera_mut().set_rotation(rotation);
+    }
+
+    fn set_image_resolutions(&mut self, image_resolution: [u32; 2]) {
+        self.as_camera_mut().set_image_resolutions(image_resolution);
+    }
+
+    fn compute_transformation_matrix(&mut self) {
+        self.as_camera_mut().compute_transformation_matrix();
+    }
+
+    fn on_update(&mut self) {
+        self.as_camera_mut().on_update();
+    }
+}
+
+pub mod equirectangular_camera;
+pub mod fisheye_camera;
+pub mod orthographic_camera;
 pub mod pinhole_camera;
+pub mod thin_lens_camera;
+
+pub use equirectangular_camera::EquirectangularCamera;
+pub use fisheye_camera::{FisheyeCamera, FisheyeMapping};
+pub use orthographic_camera::OrthographicCamera;
 pub use pinhole_camera::PinholeCamera;
+pub use thin_lens_camera::{Aperture, ThinLensCamera};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn camera(kind: CameraKind, image_size: [u32; 2]) -> CameraModel {
+        let placement = PinholeCamera::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.0, 0.5, 0.0),
+            35.0,
+            55.0,
+            image_size,
+        );
+        CameraModel::new(kind, placement)
+    }
+
+    /// Direction through the center of the image at film position (u, v)
+    fn direction(camera: &CameraModel, u: f32, v: f32) -> Option<Vec3> {
+        let [width, height] = camera.pinhole().image_size.map(|v| v as f32);
+        let offset = Vec2::new(u * width, v * height);
+        camera
+            .get_ray(0, 0, offset, Vec2::splat(0.5))
+            .map(|ray| ray.direction)
+    }
+
+    #[test]
+    fn projections_map_film_to_expected_directions() {
+        let ortho = camera(CameraKind::Orthographic, [200, 100]);
+        let (forward, right, up) = {
+            let p = ortho.pinhole();
+            (p.forward, p.right, p.up)
+        };
+        let left_ray = ortho
+            .get_ray(0, 50, Vec2::new(0.0, 0.0), Vec2::ZERO)
+            .unwrap();
+        let right_ray = ortho
+            .get_ray(200, 50, Vec2::new(0.0, 0.0), Vec2::ZERO)
+            .unwrap();
+        assert!(left_ray.direction.abs_diff_eq(forward, 1e-6));
+        assert!(((right_ray.origin - left_ray.origin).dot(right) - 4.0).abs() < 1e-5);
+
+        let fisheye = camera(CameraKind::Fisheye, [100, 100]);
+        assert!(
+            direction(&fisheye, 0.5, 0.5)
+                .unwrap()
+                .abs_diff_eq(forward, 1e-5)
+        );
+        // 180° across the circle, its rim looks sideways
+        assert!(
+            direction(&fisheye, 1.0, 0.5)
+                .unwrap()
+                .abs_diff_eq(right, 1e-5)
+        );
+        assert!(direction(&fisheye, 0.5, 1.0).unwrap().abs_diff_eq(up, 1e-5));
+        assert!(direction(&fisheye, 0.98, 0.98).is_none());
+
+        let mut panorama = camera(CameraKind::Equirectangular, [200, 100]);
+        assert!(
+            direction(&panorama, 0.5, 0.5)
+                .unwrap()
+                .abs_diff_eq(forward, 1e-5)
+        );
+        assert!(
+            direction(&panorama, 0.75, 0.5)
+                .unwrap()
+                .abs_diff_eq(right, 1e-5)
+        );
+        assert!(
+            direction(&panorama, 0.0, 0.5)
+                .unwrap()
+                .abs_diff_eq(-forward, 1e-5)
+        );
+        assert!(
+            direction(&panorama, 0.3, 1.0)
+                .unwrap()
+                .abs_diff_eq(up, 1e-5)
+        );
+
+        if let CameraModel::Equirectangular(camera) = &mut panorama {
+            camera.stereo = true;
+        }
+        let left_eye = panorama.get_ray(100, 75, Vec2::ZERO, Vec2::ZERO).unwrap();
+        let right_eye = panorama.get_ray(100, 25, Vec2::ZERO, Vec2::ZERO).unwrap();
+        assert!(left_eye.direction.abs_diff_eq(right_eye.direction, 1e-5));
+        assert!((right_eye.origin - left_eye.origin).abs_diff_eq(right * 0.064, 1e-5));
+    }
+}
@@ -0,0 +1,59 @@
+use glam::{Vec2, Vec3};
+
+pub use super::Camera;
+use super::PinholeCamera;
+use crate::Ray;
+
+/// Parallel projection, all rays share the view direction
+#[derive(Debug, Default, Clone)]
+pub struct OrthographicCamera {
+    pub pinhole: PinholeCamera, // placement, its field of view is unused
+    pub ortho_scale: f32,       // extent of the longer image side in scene units
+}
+
+impl OrthographicCamera {
+    pub fn new(pinhole: PinholeCamera, ortho_scale: f32) -> Self {
+        Self {
+            pinhole,
+            ortho_scale,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, x: u32, y: u32, offset: Vec2, _lens: Vec2) -> Option<Ray> {
+        let [width, height] = self.pinhole.image_size.map(|v| v as f32);
+        let half_extent = 0.5 * self.ortho_scale * Vec2::new(width, height) / width.max(height);
+        let film = (self.pinhole.film_position(x, y, offset) * 2.0 - 1.0) * half_extent;
+
+        let origin = self
+            .pinhole
+            .get_camera_to_world_matrix()
+            .transform_point3(Vec3::new(film.x, film.y, 0.0));
+
+        Some(Ray {
+            origin,
+            direction: self.pinhole.forward,
+        })
+    }
+
+    fn set_position(&mut self, position: Vec3) {
+        self.pinhole.set_position(position);
+    }
+
+    fn set_rotation(&mut self, rotation: Vec3) {
+        self.pinhole.set_rotation(rotation);
+    }
+
+    fn set_image_resolutions(&mut self, image_resolution: [u32; 2]) {
+        self.pinhole.set_image_resolutions(image_resolution);
+    }
+
+    fn compute_transformation_matrix(&mut self) {
+        self.pinhole.compute_transformation_matrix();
+    }
+
+    fn on_update(&mut self) {
+        self.pinhole.on_update();
+    }
+}
@@ -3,7 +3,7 @@ use glam::{Mat4, Vec2, Vec3, Vec4Swizzles};
 pub use super::Camera;
 use crate::Ray;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct PinholeCamera {
     pub position: Vec3,
     pub rotation: Vec3, // [x, y, z] Eular rotation in radians
@@ -71,30 +71,35 @@ impl PinholeCamera {
     pub fn get_camera_to_world_matrix(&self) -> Mat4 {
         self.local_to_world
     }
+
+    /// Position on the image in [0, 1]^2, (0, 0) is the bottom left corner
+    #[inline]
+    pub(crate) fn film_position(&self, x: u32, y: u32, offset: Vec2) -> Vec2 {
+        let &[width, height] = &self.image_size;
+        Vec2::new(
+            (x as f32 + offset.x) / width as f32,
+            (y as f32 + offset.y) / height as f32,
+        )
+    }
 }
 
 impl Camera for PinholeCamera {
     /// this function generated ray directly from world space of camera for performance reason
-    fn get_ray(&self, x: u32, y: u32) -> Ray {
-        let &[width, height] = &self.image_size;
-
-        let mut vec = Vec2::new(
-            (x as f32 + 0.5) / width as f32,
-            (y as f32 + 0.5) / height as f32,
-        );
+    fn get_ray(&self, x: u32, y: u32, offset: Vec2, _lens: Vec2) -> Option<Ray> {
+        let mut vec = self.film_position(x, y, offset);
 
         vec = (vec * 2.0 - 1.0) * (self.fov / 2.0).tan();
         vec.x *= self.aspect_ratio;
 
         let ray_direction = Vec3::new(vec.x, vec.y, -1.0);
 
-        Ray {
+        Some(Ray {
             origin: self.position,
             direction: self
                 .local_to_world
                 .transform_vector3(ray_direction)
                 .normalize(),
-        }
+        })
     }
 
     fn set_position(&mut self, position: Vec3) {
@@ -131,8 +136,6 @@ impl Camera for PinholeCamera {
 
 #[cfg(test)]
 mod test {
-    use std::f32::EPSILON;
-
     use super::*;
 
     #[test]
@@ -152,7 +155,7 @@ mod test {
 
         // Allow a small floating-point error margin
         assert!(
-            (camera.fov - expected_fov).abs() < EPSILON,
+            (camera.fov - expected_fov).abs() < f32::EPSILON,
             "Expected FOV: {}, got: {}",
             expected_fov,
             camera.fov
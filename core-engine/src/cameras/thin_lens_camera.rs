@@ -0,0 +1,165 @@
+use std::f32::consts::TAU;
+
+use glam::{Vec2, Vec3};
+
+pub use super::Camera;
+use super::PinholeCamera;
+use crate::Ray;
+use crate::sampler::sample_disk_concentric;
+
+/// Opening of a thin lens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aperture {
+    pub f_stop: f32,
+    pub focus_distance: f32, // distance of the plane in focus, along the view direction
+    pub blades: u32,         // polygonal opening for shaped bokeh, 0 for a round one
+    pub rotation: f32,       // of the blades, radians
+}
+
+impl Default for Aperture {
+    fn default() -> Self {
+        Self {
+            f_stop: 2.8,
+            focus_distance: 2.0,
+            blades: 0,
+            rotation: 0.0,
+        }
+    }
+}
+
+impl Aperture {
+    /// Radius in scene units (metres) of the opening of a lens with the given focal length in millimetres
+    #[inline]
+    pub fn radius(&self, focal_length: f32) -> f32 {
+        0.5 * focal_length / self.f_stop * 1e-3
+    }
+
+    /// Uniform point on the opening scaled to a unit circumradius, [0.5, 0.5] is the center
+    fn sample(&self, u: Vec2) -> Vec2 {
+        let disk = sample_disk_concentric(u);
+        if self.blades < 3 {
+            return disk;
+        }
+
+        // the squared radius and the angle of the disk sample are uniform and independent,
+        // reused as position along the triangle fan of the polygon they stay uniform
+        let sector = TAU / self.blades as f32;
+        let angle = (disk.y.atan2(disk.x) - self.rotation).rem_euclid(TAU) / sector;
+        let corner = |i: f32| Vec2::from_angle(self.rotation + i * sector);
+        let edge = corner(angle.floor()).lerp(corner(angle.floor() + 1.0), angle.fract());
+
+        disk.length() * edge
+    }
+}
+
+/// Pinhole camera with a finite aperture, points off the plane of focus are blurred
+#[derive(Debug, Default, Clone)]
+pub struct ThinLensCamera {
+    pub pinhole: PinholeCamera, // placement and field of view
+    pub aperture: Aperture,
+}
+
+impl ThinLensCamera {
+    pub fn new(pinhole: PinholeCamera, aperture: Aperture) -> Self {
+        Self { pinhole, aperture }
+    }
+
+    /// Moves the plane of focus through `point`
+    pub fn focus_on(&mut self, point: Vec3) {
+        let distance = (point - self.pinhole.position).dot(self.pinhole.forward);
+        if distance > 0.0 {
+            self.aperture.focus_distance = distance;
+        }
+    }
+}
+
+impl Camera for ThinLensCamera {
+    fn get_ray(&self, x: u32, y: u32, offset: Vec2, lens: Vec2) -> Option<Ray> {
+        let ray = self.pinhole.get_ray(x, y, offset, lens)?;
+        let radius = self.aperture.radius(self.pinhole.focal_length);
+        if radius <= 0.0 {
+            return Some(ray);
+        }
+
+        // rays from anywhere on the lens meet the pinhole ray on the plane of focus
+        let focus_point = ray.origin
+            + ray.direction
+                * (self.aperture.focus_distance / ray.direction.dot(self.pinhole.forward));
+
+        let lens_point = self.aperture.sample(lens) * radius;
+        let origin =
+            ray.origin + self.pinhole.right * lens_point.x + self.pinhole.up * lens_point.y;
+
+        Some(Ray {
+            origin,
+            direction: (focus_point - origin).normalize(),
+        })
+    }
+
+    fn set_position(&mut self, position: Vec3) {
+        self.pinhole.set_position(position);
+    }
+
+    fn set_rotation(&mut self, rotation: Vec3) {
+        self.pinhole.set_rotation(rotation);
+    }
+
+    fn set_image_resolutions(&mut self, image_resolution: [u32; 2]) {
+        self.pinhole.set_image_resolutions(image_resolution);
+    }
+
+    fn compute_transformation_matrix(&mut self) {
+        self.pinhole.compute_transformation_matrix();
+    }
+
+    fn on_update(&mut self) {
+        self.pinhole.on_update();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lens_rays_converge_on_the_focus_plane() {
+        let pinhole = PinholeCamera::new(
+            Vec3::new(1.0, 0.5, 2.0),
+            Vec3::new(0.1, 0.3, 0.0),
+            35.0,
+            55.0,
+            [64, 48],
+        );
+        let aperture = Aperture {
+            f_stop: 1.4,
+            focus_distance: 3.0,
+            blades: 6,
+            rotation: 0.2,
+        };
+        let camera = ThinLensCamera::new(pinhole.clone(), aperture);
+        let radius = aperture.radius(35.0);
+
+        let center = pinhole
+            .get_ray(10, 20, Vec2::splat(0.5), Vec2::ZERO)
+            .unwrap();
+        let focus_t = 3.0 / center.direction.dot(pinhole.forward);
+        let focus_point = center.origin + center.direction * focus_t;
+
+        for lens in [Vec2::splat(0.5), Vec2::new(0.1, 0.9), Vec2::new(0.99, 0.3)] {
+            let ray = camera.get_ray(10, 20, Vec2::splat(0.5), lens).unwrap();
+            let t = (focus_point - ray.origin).dot(ray.direction);
+            assert!((ray.origin + ray.direction * t).abs_diff_eq(focus_point, 1e-4));
+            assert!((ray.origin - pinhole.position).length() <= radius * 1.0001);
+            assert!((ray.origin - pinhole.position).dot(pinhole.forward).abs() < 1e-6);
+        }
+
+        // hexagon inscribed in the unit circle, the inner circle has radius cos(30°)
+        for i in 0..64 {
+            let u = Vec2::new((i % 8) as f32 + 0.5, (i / 8) as f32 + 0.5) / 8.0;
+            let p = aperture.sample(u);
+            let angle = (p.y.atan2(p.x) - aperture.rotation).rem_euclid(TAU / 6.0);
+            let edge_distance = (TAU / 12.0).cos() / (angle - TAU / 12.0).cos();
+            assert!(p.length() <= edge_distance + 1e-5);
+        }
+    }
+}
@@ -1,8 +1,7 @@
 use crate::accumulators::TileAccumulator;
-use crate::sampler::Sampler;
 
-type RenderJob = Box<dyn FnOnce(&mut Sampler) -> TileAccumulator + Send + 'static>;
-type RenderJobResult = TileAccumulator;
+pub(crate) type RenderJobResult = crate::Result<TileAccumulator>;
+pub(crate) type RenderJob = Box<dyn FnOnce() -> RenderJobResult + Send + 'static>;
 
 pub(crate) mod threadpool;
 pub(crate) mod worker;
@@ -1,57 +1,126 @@
-use crossbeam::channel::{Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use crossbeam::channel::Receiver;
+use crossbeam::deque::{Injector, Stealer, Worker};
 
 use crate::concurrency::{RenderJob, RenderJobResult};
-use crate::sampler::Sampler;
 
 use super::worker::RenderingWorker;
 
+/// Jobs go to a global queue, every worker moves a few of them into its own deque at a
+/// time and steals from the others once both run dry, so no core idles while the last
+/// tiles of a pass are rendered
+pub(crate) struct JobQueue {
+    injector: Injector<RenderJob>,
+    stealers: Vec<Stealer<RenderJob>>,
+    pending: AtomicUsize, // counted before the push, until a worker picks the job up
+    shutdown: AtomicBool,
+    sleep: Mutex<()>,
+    wake: Condvar,
+}
+
+/// Jobs a worker takes from the global queue at once, small to keep the tile order
+const STEAL_BATCH: usize = 2;
+
+impl JobQueue {
+    /// Next job for the worker owning `local`, `None` once every queue is empty
+    pub(crate) fn find_job(&self, local: &Worker<RenderJob>) -> Option<RenderJob> {
+        let job = local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_with_limit_and_pop(local, STEAL_BATCH)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(|steal| steal.success())
+        })?;
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+        Some(job)
+    }
+
+    /// Blocks until there may be work, false once the pool shuts down
+    pub(crate) fn wait_for_jobs(&self) -> bool {
+        let mut guard = self.sleep.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if self.shutdown.load(Ordering::Acquire) {
+                return false;
+            }
+            if self.pending.load(Ordering::Acquire) > 0 {
+                return true;
+            }
+            guard = self
+                .wake
+                .wait(guard)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
 pub struct Threadpool {
     workers: Vec<RenderingWorker>,
-    job_tx: Option<Sender<RenderJob>>,
+    queue: Arc<JobQueue>,
 }
 
 impl Threadpool {
     pub fn new(size: usize) -> (Self, Receiver<RenderJobResult>) {
         assert!(size > 0);
-        let (job_tx, job_rx) = crossbeam::channel::unbounded::<RenderJob>();
-
         let (result_tx, result_rx) = crossbeam::channel::unbounded();
 
-        let mut workers = vec![];
+        let locals: Vec<_> = (0..size).map(|_| Worker::new_fifo()).collect();
+        let queue = Arc::new(JobQueue {
+            injector: Injector::new(),
+            stealers: locals.iter().map(Worker::stealer).collect(),
+            pending: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+        });
 
-        for id in 0..size {
-            workers.push(RenderingWorker::new(id, job_rx.clone(), result_tx.clone()));
-        }
+        let workers = locals
+            .into_iter()
+            .enumerate()
+            .map(|(id, local)| RenderingWorker::new(id, local, queue.clone(), result_tx.clone()))
+            .collect();
 
-        let tp = Self {
-            workers,
-            job_tx: Some(job_tx),
-        };
+        let tp = Self { workers, queue };
 
         (tp, result_rx)
     }
 
     pub fn execute<T>(&self, render_func: T)
     where
-        T: FnOnce(&mut Sampler) -> RenderJobResult + Send + 'static,
+        T: FnOnce() -> RenderJobResult + Send + 'static,
     {
-        let job = Box::new(render_func);
-        self.job_tx.as_ref().unwrap().send(job).unwrap();
+        // counted first, a worker may pop and finish the job before `push` returns
+        self.queue.pending.fetch_add(1, Ordering::AcqRel);
+        self.queue.injector.push(Box::new(render_func));
+
+        // under the lock, a worker is either before its check or already waiting
+        let _guard = self
+            .queue
+            .sleep
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.queue.wake.notify_one();
     }
 }
 
 impl Drop for Threadpool {
     fn drop(&mut self) {
-        if let Some(job_tx) = self.job_tx.take() {
-            drop(job_tx);
-        }
-        self.workers
-            .iter_mut()
-            .enumerate()
-            .for_each(|(id, worker)| {
-                worker.join();
-                println!("Worker {} has shut down.", id);
-            });
-        println!("RenderThreadpool has been dropped. All workers shut down.");
+        self.queue.shutdown.store(true, Ordering::Release);
+        drop(
+            self.queue
+                .sleep
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        self.queue.wake.notify_all();
+
+        self.workers.iter_mut().for_each(RenderingWorker::join);
+        log::debug!(
+            "threadpool dropped, {} workers shut down",
+            self.workers.len()
+        );
     }
 }
@@ -1,34 +1,46 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
 
-use crossbeam::channel::{Receiver, Sender};
+use crossbeam::channel::Sender;
+use crossbeam::deque::Worker;
 
+use crate::Error;
 use crate::concurrency::{RenderJob, RenderJobResult};
-use crate::sampler::Sampler;
+
+use super::threadpool::JobQueue;
 
 pub struct RenderingWorker {
     thread: Option<JoinHandle<()>>,
 }
 
 impl RenderingWorker {
-    pub fn new(id: usize, job_rx: Receiver<RenderJob>, result_tx: Sender<RenderJobResult>) -> Self {
+    pub fn new(
+        id: usize,
+        local: Worker<RenderJob>,
+        queue: Arc<JobQueue>,
+        result_tx: Sender<RenderJobResult>,
+    ) -> Self {
         let thread = thread::spawn(move || {
-            let mut sampler = Sampler::new();
             loop {
-                match job_rx.recv() {
-                    Ok(job) => {
-                        let acc = job(&mut sampler);
-                        if let Err(e) = result_tx.send(acc) {
-                            println!(
-                                "Worker {} failed to send result: receiver disconnected. Err : {:?}",
-                                id, e
-                            );
+                match queue.find_job(&local) {
+                    Some(job) => {
+                        // the pass waits for a result of every tile, a panicking
+                        // job still has to send one or the render never returns
+                        let result =
+                            panic::catch_unwind(AssertUnwindSafe(job)).unwrap_or_else(|_| {
+                                Err(Error::RenderAborted(format!("worker {id} panicked")))
+                            });
+                        if result_tx.send(result).is_err() {
+                            log::debug!("worker {id} shutting down, result receiver disconnected");
                             break;
                         }
                     }
-                    Err(_) => {
-                        println!("Worker {id} disconnected; Shutting down..");
-                        break;
+                    None => {
+                        if !queue.wait_for_jobs() {
+                            break;
+                        }
                     }
                 }
             }
@@ -0,0 +1,245 @@
+use std::num::NonZero;
+use std::thread;
+
+use glam::{Vec3, Vec4};
+
+use crate::aov::{Aov, AovSet};
+
+/// AOVs the denoiser needs, recorded next to the selected ones while denoising is on
+pub(crate) const GUIDES: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+/// Wavelet levels, the footprint doubles with every one and ends at 61 pixels wide
+const LEVELS: u32 = 5;
+/// B3 spline taps of the à-trous wavelet
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Luminance differences in standard deviations of the noise, larger blurs more
+const SIGMA_LUMINANCE: f32 = 4.0;
+/// Sharpness of the normal edge stop, as the power of the cosine between normals
+const NORMAL_POWER: i32 = 128;
+/// Depth differences relative to the depth of the center pixel, per pixel of distance
+const SIGMA_DEPTH: f32 = 0.05;
+
+pub(crate) fn has_guides(aovs: AovSet) -> bool {
+    GUIDES.iter().all(|&aov| aovs.contains(aov))
+}
+
+/// Snapshot of an accumulator the denoiser works on, taken so the filter
+/// runs without holding the accumulator lock
+pub(crate) struct DenoiseInput {
+    pub width: usize,
+    pub height: usize,
+    pub radiance: Vec<Vec4>,
+    pub noise: Vec<Vec3>, // per pixel estimate of the standard error of the radiance
+    pub albedo: Vec<Vec3>,
+    pub normal: Vec<Vec3>,
+    pub depth: Vec<f32>, // infinite where nothing was hit
+}
+
+impl DenoiseInput {
+    /// Edge-avoiding à-trous wavelet filter of SVGF without its temporal part. The lighting
+    /// is divided by the albedo before filtering and multiplied back after, so texture
+    /// detail survives; normals, depth and the noise estimate stop the filter at edges
+    pub fn run(mut self) -> Vec<Vec4> {
+        for (albedo, depth) in self.albedo.iter_mut().zip(&self.depth) {
+            *albedo = match depth.is_finite() {
+                true => albedo.max(Vec3::splat(0.01)),
+                false => Vec3::ONE,
+            };
+        }
+        for normal in &mut self.normal {
+            *normal = normal.normalize_or_zero();
+        }
+
+        let mut color: Vec<Vec3> = self
+            .radiance
+            .iter()
+            .zip(&self.albedo)
+            .map(|(radiance, &albedo)| radiance.truncate() / albedo)
+            .collect();
+        let mut variance: Vec<f32> = self
+            .noise
+            .iter()
+            .zip(&self.albedo)
+            .map(|(&noise, &albedo)| luminance(noise / albedo).powi(2))
+            .collect();
+
+        for level in 0..LEVELS {
+            let step = 1 << level;
+            let smoothed = self.blur_variance(&variance);
+            let filtered =
+                self.par_map(|x, y| self.a_trous_tap(&color, &variance, &smoothed, x, y, step));
+            (color, variance) = filtered.into_iter().unzip();
+        }
+
+        color
+            .iter()
+            .zip(&self.albedo)
+            .zip(&self.radiance)
+            .map(|((&color, &albedo), radiance)| Vec4::from((color * albedo, radiance.w)))
+            .collect()
+    }
+
+    /// One pixel of a wavelet level, with the variance of its result
+    fn a_trous_tap(
+        &self,
+        color: &[Vec3],
+        variance: &[f32],
+        smoothed: &[f32],
+        x: usize,
+        y: usize,
+        step: usize,
+    ) -> (Vec3, f32) {
+        let i = y * self.width + x;
+        let center = luminance(color[i]);
+        let sigma = SIGMA_LUMINANCE * smoothed[i].sqrt() + 1e-6;
+
+        let (mut color_sum, mut variance_sum, mut weight_sum) = (Vec3::ZERO, 0.0, 0.0);
+        for (ky, &hy) in KERNEL.iter().enumerate() {
+            for (kx, &hx) in KERNEL.iter().enumerate() {
+                let (Some(sx), Some(sy)) = (
+                    (x + kx * step).checked_sub(2 * step),
+                    (y + ky * step).checked_sub(2 * step),
+                ) else {
+                    continue;
+                };
+                if sx >= self.width || sy >= self.height {
+                    continue;
+                }
+
+                let j = sy * self.width + sx;
+                let mut weight = hx * hy;
+                if j != i {
+                    let distance = (kx.abs_diff(2) + ky.abs_diff(2)) * step;
+                    weight *= (-(center - luminance(color[j])).abs() / sigma).exp();
+                    weight *= self.geometry_weight(i, j, distance);
+                }
+
+                color_sum += color[j] * weight;
+                variance_sum += variance[j] * weight * weight;
+                weight_sum += weight;
+            }
+        }
+
+        // the center tap always has weight
+        (
+            color_sum / weight_sum,
+            variance_sum / (weight_sum * weight_sum),
+        )
+    }
+
+    /// Edge stop of the first hits, pixels that both missed count as the same surface
+    fn geometry_weight(&self, i: usize, j: usize, distance: usize) -> f32 {
+        let (depth_i, depth_j) = (self.depth[i], self.depth[j]);
+        match (depth_i.is_finite(), depth_j.is_finite()) {
+            (false, false) => 1.0,
+            (true, true) => {
+                let normal_weight = self.normal[i]
+                    .dot(self.normal[j])
+                    .max(0.0)
+                    .powi(NORMAL_POWER);
+                let depth_scale = SIGMA_DEPTH * depth_i * distance as f32 + 1e-6;
+                normal_weight * (-(depth_i - depth_j).abs() / depth_scale).exp()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// 3x3 gaussian of the variance, a single pixel's estimate is too noisy to steer the filter
+    fn blur_variance(&self, variance: &[f32]) -> Vec<f32> {
+        const TAPS: [f32; 3] = [0.25, 0.5, 0.25];
+        self.par_map(|x, y| {
+            let (mut sum, mut weight_sum) = (0.0, 0.0);
+            for (ky, &hy) in TAPS.iter().enumerate() {
+                for (kx, &hx) in TAPS.iter().enumerate() {
+                    let (Some(sx), Some(sy)) = ((x + kx).checked_sub(1), (y + ky).checked_sub(1))
+                    else {
+                        continue;
+                    };
+                    if sx < self.width && sy < self.height {
+                        sum += variance[sy * self.width + sx] * hx * hy;
+                        weight_sum += hx * hy;
+                    }
+                }
+            }
+            sum / weight_sum
+        })
+    }
+
+    /// Evaluates `f` for every pixel, bands of rows go to all cores
+    fn par_map<T: Send + Default + Clone>(&self, f: impl Fn(usize, usize) -> T + Sync) -> Vec<T> {
+        let mut output = vec![T::default(); self.width * self.height];
+        let threads = thread::available_parallelism().map_or(4, NonZero::get);
+        let band_rows = self.height.div_ceil(threads).max(1);
+
+        thread::scope(|s| {
+            for (band, chunk) in output.chunks_mut(band_rows * self.width.max(1)).enumerate() {
+                let f = &f;
+                s.spawn(move || {
+                    for (offset, value) in chunk.iter_mut().enumerate() {
+                        let index = band * band_rows * self.width + offset;
+                        *value = f(index % self.width, index / self.width);
+                    }
+                });
+            }
+        });
+        output
+    }
+}
+
+fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smooths_noise_and_keeps_normal_edges() {
+        let (width, height) = (32, 16);
+        let mut input = DenoiseInput {
+            width,
+            height,
+            radiance: vec![],
+            noise: vec![Vec3::splat(0.3); width * height],
+            albedo: vec![Vec3::ONE; width * height],
+            normal: vec![],
+            depth: vec![2.0; width * height],
+        };
+        // left half faces the camera and is lit, the right half faces sideways and is dark
+        for y in 0..height {
+            for x in 0..width {
+                let lit = x < width / 2;
+                let hash = ((x * 7919 + y * 104_729) % 97) as f32 / 96.0;
+                let value = if lit { 0.5 + hash } else { hash * 0.1 };
+                input.radiance.push(Vec4::new(value, value, value, 1.0));
+                input.normal.push(if lit { Vec3::Z } else { Vec3::X });
+            }
+        }
+
+        let noisy = input.radiance.clone();
+        let denoised = input.run();
+        let spread = |image: &[Vec4], x_range: std::ops::Range<usize>| {
+            let values: Vec<f32> = (0..height)
+                .flat_map(|y| x_range.clone().map(move |x| y * width + x))
+                .map(|i| image[i].x)
+                .collect();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            let deviation = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>();
+            (mean, (deviation / values.len() as f32).sqrt())
+        };
+
+        let (noisy_mean, noisy_deviation) = spread(&noisy, 0..width / 2);
+        let (mean, deviation) = spread(&denoised, 0..width / 2);
+        assert!((mean - noisy_mean).abs() < 0.05, "{mean} vs {noisy_mean}");
+        assert!(
+            deviation < noisy_deviation * 0.5,
+            "{deviation} vs {noisy_deviation}"
+        );
+
+        // the dark side right at the edge doesn't pick up light from across it
+        let (dark_mean, _) = spread(&denoised, width / 2..width / 2 + 1);
+        assert!(dark_mean < 0.1, "{dark_mean}");
+        assert!(denoised.iter().all(|pixel| pixel.w == 1.0));
+    }
+}
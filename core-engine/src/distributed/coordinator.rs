@@ -0,0 +1,381 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufReader, BufWriter};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crossbeam::channel::{Receiver, Sender};
+
+use crate::accumulators::TileAccumulator;
+use crate::file_formats::checkpoint::{BinaryReader, BinaryWriter};
+use crate::renderer::{RayTracer, StopReason, TileJob};
+use crate::scene::Scene;
+use crate::{Error, Result};
+
+use super::protocol::{self, JobRequest};
+
+/// Renders on worker processes connected over TCP, see `run_worker` for their side.
+///
+/// Every pass is split into tile jobs like for the local threadpool. Connected workers
+/// take one job at a time, render the tile for the passes it names and send back a tile
+/// accumulator per pass. The jobs of a worker that disconnects go back in the queue for
+/// the others. Tiles are merged in the same order as local ones, so the image is
+/// identical to a local render of the same scene and settings.
+///
+/// Workers connect whenever they are ready, a pass waits until there is one.
+pub struct Coordinator {
+    renderer: RayTracer,
+    scene: Arc<RwLock<Scene>>,
+    size: [u32; 2],
+    accumulate: bool,
+    passes_per_job: u32,
+    next_id: u64,
+    rays_traced: u64,
+    results: Receiver<JobResult>,
+    listener: Listener,
+}
+
+/// Tiles of a job, one per pass
+type JobResult = (u64, Vec<TileAccumulator>);
+
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    hash: u64,
+    job: TileJob,
+    results: Sender<JobResult>,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<JobRequest>,
+    workers: usize,
+    shutdown: bool,
+}
+
+impl Shared {
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Blocks until there is a job, `None` once the coordinator is gone
+    fn next_job(&self) -> Option<JobRequest> {
+        let mut queue = self.queue();
+        loop {
+            if queue.shutdown {
+                return None;
+            }
+            if let Some(job) = queue.jobs.pop_front() {
+                return Some(job);
+            }
+            queue = self
+                .changed
+                .wait(queue)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Puts the job of a lost worker in front, the pass is waiting for it
+    fn reissue(&self, job: JobRequest) {
+        self.queue().jobs.push_front(job);
+        self.changed.notify_one();
+    }
+}
+
+impl Coordinator {
+    /// Listens on `address` for workers. A renderer that already holds passes, e.g. resumed
+    /// from a checkpoint, is continued
+    pub fn bind(
+        address: impl ToSocketAddrs,
+        mut renderer: RayTracer,
+        scene: Arc<RwLock<Scene>>,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let accumulate = renderer.get_passes() > 0;
+        renderer.begin_pass(&scene, width, height, accumulate)?;
+        let hash = renderer.checkpoint_hash(&scene, renderer.get_settings().seed)?;
+
+        let (results_tx, results) = crossbeam::channel::unbounded();
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            changed: Condvar::new(),
+            hash,
+            job: renderer.tile_job(),
+            results: results_tx,
+        });
+        let listener = Listener::start(TcpListener::bind(address)?, shared)?;
+
+        Ok(Self {
+            renderer,
+            scene,
+            size: [width, height],
+            accumulate,
+            passes_per_job: 1,
+            next_id: 0,
+            rays_traced: 0,
+            results,
+            listener,
+        })
+    }
+
+    /// Where workers connect, useful after binding port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.address
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.listener.shared.queue().workers
+    }
+
+    /// Passes a job covers, more means fewer round trips but coarser progress [default: 1]
+    pub fn set_passes_per_job(&mut self, passes: u32) {
+        self.passes_per_job = passes.max(1);
+    }
+
+    /// Rays the workers traced so far
+    pub fn get_rays_traced(&self) -> u64 {
+        self.rays_traced
+    }
+
+    pub fn renderer(&self) -> &RayTracer {
+        &self.renderer
+    }
+
+    pub fn into_renderer(self) -> RayTracer {
+        self.renderer
+    }
+
+    /// Renders the passes of the next round of jobs on the workers, blocking until every
+    /// tile came back. Returns the stop condition of the render settings once one is met
+    pub fn render_passes(&mut self) -> Result<Option<StopReason>> {
+        // a resumed renderer may be done already
+        if let Some(reason) = self.renderer.stop_reason(self.renderer.get_elapsed()) {
+            return Ok(Some(reason));
+        }
+
+        let started = Instant::now();
+        let [width, height] = self.size;
+        let Some(tiles) = self
+            .renderer
+            .begin_pass(&self.scene, width, height, self.accumulate)?
+        else {
+            return Ok(Some(StopReason::NoiseThreshold));
+        };
+        self.accumulate = true;
+
+        // the last round stops at the sample count
+        let first = self.renderer.get_passes();
+        let remaining = self.renderer.get_samples_per_pixel().saturating_sub(first);
+        let count = self.passes_per_job.min(remaining);
+
+        let mut pending = HashMap::with_capacity(tiles.len());
+        {
+            let mut queue = self.listener.shared.queue();
+            for tile in tiles {
+                let job = JobRequest {
+                    id: self.next_id,
+                    tile,
+                    passes: first..first + count,
+                };
+                self.next_id += 1;
+                pending.insert(job.id, tile);
+                queue.jobs.push_back(job);
+            }
+        }
+        self.listener.shared.changed.notify_all();
+
+        let mut passes: Vec<Vec<TileAccumulator>> = (0..count).map(|_| vec![]).collect();
+        while !pending.is_empty() {
+            // `shared` holds a sender as long as the listener lives
+            let (id, tiles) = self.results.recv().map_err(|_| {
+                Error::RenderAborted("the coordinator stopped listening for workers".into())
+            })?;
+            if pending.remove(&id).is_none() {
+                continue;
+            }
+            for (pass, tile) in passes.iter_mut().zip(tiles) {
+                self.rays_traced += tile.rays_traced;
+                pass.push(tile);
+            }
+        }
+
+        self.renderer.merge_passes(passes, started)?;
+        Ok(self.renderer.stop_reason(self.renderer.get_elapsed()))
+    }
+}
+
+/// Accepts workers on a thread of its own, a connection thread serves each of them
+struct Listener {
+    shared: Arc<Shared>,
+    address: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Listener {
+    fn start(listener: TcpListener, shared: Arc<Shared>) -> Result<Self> {
+        let address = listener.local_addr()?;
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shared.queue().shutdown {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log::warn!("failed accepting a worker: {e}");
+                            continue;
+                        }
+                    };
+                    let shared = Arc::clone(&shared);
+                    thread::spawn(move || serve_worker(stream, &shared));
+                }
+            })
+        };
+
+        Ok(Self {
+            shared,
+            address,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.shared.queue().shutdown = true;
+        self.shared.changed.notify_all();
+
+        // wakes the accept loop, which sees the shutdown
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect(address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve_worker(stream: TcpStream, shared: &Shared) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "unknown".into(), |address| address.to_string());
+    let mut job = None;
+    match run_connection(stream, shared, &mut job) {
+        Ok(()) => log::debug!("worker {peer} done"),
+        Err(e) => log::warn!("dropped worker {peer}: {e}"),
+    }
+    if let Some(job) = job {
+        log::debug!("reissuing job {} of worker {peer}", job.id);
+        shared.reissue(job);
+    }
+}
+
+/// Hands jobs to one worker until shutdown, `job` holds the one in flight when it fails
+fn run_connection(stream: TcpStream, shared: &Shared, job: &mut Option<JobRequest>) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let mut reader = BinaryReader(BufReader::new(stream.try_clone()?));
+    let mut writer = BinaryWriter(BufWriter::new(stream));
+
+    let worker = protocol::read_hello(&mut reader)?;
+    protocol::write_hello(&mut writer, shared.hash)?;
+    if worker != shared.hash {
+        return Err(Error::WorkerMismatch {
+            worker,
+            coordinator: shared.hash,
+        });
+    }
+
+    shared.queue().workers += 1;
+    let result = hand_out_jobs(&mut reader, &mut writer, shared, job);
+    shared.queue().workers -= 1;
+    result
+}
+
+fn hand_out_jobs(
+    reader: &mut BinaryReader<BufReader<TcpStream>>,
+    writer: &mut BinaryWriter<BufWriter<TcpStream>>,
+    shared: &Shared,
+    in_flight: &mut Option<JobRequest>,
+) -> Result<()> {
+    while let Some(request) = shared.next_job() {
+        let request = in_flight.insert(request);
+        protocol::write_job(writer, request)?;
+        let tiles = protocol::read_result(reader, request, &shared.job)?;
+        let id = request.id;
+        *in_flight = None;
+        // a closed receiver means the coordinator is shutting down
+        let _ = shared.results.send((id, tiles));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::distributed::run_worker;
+    use crate::renderer::{Aov, RenderSettings};
+
+    #[test]
+    fn workers_match_local_render_and_lost_jobs_are_reissued() {
+        let settings = RenderSettings {
+            samples_per_pixel: 4,
+            thread_count: 2,
+            aovs: [Aov::Depth, Aov::Normal].into_iter().collect(),
+            ..Default::default()
+        };
+        let (width, height) = (70, 50);
+        let scene = Arc::new(RwLock::new(Scene::get_example_scene()));
+        let mut local = RayTracer::with_settings(width, height, settings);
+        for pass in 0..4 {
+            local.render(&scene, width, height, pass > 0).unwrap();
+        }
+
+        let renderer = RayTracer::with_settings(width, height, settings);
+        let mut coordinator =
+            Coordinator::bind("127.0.0.1:0", renderer, scene, width, height).unwrap();
+        coordinator.set_passes_per_job(3); // the second round has a single pass left
+        let (address, hash) = (coordinator.local_addr(), coordinator.listener.shared.hash);
+
+        let workers = thread::spawn(move || {
+            // takes a job and disconnects without an answer
+            let stream = TcpStream::connect(address).unwrap();
+            let mut reader = BinaryReader(BufReader::new(stream.try_clone().unwrap()));
+            let mut writer = BinaryWriter(BufWriter::new(stream));
+            protocol::write_hello(&mut writer, hash).unwrap();
+            protocol::read_hello(&mut reader).unwrap();
+            assert!(protocol::read_job(&mut reader).unwrap().is_some());
+            drop((reader, writer));
+
+            let scene = Arc::new(RwLock::new(Scene::get_example_scene()));
+            let renderer = RayTracer::with_settings(width, height, settings);
+            run_worker(address, renderer, &scene, width, height).unwrap()
+        });
+
+        assert_eq!(coordinator.render_passes().unwrap(), None);
+        assert_eq!(
+            coordinator.render_passes().unwrap(),
+            Some(StopReason::SampleCount)
+        );
+        assert!(coordinator.get_rays_traced() > 0);
+        // done, no jobs beyond the sample count
+        assert_eq!(
+            coordinator.render_passes().unwrap(),
+            Some(StopReason::SampleCount)
+        );
+        let renderer = coordinator.into_renderer();
+        assert!(workers.join().unwrap() > 0);
+
+        assert_eq!(renderer.get_passes(), 4);
+        assert!(renderer.get_hdr_output() == local.get_hdr_output());
+        assert_eq!(renderer.get_aov(Aov::Normal), local.get_aov(Aov::Normal));
+    }
+}
@@ -0,0 +1,6 @@
+mod coordinator;
+mod protocol;
+mod worker;
+
+pub use coordinator::Coordinator;
+pub use worker::run_worker;
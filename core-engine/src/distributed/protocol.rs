@@ -0,0 +1,124 @@
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use crate::accumulators::TileAccumulator;
+use crate::file_formats::checkpoint::{BinaryReader, BinaryWriter};
+use crate::renderer::TileJob;
+use crate::tiles::Tile;
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 8] = b"INSPTILE";
+/// Both ends must speak the same version, there is no negotiation
+const PROTOCOL_VERSION: u32 = 1;
+
+/// A tile and the passes it is sampled for, the worker answers with one tile
+/// accumulator per pass
+#[derive(Debug, Clone)]
+pub(crate) struct JobRequest {
+    pub id: u64,
+    pub tile: Tile,
+    pub passes: Range<u32>,
+}
+
+/// First message of either side, the hash covers the scene, camera and sampling settings
+pub(crate) fn write_hello<W: Write>(w: &mut BinaryWriter<W>, hash: u64) -> io::Result<()> {
+    w.0.write_all(MAGIC)?;
+    w.u32(PROTOCOL_VERSION)?;
+    w.u64(hash)?;
+    w.0.flush()
+}
+
+pub(crate) fn read_hello<R: Read>(r: &mut BinaryReader<R>) -> Result<u64> {
+    let mut magic = [0; MAGIC.len()];
+    r.0.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid(
+            "peer is not an insploray coordinator or worker".into(),
+        ));
+    }
+    let version = r.u32()?;
+    if version != PROTOCOL_VERSION {
+        return Err(invalid(format!(
+            "peer speaks version {version}, this build {PROTOCOL_VERSION}"
+        )));
+    }
+    Ok(r.u64()?)
+}
+
+pub(crate) fn write_job<W: Write>(w: &mut BinaryWriter<W>, job: &JobRequest) -> io::Result<()> {
+    let Tile {
+        x,
+        y,
+        width,
+        height,
+    } = job.tile;
+    w.u64(job.id)?;
+    w.u32s(&[x, y, width, height, job.passes.start, job.passes.end])?;
+    w.0.flush()
+}
+
+/// `None` once the coordinator closed the connection between jobs
+pub(crate) fn read_job<R: Read>(r: &mut BinaryReader<R>) -> Result<Option<JobRequest>> {
+    let id = match r.u64() {
+        Ok(id) => id,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut values = [0; 6];
+    r.u32s(&mut values)?;
+    let [x, y, width, height, start, end] = values;
+    if start > end {
+        return Err(invalid(format!("job {id} has passes {start}..{end}")));
+    }
+    Ok(Some(JobRequest {
+        id,
+        tile: Tile {
+            x,
+            y,
+            width,
+            height,
+        },
+        passes: start..end,
+    }))
+}
+
+pub(crate) fn write_result<W: Write>(
+    w: &mut BinaryWriter<W>,
+    id: u64,
+    tiles: &[TileAccumulator],
+) -> io::Result<()> {
+    w.u64(id)?;
+    tiles.iter().try_for_each(|tile| tile.write_state(w))?;
+    w.0.flush()
+}
+
+/// Tiles of `request`, `job` shapes the accumulators they are read into
+pub(crate) fn read_result<R: Read>(
+    r: &mut BinaryReader<R>,
+    request: &JobRequest,
+    job: &TileJob,
+) -> Result<Vec<TileAccumulator>> {
+    let id = r.u64()?;
+    if id != request.id {
+        return Err(invalid(format!(
+            "result of job {id}, expected {}",
+            request.id
+        )));
+    }
+    request
+        .passes
+        .clone()
+        .map(|_| {
+            let mut tile = job.tile_accumulator(request.tile);
+            tile.read_state(r)?;
+            Ok(tile)
+        })
+        .collect()
+}
+
+fn invalid(message: String) -> Error {
+    Error::Format {
+        format: "tile protocol message",
+        message,
+    }
+}
@@ -0,0 +1,76 @@
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use crate::file_formats::checkpoint::{BinaryReader, BinaryWriter};
+use crate::renderer::{RayTracer, TileJob};
+use crate::scene::Scene;
+use crate::{Error, Result};
+
+use super::protocol;
+
+/// Renders tile jobs of the `Coordinator` at `address` until it closes the connections,
+/// with one connection per thread of the renderer. `renderer`, `scene` and the image size
+/// must be set up like the coordinator's, it refuses the connection otherwise.
+/// Returns how many jobs were rendered
+pub fn run_worker(
+    address: impl ToSocketAddrs,
+    mut renderer: RayTracer,
+    scene: &Arc<RwLock<Scene>>,
+    width: u32,
+    height: u32,
+) -> Result<u64> {
+    renderer.begin_pass(scene, width, height, false)?;
+    let hash = renderer.checkpoint_hash(scene, renderer.get_settings().seed)?;
+    let job = renderer.tile_job();
+    let connections = renderer.get_settings().worker_count();
+
+    let address: Vec<_> = address.to_socket_addrs()?.collect();
+    thread::scope(|s| {
+        let threads: Vec<_> = (0..connections)
+            .map(|_| s.spawn(|| serve(TcpStream::connect(&address[..])?, hash, &job, scene)))
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| {
+                thread.join().unwrap_or_else(|_| {
+                    Err(Error::RenderAborted("a worker connection panicked".into()))
+                })
+            })
+            .sum()
+    })
+}
+
+/// Renders the jobs of one connection, returns how many there were
+fn serve(stream: TcpStream, hash: u64, job: &TileJob, scene: &RwLock<Scene>) -> Result<u64> {
+    stream.set_nodelay(true)?;
+    let mut reader = BinaryReader(BufReader::new(stream.try_clone()?));
+    let mut writer = BinaryWriter(BufWriter::new(stream));
+
+    protocol::write_hello(&mut writer, hash)?;
+    let coordinator = protocol::read_hello(&mut reader)?;
+    if coordinator != hash {
+        return Err(Error::WorkerMismatch {
+            worker: hash,
+            coordinator,
+        });
+    }
+
+    let abort = AtomicBool::new(false); // jobs are never cut short, the coordinator waits for them
+    let mut rendered = 0;
+    while let Some(request) = protocol::read_job(&mut reader)? {
+        let tiles: Vec<_> = {
+            let scene = scene.read()?;
+            request
+                .passes
+                .clone()
+                .map(|pass| job.render(&scene, request.tile, pass, &abort))
+                .collect()
+        };
+        protocol::write_result(&mut writer, request.id, &tiles)?;
+        rendered += 1;
+    }
+    Ok(rendered)
+}
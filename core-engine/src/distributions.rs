@@ -0,0 +1,171 @@
+use glam::Vec2;
+
+/// Piecewise constant 1D distribution over [0, 1)
+#[derive(Debug, Default, Clone)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>, // func.len() + 1 entries
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Self {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+
+        let mut running = 0.0_f64;
+        for &f in &func {
+            running += f.max(0.0) as f64 / n as f64;
+            cdf.push(running as f32);
+        }
+
+        let integral = running as f32;
+        if integral > 0.0 {
+            for c in cdf.iter_mut() {
+                *c /= integral;
+            }
+        } else {
+            // nothing to importance sample, stay uniform
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        }
+
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    #[inline]
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Returns sampled position in [0, 1), its pdf and the segment index
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.len();
+        // last cdf entry not greater than u
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(n - 1);
+
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.5
+        };
+
+        let x = ((index as f32 + offset.clamp(0.0, 1.0)) / n as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf(index), index)
+    }
+
+    /// Density of segment `index` with respect to [0, 1)
+    #[inline]
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[index].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise constant 2D distribution over [0, 1)^2, built from a row major image
+#[derive(Debug, Default, Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>, // one per row
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        assert_eq!(func.len(), width * height);
+
+        let conditional: Vec<Distribution1D> = func
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.conditional.is_empty() || self.marginal.integral() <= 0.0
+    }
+
+    /// Returns sampled (u, v) and its pdf with respect to [0, 1)^2
+    pub fn sample_continuous(&self, u: Vec2) -> (Vec2, f32) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u.y);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u.x);
+        (Vec2::new(u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, uv: Vec2) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let width = self.conditional[0].len();
+        let height = self.marginal.len();
+        let x = ((uv.x * width as f32) as usize).min(width - 1);
+        let y = ((uv.y * height as f32) as usize).min(height - 1);
+
+        self.conditional[y].func[x].max(0.0) / self.marginal.integral()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sampled_pdf_matches_lookup() {
+        let (width, height) = (7, 5);
+        let func: Vec<f32> = (0..width * height)
+            .map(|i| if i % 3 == 0 { 0.0 } else { (i % 11) as f32 })
+            .collect();
+        let distribution = Distribution2D::new(&func, width, height);
+
+        let mut histogram = vec![0_u32; width * height];
+        let count = 64;
+        for i in 0..count {
+            for j in 0..count {
+                let u = Vec2::new(
+                    (i as f32 + 0.5) / count as f32,
+                    (j as f32 + 0.5) / count as f32,
+                );
+                let (uv, pdf) = distribution.sample_continuous(u);
+                assert!(pdf > 0.0, "sampled a zero valued cell");
+                assert!((pdf - distribution.pdf(uv)).abs() < 1e-4);
+
+                let x = (uv.x * width as f32) as usize;
+                let y = (uv.y * height as f32) as usize;
+                histogram[y * width + x] += 1;
+            }
+        }
+
+        // sample frequency follows the function
+        let total: f32 = func.iter().sum();
+        for (cell, &hits) in histogram.iter().enumerate() {
+            let expected = func[cell] / total;
+            let actual = hits as f32 / (count * count) as f32;
+            assert!((expected - actual).abs() < 0.01, "cell {cell}");
+        }
+    }
+}
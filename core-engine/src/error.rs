@@ -0,0 +1,115 @@
+use std::fmt;
+use std::sync::PoisonError;
+
+/// Errors of the engine, every fallible public function returns this
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// A file that is not valid in its format, or a value that can't be written to it
+    Format {
+        format: &'static str,
+        message: String,
+    },
+    /// A scene that can't be rendered, `line` points into the scene file it was loaded from
+    InvalidScene {
+        line: Option<usize>,
+        key: String,
+        message: String,
+    },
+    /// A worker failed mid pass, the image keeps the passes merged before it
+    RenderAborted(String),
+    /// A checkpoint saved for another scene, camera or sampling settings than the render resuming it
+    CheckpointMismatch {
+        checkpoint: u64,
+        current: u64,
+    },
+    /// A remote worker set up with another scene, camera or sampling settings than its coordinator
+    WorkerMismatch {
+        worker: u64,
+        coordinator: u64,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Format { format, message } => write!(f, "invalid {format}: {message}"),
+            Self::InvalidScene {
+                line: Some(line),
+                key,
+                message,
+            } => write!(f, "line {line}, in `{key}`: {message}"),
+            Self::InvalidScene {
+                line: None,
+                key,
+                message,
+            } => write!(f, "in `{key}`: {message}"),
+            Self::RenderAborted(reason) => write!(f, "render aborted: {reason}"),
+            Self::CheckpointMismatch {
+                checkpoint,
+                current,
+            } => write!(
+                f,
+                "checkpoint was saved for a different scene or settings \
+                 (hash {checkpoint:016x}, now {current:016x})"
+            ),
+            Self::WorkerMismatch {
+                worker,
+                coordinator,
+            } => write!(
+                f,
+                "worker and coordinator render different scenes or settings \
+                 (hash {worker:016x}, coordinator {coordinator:016x})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<exr::error::Error> for Error {
+    fn from(e: exr::error::Error) -> Self {
+        match e {
+            exr::error::Error::Io(e) => Self::Io(e),
+            e => Self::Format {
+                format: "EXR",
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(e: png::EncodingError) -> Self {
+        match e {
+            png::EncodingError::IoError(e) => Self::Io(e),
+            e => Self::Format {
+                format: "PNG",
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+/// A panic while a lock was held leaves the scene or image half updated
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Self {
+        Self::RenderAborted("a render thread panicked while holding a lock".into())
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::time::Duration;
+
+use crate::accumulators::Accumulator;
+use crate::aov::AovSet;
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 8] = b"INSPCKPT";
+/// Newest version of the checkpoint format this build can read and the one it writes
+const CHECKPOINT_VERSION: u32 = 1;
+/// Larger images in a header mean a corrupt file, not a render worth allocating for
+const MAX_PIXELS: u64 = 1 << 28;
+
+/// State of a render between two passes: the accumulated sums with the resolution and AOV
+/// selection they were made for, how many passes went in and how long they took. Being raw
+/// sums, the checkpoints of renders with different seeds also merge into one image
+pub(crate) struct Checkpoint {
+    pub hash: u64, // of the scene, camera and sampling settings, see `RayTracer::save_checkpoint`
+    pub seed: u32, // also part of the hash, kept to verify the checkpoints being merged
+    pub passes: u32, // also the index of the next sample of every pixel
+    pub elapsed: Duration,
+    pub accumulator: Accumulator,
+}
+
+impl Checkpoint {
+    /// Writes next to `path` and renames over it once complete,
+    /// a crash while saving leaves the previous checkpoint intact
+    pub fn save(&self, path: &str) -> Result<()> {
+        let partial = format!("{path}.partial");
+        let mut w = BinaryWriter(BufWriter::new(File::create(&partial)?));
+
+        w.0.write_all(MAGIC)?;
+        w.u32(CHECKPOINT_VERSION)?;
+        w.u64(self.hash)?;
+        w.u32(self.seed)?;
+        let [width, height] = self.accumulator.get_resolution();
+        w.u32(width)?;
+        w.u32(height)?;
+        w.u32(self.accumulator.get_aov_selection().bits() as u32)?;
+        w.u32(self.passes)?;
+        w.u64(self.elapsed.as_nanos() as u64)?;
+        self.accumulator.write_state(&mut w)?;
+
+        w.0.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Checkpoint> {
+        let mut r = BinaryReader(BufReader::new(File::open(path)?));
+        Self::read(&mut r).map_err(|e| match e {
+            Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                invalid("file is truncated".into())
+            }
+            e => e,
+        })
+    }
+
+    fn read<R: Read>(r: &mut BinaryReader<R>) -> Result<Checkpoint> {
+        let mut magic = [0; MAGIC.len()];
+        r.0.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a checkpoint file".into()));
+        }
+        let version = r.u32()?;
+        if version > CHECKPOINT_VERSION {
+            return Err(invalid(format!(
+                "version {version} is newer than the supported {CHECKPOINT_VERSION}"
+            )));
+        }
+
+        let hash = r.u64()?;
+        let seed = r.u32()?;
+        let (width, height) = (r.u32()?, r.u32()?);
+        if width as u64 * height as u64 > MAX_PIXELS {
+            return Err(invalid(format!("image size {width}x{height}")));
+        }
+        let aovs = AovSet::from_bits(r.u32()? as u16);
+        let passes = r.u32()?;
+        let elapsed = Duration::from_nanos(r.u64()?);
+
+        let mut accumulator = Accumulator::new(width, height, aovs);
+        accumulator.read_state(r)?;
+        Ok(Checkpoint {
+            hash,
+            seed,
+            passes,
+            elapsed,
+            accumulator,
+        })
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::Format {
+        format: "checkpoint",
+        message,
+    }
+}
+
+/// Little endian numbers, the layout of checkpoints
+pub(crate) struct BinaryWriter<W>(pub W);
+
+impl<W: Write> BinaryWriter<W> {
+    pub fn u32(&mut self, value: u32) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    pub fn u64(&mut self, value: u64) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    pub fn u32s(&mut self, values: &[u32]) -> io::Result<()> {
+        values.iter().try_for_each(|&value| self.u32(value))
+    }
+
+    pub fn f32s(&mut self, values: &[f32]) -> io::Result<()> {
+        values
+            .iter()
+            .try_for_each(|value| self.u32(value.to_bits()))
+    }
+}
+
+pub(crate) struct BinaryReader<R>(pub R);
+
+impl<R: Read> BinaryReader<R> {
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.0.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        self.0.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Fills `values`, the caller knows how many there are
+    pub fn u32s(&mut self, values: &mut [u32]) -> io::Result<()> {
+        values.iter_mut().try_for_each(|value| {
+            *value = self.u32()?;
+            Ok(())
+        })
+    }
+
+    pub fn f32s(&mut self, values: &mut [f32]) -> io::Result<()> {
+        values.iter_mut().try_for_each(|value| {
+            *value = f32::from_bits(self.u32()?);
+            Ok(())
+        })
+    }
+}
@@ -1,5 +1,9 @@
-use exr::error::Result;
-use glam::Vec3;
+use std::f32::consts::PI;
+
+use glam::{Vec2, Vec3};
+
+use crate::Result;
+use crate::distributions::Distribution2D;
 
 /// HDR skybox loaded from EXR
 #[derive(Default)]
@@ -7,22 +11,95 @@ pub struct ExrImage {
     pub pixels_buffer: Vec<Vec3>,
     pub width: usize,
     pub height: usize,
+
+    // importance sampling of bright regions, empty until built
+    pub(crate) distribution: Distribution2D,
+}
+
+/// Lat-long mapping used by the skybox, y is up
+#[inline]
+fn direction_to_uv(dir: Vec3) -> Vec2 {
+    let theta = dir.y.clamp(-1.0, 1.0).acos();
+    let phi = dir.z.atan2(dir.x);
+    Vec2::new((phi + PI) / (2.0 * PI), theta / PI)
+}
+
+#[inline]
+fn uv_to_direction(uv: Vec2) -> Vec3 {
+    let phi = uv.x * 2.0 * PI - PI;
+    let theta = uv.y * PI;
+    let sin_theta = theta.sin();
+    Vec3::new(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin())
 }
 
 impl ExrImage {
     pub fn sample(&self, dir: Vec3) -> Vec3 {
-        let dir = dir.normalize();
-        let theta = dir.y.clamp(-1.0, 1.0).acos();
-        let phi = dir.z.atan2(dir.x);
-        let u = (phi + std::f32::consts::PI) / (2.0 * std::f32::consts::PI);
-        let v = theta / std::f32::consts::PI;
+        let uv = direction_to_uv(dir.normalize());
 
-        let x = (u * self.width as f32).floor() as usize % self.width;
-        let y = (v * self.height as f32).floor() as usize % self.height;
+        let x = (uv.x * self.width as f32).floor() as usize % self.width;
+        let y = (uv.y * self.height as f32).floor() as usize % self.height;
 
         self.pixels_buffer[y * self.width + x]
     }
 
+    /// Builds the luminance distribution used for importance sampling,
+    /// must be called again if `pixels_buffer` is edited
+    pub fn build_distribution(&mut self) {
+        let luminance: Vec<f32> = self
+            .pixels_buffer
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                // rows near the poles cover less solid angle
+                let v = ((i / self.width) as f32 + 0.5) / self.height as f32;
+                let sin_theta = (v * PI).sin();
+                pixel.dot(Vec3::new(0.2126, 0.7152, 0.0722)).max(0.0) * sin_theta
+            })
+            .collect();
+
+        self.distribution = Distribution2D::new(&luminance, self.width, self.height);
+    }
+
+    #[inline]
+    pub fn can_importance_sample(&self) -> bool {
+        !self.distribution.is_empty()
+    }
+
+    /// Samples a direction proportional to luminance, returns direction and solid angle pdf
+    pub fn sample_direction(&self, u: Vec2) -> Option<(Vec3, f32)> {
+        if !self.can_importance_sample() {
+            return None;
+        }
+
+        let (uv, uv_pdf) = self.distribution.sample_continuous(u);
+        let sin_theta = (uv.y * PI).sin();
+        if uv_pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        Some((uv_to_direction(uv), uv_pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    /// Solid angle pdf of `sample_direction` producing `dir`
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        let uv = direction_to_uv(dir.normalize());
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        self.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// Writes 32-bit float RGB, first row of `pixels_buffer` is the top of the image
+    pub fn save_exr_image(&self, path: &str) -> Result<()> {
+        exr::prelude::write_rgb_file(path, self.width, self.height, |x, y| {
+            let pixel = self.pixels_buffer[y * self.width + x];
+            (pixel.x, pixel.y, pixel.z)
+        })?;
+        Ok(())
+    }
+
     pub fn load_exr_image(path: &str) -> Result<ExrImage> {
         let image_2d = exr::prelude::read_first_rgba_layer_from_file(
             path,
@@ -32,19 +109,69 @@ impl ExrImage {
                     pixels_buffer: image_data,
                     width: resolution.width(),
                     height: resolution.height(),
+                    ..Default::default()
                 }
             },
             |skybox, pos, (r, g, b, _): (f32, f32, f32, f32)| {
                 skybox.pixels_buffer[pos.y() * skybox.width + pos.x()] = Vec3::new(r, g, b);
             },
-        );
-
-        match image_2d {
-            Ok(img) => {
-                let skybox = img.layer_data.channel_data.pixels;
-                Ok(skybox)
-            }
-            Err(err) => Err(err),
-        }
+        )?;
+
+        let mut skybox = image_2d.layer_data.channel_data.pixels;
+        skybox.build_distribution();
+        Ok(skybox)
+    }
+}
+
+/// Writes named 32-bit float channels into one EXR, `layer.channel` names group them
+/// into layers the way compositors expect. Every channel has `width * height` samples,
+/// first row is the top of the image
+pub fn save_layered_exr(
+    path: &str,
+    width: u32,
+    height: u32,
+    channels: Vec<(String, Vec<f32>)>,
+) -> Result<()> {
+    use exr::prelude::*;
+
+    let size = (width as usize, height as usize);
+    let channels = channels
+        .into_iter()
+        .map(|(name, samples)| AnyChannel::new(name.as_str(), FlatSamples::F32(samples)))
+        .collect();
+    let layer = Layer::new(
+        size,
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer).write().to_file(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let (width, height) = (5, 3);
+        let image = ExrImage {
+            pixels_buffer: (0..width * height)
+                .map(|i| Vec3::new(i as f32, 0.5 * i as f32, 100.0 + i as f32))
+                .collect(),
+            width,
+            height,
+            ..Default::default()
+        };
+
+        let path = crate::utils::test_path("exr_round_trip.exr");
+        let path = path.to_str().unwrap();
+        image.save_exr_image(path).unwrap();
+        let loaded = ExrImage::load_exr_image(path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height), (width, height));
+        assert_eq!(loaded.pixels_buffer, image.pixels_buffer);
+        std::fs::remove_file(path).unwrap();
     }
 }
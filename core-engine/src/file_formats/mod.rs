@@ -1,3 +1,10 @@
+pub(crate) mod checkpoint;
 pub mod exr;
+pub mod obj;
+pub mod png;
+pub mod scene_file;
 
-pub use exr::ExrImage;
+pub use exr::{ExrImage, save_layered_exr};
+pub use obj::ObjModel;
+pub use png::save_png_image;
+pub use scene_file::SceneFile;
@@ -0,0 +1,165 @@
+use crate::scene::{Matrial, Mesh};
+use crate::{Error, Result};
+use glam::{Vec2, Vec3};
+
+/// Triangle meshes and materials loaded from a Wavefront OBJ (+ MTL)
+#[derive(Default)]
+pub struct ObjModel {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Matrial>, // mesh material ids index into this list
+}
+
+impl ObjModel {
+    pub fn load_obj_model(path: &str) -> Result<ObjModel> {
+        let (models, materials) =
+            tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(|e| Error::Format {
+                format: "OBJ",
+                message: format!("{path}: {e}"),
+            })?;
+
+        let materials = match materials {
+            Ok(materials) => materials.iter().map(convert_material).collect(),
+            Err(e) => {
+                // geometry is still usable without the MTL, faces fall back to default material
+                log::warn!("failed loading MTL for {path}: {e}");
+                vec![]
+            }
+        };
+
+        let meshes = models
+            .into_iter()
+            .map(|model| convert_mesh(model.mesh, materials.len()))
+            .filter(|mesh| mesh.triangle_count() > 0)
+            .collect();
+
+        Ok(ObjModel { meshes, materials })
+    }
+}
+
+fn convert_mesh(mesh: tobj::Mesh, material_count: usize) -> Mesh {
+    let positions: Vec<Vec3> = mesh
+        .positions
+        .chunks_exact(3)
+        .map(|p| Vec3::new(p[0], p[1], p[2]))
+        .collect();
+
+    // with single index, attributes are either per vertex or missing
+    let normals = if mesh.normals.len() == mesh.positions.len() {
+        mesh.normals
+            .chunks_exact(3)
+            .map(|n| Vec3::new(n[0], n[1], n[2]).normalize_or_zero())
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let uvs = if mesh.texcoords.len() / 2 == positions.len() {
+        mesh.texcoords
+            .chunks_exact(2)
+            .map(|t| Vec2::new(t[0], t[1]))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let indices: Vec<[u32; 3]> = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|i| [i[0], i[1], i[2]])
+        .filter(|i| i.iter().all(|&v| (v as usize) < positions.len()))
+        .collect();
+
+    let material_id = match mesh.material_id {
+        Some(id) if id < material_count => id as i32,
+        _ => -1,
+    };
+
+    Mesh {
+        material_ids: vec![material_id; indices.len()],
+        positions,
+        normals,
+        uvs,
+        indices,
+    }
+}
+
+/// Maps MTL parameters onto metallic-roughness material
+fn convert_material(mtl: &tobj::Material) -> Matrial {
+    let mut material = Matrial::default();
+
+    if let Some([r, g, b]) = mtl.diffuse {
+        material.albedo = Vec3::new(r, g, b);
+    }
+
+    // Phong exponent to roughness, same mapping as Blender's OBJ importer
+    if let Some(ns) = mtl.shininess {
+        material.roughness = (1.0 - ns.clamp(0.0, 1000.0).sqrt() / 31.62).clamp(0.0, 1.0);
+    }
+
+    // PBR extension of MTL
+    if let Some(pr) = parse_param(mtl, "Pr") {
+        material.roughness = pr.clamp(0.0, 1.0);
+    }
+    if let Some(pm) = parse_param(mtl, "Pm") {
+        material.metalic = pm.clamp(0.0, 1.0);
+    }
+
+    let emission = mtl
+        .emissive
+        .map(|[r, g, b]| Vec3::new(r, g, b))
+        .unwrap_or(Vec3::ZERO);
+    if emission.max_element() > 0.0 {
+        // keep color normalized and move intensity into power
+        let power = emission.max_element();
+        material.emission_color = emission / power;
+        material.emissive_power = power;
+    }
+
+    material
+}
+
+fn parse_param(mtl: &tobj::Material, key: &str) -> Option<f32> {
+    mtl.unknown_param.get(key)?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_quad_with_material() {
+        let dir = crate::utils::test_path("obj_test");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::write(
+            dir.join("quad.mtl"),
+            "newmtl red\nKd 1.0 0.0 0.0\nKe 0.0 0.0 4.0\nPm 1.0\nPr 0.25\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("quad.obj"),
+            "mtllib quad.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             usemtl red\nf 1/1 2/2 3/3 4/4\n",
+        )
+        .unwrap();
+
+        let path = dir.join("quad.obj");
+        let model = ObjModel::load_obj_model(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.triangle_count(), 2);
+        assert_eq!(mesh.uvs.len(), mesh.positions.len());
+        assert_eq!(mesh.material_ids, vec![0, 0]);
+
+        let material = &model.materials[0];
+        assert_eq!(material.albedo, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(material.metalic, 1.0);
+        assert_eq!(material.roughness, 0.25);
+        assert_eq!(material.emission_color, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(material.emissive_power, 4.0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::Result;
+
+/// Writes 8-bit RGBA from 0xAARRGGBB pixels, first row is the top of the image
+pub fn save_png_image(path: &str, width: u32, height: u32, argb_pixels: &[u32]) -> Result<()> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // tone mapped output is already gamma encoded
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let data: Vec<u8> = argb_pixels
+        .iter()
+        .flat_map(|&argb| {
+            [
+                (argb >> 16) as u8,
+                (argb >> 8) as u8,
+                argb as u8,
+                (argb >> 24) as u8,
+            ]
+        })
+        .collect();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,1182 @@
+use std::f32::consts::TAU;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::cameras::{CameraKind, CameraModel, PinholeCamera};
+use crate::file_formats::{ExrImage, ObjModel};
+use crate::renderer::{AovSet, DisplayTransform, Filter, RenderSettings};
+use crate::scene::{MaterialType, Matrial, Mesh, Scene, Sphere};
+use crate::{Error, Result};
+
+/// Newest version of the scene format this build can read and the one it writes
+pub const SCENE_FILE_VERSION: u32 = 1;
+
+/// Scene, camera and render settings described by a TOML scene file
+pub struct SceneFile {
+    pub scene: Scene,
+    pub camera: CameraModel,
+    pub settings: RenderSettings,
+    pub width: u32,
+    pub height: u32,
+    pub skybox_path: Option<String>, // as written in the file, relative to the file
+}
+
+impl SceneFile {
+    /// Loads the scene file and every OBJ and EXR it references,
+    /// relative paths are resolved against the directory of the scene file
+    pub fn load_scene_file(path: &str) -> Result<SceneFile> {
+        let text = std::fs::read_to_string(path)?;
+        let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        Self::from_toml_str(&text, base_dir)
+    }
+
+    pub fn from_toml_str(text: &str, base_dir: &Path) -> Result<SceneFile> {
+        let deserializer =
+            toml::Deserializer::parse(text).map_err(|error| syntax_error("", error))?;
+        let dto: SceneDto = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            // spanned values show up as an extra level in the path
+            let key = e
+                .path()
+                .to_string()
+                .replace(".$__serde_spanned_private_value", "");
+            let mut error = e.into_inner();
+            error.set_input(Some(text));
+            syntax_error(&key, error)
+        })?;
+
+        SceneBuilder { text, base_dir }.build(dto)
+    }
+
+    /// Wraps an in-memory scene with the default camera and render settings
+    pub fn from_scene(scene: Scene) -> SceneFile {
+        let (camera, render) = (CameraDto::default(), RenderDto::default());
+        let [width, height] = [*render.width.get_ref(), *render.height.get_ref()];
+
+        SceneFile {
+            scene,
+            camera: CameraModel::Pinhole(PinholeCamera::new(
+                camera.position,
+                camera.rotation,
+                camera.focal_length,
+                camera.sensor_size,
+                [width, height],
+            )),
+            settings: RenderSettings::default(),
+            width,
+            height,
+            skybox_path: None,
+        }
+    }
+
+    pub fn save_scene_file(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.to_toml_string()?)?;
+        Ok(())
+    }
+
+    /// Meshes are written inline so the file does not depend on the OBJs it was built from
+    pub fn to_toml_string(&self) -> Result<String> {
+        let dto = SceneDto::from_scene_file(self);
+        toml::to_string(&dto).map_err(|e| Error::Format {
+            format: "scene file",
+            message: e.to_string(),
+        })
+    }
+}
+
+/// Malformed TOML or a value of the wrong type, toml reports line, column and
+/// the offending snippet itself
+fn syntax_error(key: &str, error: toml::de::Error) -> Error {
+    let message = match key {
+        "" | "." => error.to_string(),
+        key => format!("in `{key}`: {error}"),
+    };
+    Error::Format {
+        format: "scene file",
+        message,
+    }
+}
+
+/// Material referenced by its position in `materials` or by its name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum MaterialRef {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDto {
+    version: Spanned<u32>,
+    #[serde(default)]
+    camera: CameraDto,
+    #[serde(default)]
+    render: RenderDto,
+    #[serde(default)]
+    display: DisplayDto,
+    #[serde(default)]
+    integrator: IntegratorDto,
+    #[serde(default)]
+    environment: EnvironmentDto,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    materials: Vec<MaterialDto>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    spheres: Vec<Spanned<SphereDto>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    meshes: Vec<Spanned<MeshDto>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraDto {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    position: Vec3,
+    rotation: Vec3, // euler angles in radians
+    focal_length: f32,
+    sensor_size: f32,
+
+    // parameters of a single camera type each, defaults of `CameraModel::new` when missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    f_stop: Option<Spanned<f32>>, // thin-lens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    focus_distance: Option<Spanned<f32>>, // thin-lens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aperture_blades: Option<Spanned<u32>>, // thin-lens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aperture_rotation: Option<Spanned<f32>>, // thin-lens, radians
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ortho_scale: Option<Spanned<f32>>, // orthographic
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fov: Option<Spanned<f32>>, // fisheye, radians
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fisheye_mapping: Option<Spanned<String>>, // fisheye
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stereo: Option<Spanned<bool>>, // equirectangular
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eye_separation: Option<Spanned<f32>>, // equirectangular
+}
+
+impl Default for CameraDto {
+    fn default() -> Self {
+        Self {
+            kind: unspanned(CameraKind::default().to_string()),
+            position: Vec3::new(0.0, 0.0, 2.0),
+            rotation: Vec3::ZERO,
+            focal_length: 35.0,
+            sensor_size: 55.0,
+            f_stop: None,
+            focus_distance: None,
+            aperture_blades: None,
+            aperture_rotation: None,
+            ortho_scale: None,
+            fov: None,
+            fisheye_mapping: None,
+            stereo: None,
+            eye_separation: None,
+        }
+    }
+}
+
+impl CameraDto {
+    fn from_model(camera: &CameraModel) -> Self {
+        let pinhole = camera.pinhole();
+        let mut dto = CameraDto {
+            kind: unspanned(camera.kind().to_string()),
+            position: pinhole.position,
+            rotation: pinhole.rotation,
+            focal_length: pinhole.focal_length,
+            sensor_size: pinhole.sensor_size,
+            ..Default::default()
+        };
+
+        match camera {
+            CameraModel::Pinhole(_) => {}
+            CameraModel::ThinLens(camera) => {
+                let aperture = camera.aperture;
+                dto.f_stop = Some(unspanned(aperture.f_stop));
+                dto.focus_distance = Some(unspanned(aperture.focus_distance));
+                dto.aperture_blades = Some(unspanned(aperture.blades));
+                dto.aperture_rotation = Some(unspanned(aperture.rotation));
+            }
+            CameraModel::Orthographic(camera) => {
+                dto.ortho_scale = Some(unspanned(camera.ortho_scale));
+            }
+            CameraModel::Fisheye(camera) => {
+                dto.fov = Some(unspanned(camera.fov));
+                dto.fisheye_mapping = Some(unspanned(camera.mapping.to_string()));
+            }
+            CameraModel::Equirectangular(camera) => {
+                dto.stereo = Some(unspanned(camera.stereo));
+                dto.eye_separation = Some(unspanned(camera.eye_separation));
+            }
+        }
+        dto
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RenderDto {
+    width: Spanned<u32>,
+    height: Spanned<u32>,
+    samples_per_pixel: u32,
+    sampler: Spanned<String>,
+    filter: Spanned<String>,
+    filter_radius: Option<Spanned<f32>>, // defaults per filter type
+    noise_threshold: Spanned<f32>,       // adaptive sampling, 0 turns it off
+    min_samples: u32,
+    time_limit: Option<Spanned<f32>>, // seconds, renders stop at whichever limit comes first
+    tile_size: Spanned<u32>,
+    tile_order: Spanned<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    aovs: Vec<Spanned<String>>, // passes written next to the beauty image into EXR output
+    denoise: bool,
+    threads: usize, // 0 uses every core
+    seed: u32,
+}
+
+impl Default for RenderDto {
+    fn default() -> Self {
+        let settings = RenderSettings::default();
+        Self {
+            width: unspanned(1280),
+            height: unspanned(720),
+            samples_per_pixel: settings.samples_per_pixel,
+            sampler: unspanned(settings.sampler.to_string()),
+            filter: unspanned(settings.filter.kind.to_string()),
+            filter_radius: None,
+            noise_threshold: unspanned(settings.noise_threshold),
+            min_samples: settings.min_samples,
+            time_limit: None,
+            tile_size: unspanned(settings.tile_size),
+            tile_order: unspanned(settings.tile_order.to_string()),
+            aovs: vec![],
+            denoise: settings.denoise,
+            threads: settings.thread_count,
+            seed: settings.seed,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DisplayDto {
+    tone_mapper: Spanned<String>,
+    exposure: Spanned<f32>,    // stops
+    white_point: Spanned<f32>, // only used by reinhard
+}
+
+impl Default for DisplayDto {
+    fn default() -> Self {
+        let display = DisplayTransform::default();
+        Self {
+            tone_mapper: unspanned(display.tone_mapper.to_string()),
+            exposure: unspanned(display.exposure),
+            white_point: unspanned(display.white_point),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct IntegratorDto {
+    bounces: usize,
+    max_compulsory_bounces: usize,
+}
+
+impl Default for IntegratorDto {
+    fn default() -> Self {
+        let settings = RenderSettings::default();
+        Self {
+            bounces: settings.bounces,
+            max_compulsory_bounces: settings.max_compulsory_bounces,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct EnvironmentDto {
+    color: Vec3,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skybox: Option<Spanned<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MaterialDto {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(rename = "type")]
+    material_type: MaterialTypeDto,
+    albedo: Vec3,
+    roughness: f32,
+    metallic: f32,
+    ior: f32,
+    emission_color: Vec3,
+    emissive_power: f32,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MaterialTypeDto {
+    #[default]
+    Opaque,
+    Dielectric,
+}
+
+impl Default for MaterialDto {
+    fn default() -> Self {
+        Self::from_material(&Matrial::default())
+    }
+}
+
+impl MaterialDto {
+    fn from_material(material: &Matrial) -> Self {
+        Self {
+            name: None,
+            material_type: match material.material_type {
+                MaterialType::Opaque => MaterialTypeDto::Opaque,
+                MaterialType::Dielectric => MaterialTypeDto::Dielectric,
+            },
+            albedo: material.albedo,
+            roughness: material.roughness,
+            metallic: material.metalic,
+            ior: material.ior,
+            emission_color: material.emission_color,
+            emissive_power: material.emissive_power,
+        }
+    }
+
+    fn to_material(&self) -> Matrial {
+        Matrial {
+            material_type: match self.material_type {
+                MaterialTypeDto::Opaque => MaterialType::Opaque,
+                MaterialTypeDto::Dielectric => MaterialType::Dielectric,
+            },
+            albedo: self.albedo,
+            roughness: self.roughness,
+            metalic: self.metallic,
+            ior: self.ior,
+            emission_color: self.emission_color,
+            emissive_power: self.emissive_power,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDto {
+    position: Vec3,
+    radius: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<Spanned<MaterialRef>>,
+}
+
+/// Either a reference to an OBJ file or an inline triangle mesh
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDto {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    obj: Option<Spanned<String>>,
+    /// Overrides the materials of an OBJ, or the material of every inline triangle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<Spanned<MaterialRef>>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    positions: Vec<Vec3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    normals: Option<Spanned<Vec<Vec3>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uvs: Option<Spanned<Vec<Vec2>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    indices: Option<Spanned<Vec<[u32; 3]>>>,
+    /// Per triangle material indices, -1 is the default material
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material_ids: Option<Spanned<Vec<i32>>>,
+}
+
+impl SceneDto {
+    fn from_scene_file(file: &SceneFile) -> Self {
+        let scene = &file.scene;
+
+        let spheres = scene
+            .spheres
+            .iter()
+            .map(|sphere| {
+                unspanned(SphereDto {
+                    position: sphere.position,
+                    radius: sphere.radius,
+                    material: usize::try_from(sphere.material_id)
+                        .ok()
+                        .map(|id| unspanned(MaterialRef::Index(id))),
+                })
+            })
+            .collect();
+
+        let meshes = scene
+            .meshes
+            .iter()
+            .map(|mesh| {
+                let first_id = mesh.material_ids.first().copied().unwrap_or(-1);
+                let uniform = mesh.material_ids.iter().all(|&id| id == first_id);
+
+                let (material, material_ids) = match usize::try_from(first_id) {
+                    Ok(id) if uniform => (Some(unspanned(MaterialRef::Index(id))), vec![]),
+                    Err(_) if uniform => (None, vec![]),
+                    _ => (None, mesh.material_ids.clone()),
+                };
+
+                unspanned(MeshDto {
+                    obj: None,
+                    material,
+                    positions: mesh.positions.clone(),
+                    normals: unspanned_list(&mesh.normals),
+                    uvs: unspanned_list(&mesh.uvs),
+                    indices: unspanned_list(&mesh.indices),
+                    material_ids: unspanned_list(&material_ids),
+                })
+            })
+            .collect();
+
+        Self {
+            version: unspanned(SCENE_FILE_VERSION),
+            camera: CameraDto::from_model(&file.camera),
+            render: RenderDto {
+                width: unspanned(file.width),
+                height: unspanned(file.height),
+                samples_per_pixel: file.settings.samples_per_pixel,
+                sampler: unspanned(file.settings.sampler.to_string()),
+                filter: unspanned(file.settings.filter.kind.to_string()),
+                filter_radius: Some(unspanned(file.settings.filter.radius)),
+                noise_threshold: unspanned(file.settings.noise_threshold),
+                min_samples: file.settings.min_samples,
+                time_limit: file
+                    .settings
+                    .time_limit
+                    .map(|limit| unspanned(limit.as_secs_f32())),
+                tile_size: unspanned(file.settings.tile_size),
+                tile_order: unspanned(file.settings.tile_order.to_string()),
+                aovs: file
+                    .settings
+                    .aovs
+                    .iter()
+                    .map(|aov| unspanned(aov.to_string()))
+                    .collect(),
+                denoise: file.settings.denoise,
+                threads: file.settings.thread_count,
+                seed: file.settings.seed,
+            },
+            display: DisplayDto {
+                tone_mapper: unspanned(file.settings.display.tone_mapper.to_string()),
+                exposure: unspanned(file.settings.display.exposure),
+                white_point: unspanned(file.settings.display.white_point),
+            },
+            integrator: IntegratorDto {
+                bounces: file.settings.bounces,
+                max_compulsory_bounces: file.settings.max_compulsory_bounces,
+            },
+            environment: EnvironmentDto {
+                color: scene.default_sky_color,
+                skybox: file.skybox_path.clone().map(unspanned),
+            },
+            materials: scene
+                .materials
+                .iter()
+                .map(MaterialDto::from_material)
+                .collect(),
+            spheres,
+            meshes,
+        }
+    }
+}
+
+/// Values written by the saver have no position in any source text
+fn unspanned<T>(value: T) -> Spanned<T> {
+    Spanned::new(0..0, value)
+}
+
+/// `None` for an empty list, so it is left out of the file
+fn unspanned_list<T: Clone>(values: &[T]) -> Option<Spanned<Vec<T>>> {
+    (!values.is_empty()).then(|| unspanned(values.to_vec()))
+}
+
+/// The values of a list and where it is in the file, `fallback` if it isn't
+fn split_list<T>(list: Option<Spanned<Vec<T>>>, fallback: &Range<usize>) -> (Range<usize>, Vec<T>) {
+    match list {
+        Some(list) => (list.span(), list.into_inner()),
+        None => (fallback.clone(), vec![]),
+    }
+}
+
+fn span_of<T>(value: &Option<Spanned<T>>) -> Option<Range<usize>> {
+    value.as_ref().map(Spanned::span)
+}
+
+/// Overwrites `target` with the value if the file has one
+fn assign<T>(target: &mut T, value: Option<Spanned<T>>) {
+    if let Some(value) = value {
+        *target = value.into_inner();
+    }
+}
+
+/// Validates a parsed file and turns it into a scene, errors point back into `text`
+struct SceneBuilder<'a> {
+    text: &'a str,
+    base_dir: &'a Path,
+}
+
+impl SceneBuilder<'_> {
+    fn invalid<T>(&self, span: Range<usize>, key: String, message: String) -> Result<T> {
+        let line = self.text[..span.start.min(self.text.len())]
+            .matches('\n')
+            .count()
+            + 1;
+        Err(Error::InvalidScene {
+            line: Some(line),
+            key,
+            message,
+        })
+    }
+
+    fn build(&self, dto: SceneDto) -> Result<SceneFile> {
+        if *dto.version.get_ref() > SCENE_FILE_VERSION {
+            return self.invalid(
+                dto.version.span(),
+                "version".into(),
+                format!(
+                    "unsupported scene version {}, newest supported is {SCENE_FILE_VERSION}",
+                    dto.version.get_ref()
+                ),
+            );
+        }
+
+        let (width, height) = (*dto.render.width.get_ref(), *dto.render.height.get_ref());
+        if width == 0 {
+            return self.invalid(
+                dto.render.width.span(),
+                "render.width".into(),
+                "must be positive".into(),
+            );
+        }
+        if height == 0 {
+            return self.invalid(
+                dto.render.height.span(),
+                "render.height".into(),
+                "must be positive".into(),
+            );
+        }
+
+        let mut scene = Scene {
+            default_sky_color: dto.environment.color,
+            materials: dto.materials.iter().map(MaterialDto::to_material).collect(),
+            ..Default::default()
+        };
+
+        let names: Vec<Option<&str>> = dto.materials.iter().map(|m| m.name.as_deref()).collect();
+        let resolve = |reference: &Option<Spanned<MaterialRef>>, key: String| -> Result<i32> {
+            let Some(reference) = reference else {
+                return Ok(-1);
+            };
+            let id = match reference.get_ref() {
+                MaterialRef::Index(id) if *id < names.len() => Some(*id),
+                MaterialRef::Index(_) => None,
+                MaterialRef::Name(name) => names.iter().position(|n| *n == Some(name.as_str())),
+            };
+            match id {
+                Some(id) => Ok(id as i32),
+                None => self.invalid(
+                    reference.span(),
+                    key,
+                    format!(
+                        "unknown material {:?}, scene has {} materials",
+                        reference.get_ref(),
+                        names.len()
+                    ),
+                ),
+            }
+        };
+
+        for (i, entry) in dto.spheres.iter().enumerate() {
+            let sphere = entry.get_ref();
+            if sphere.radius <= 0.0 {
+                return self.invalid(
+                    entry.span(),
+                    format!("spheres[{i}].radius"),
+                    "must be positive".into(),
+                );
+            }
+
+            scene.spheres.push(Sphere {
+                position: sphere.position,
+                radius: sphere.radius,
+                material_id: resolve(&sphere.material, format!("spheres[{i}].material"))?,
+            });
+        }
+
+        let material_count = scene.materials.len();
+        for (i, entry) in dto.meshes.into_iter().enumerate() {
+            let span = entry.span();
+            let mesh = entry.into_inner();
+            let material_id = resolve(&mesh.material, format!("meshes[{i}].material"))?;
+
+            if let Some(obj) = &mesh.obj {
+                let path = self.base_dir.join(obj.get_ref());
+                let model = ObjModel::load_obj_model(&path.to_string_lossy()).or_else(|e| {
+                    self.invalid(
+                        obj.span(),
+                        format!("meshes[{i}].obj"),
+                        format!("failed loading OBJ {}: {e}", path.display()),
+                    )
+                })?;
+
+                let mesh_start = scene.meshes.len();
+                scene.add_obj_model(model);
+                if mesh.material.is_some() {
+                    for added in &mut scene.meshes[mesh_start..] {
+                        added.material_ids.fill(material_id);
+                    }
+                }
+                continue;
+            }
+
+            let mesh = self.inline_mesh(mesh, material_id, material_count, span, i)?;
+            scene.meshes.push(mesh);
+        }
+
+        let skybox_path = dto.environment.skybox.as_ref().map(|p| p.get_ref().clone());
+        if let Some(skybox) = &dto.environment.skybox {
+            let path = self.base_dir.join(skybox.get_ref());
+            let image = ExrImage::load_exr_image(&path.to_string_lossy()).or_else(|e| {
+                self.invalid(
+                    skybox.span(),
+                    "environment.skybox".into(),
+                    format!("failed loading EXR {}: {e}", path.display()),
+                )
+            })?;
+            scene.skybox = Some(image);
+        }
+
+        scene.update_acceleration_structure();
+        scene.update_light_list();
+
+        let sampler = &dto.render.sampler;
+        let sampler = sampler
+            .get_ref()
+            .parse()
+            .or_else(|message| self.invalid(sampler.span(), "render.sampler".into(), message))?;
+
+        let kind = &dto.render.filter;
+        let mut filter = Filter::new(
+            kind.get_ref()
+                .parse()
+                .or_else(|message| self.invalid(kind.span(), "render.filter".into(), message))?,
+        );
+        if let Some(radius) = &dto.render.filter_radius {
+            let value = *radius.get_ref();
+            if value <= 0.0 || value.is_nan() {
+                return self.invalid(
+                    radius.span(),
+                    "render.filter_radius".into(),
+                    "must be positive".into(),
+                );
+            }
+            filter.radius = value;
+        }
+
+        let noise_threshold = *dto.render.noise_threshold.get_ref();
+        if noise_threshold < 0.0 || noise_threshold.is_nan() {
+            return self.invalid(
+                dto.render.noise_threshold.span(),
+                "render.noise_threshold".into(),
+                "can't be negative".into(),
+            );
+        }
+
+        let mut time_limit = None;
+        if let Some(seconds) = &dto.render.time_limit {
+            let value = *seconds.get_ref();
+            if value <= 0.0 {
+                return self.invalid(
+                    seconds.span(),
+                    "render.time_limit".into(),
+                    "must be positive".into(),
+                );
+            }
+            match Duration::try_from_secs_f32(value) {
+                Ok(limit) => time_limit = Some(limit),
+                Err(e) => {
+                    return self.invalid(
+                        seconds.span(),
+                        "render.time_limit".into(),
+                        format!("{value} seconds can't be used: {e}"),
+                    );
+                }
+            }
+        }
+
+        let tile_size = &dto.render.tile_size;
+        if *tile_size.get_ref() == 0 {
+            return self.invalid(
+                tile_size.span(),
+                "render.tile_size".into(),
+                "must be positive".into(),
+            );
+        }
+        let tile_order = &dto.render.tile_order;
+        let tile_order = tile_order.get_ref().parse().or_else(|message| {
+            self.invalid(tile_order.span(), "render.tile_order".into(), message)
+        })?;
+
+        let mut aovs = AovSet::default();
+        for (i, aov) in dto.render.aovs.iter().enumerate() {
+            let parsed = aov.get_ref().parse().or_else(|message| {
+                self.invalid(aov.span(), format!("render.aovs[{i}]"), message)
+            })?;
+            aovs.insert(parsed);
+        }
+
+        let display = self.display(&dto.display)?;
+        let camera = self.camera(dto.camera, [width, height])?;
+        let settings = RenderSettings {
+            thread_count: dto.render.threads,
+            bounces: dto.integrator.bounces,
+            max_compulsory_bounces: dto.integrator.max_compulsory_bounces,
+            seed: dto.render.seed,
+            sampler,
+            samples_per_pixel: dto.render.samples_per_pixel,
+            filter,
+            noise_threshold,
+            min_samples: dto.render.min_samples,
+            time_limit,
+            tile_size: *dto.render.tile_size.get_ref(),
+            tile_order,
+            aovs,
+            denoise: dto.render.denoise,
+            display,
+        };
+
+        Ok(SceneFile {
+            scene,
+            camera,
+            settings,
+            width,
+            height,
+            skybox_path,
+        })
+    }
+
+    fn display(&self, dto: &DisplayDto) -> Result<DisplayTransform> {
+        let tone_mapper = &dto.tone_mapper;
+        let tone_mapper = tone_mapper.get_ref().parse().or_else(|message| {
+            self.invalid(tone_mapper.span(), "display.tone_mapper".into(), message)
+        })?;
+
+        let exposure = *dto.exposure.get_ref();
+        if !exposure.is_finite() {
+            return self.invalid(
+                dto.exposure.span(),
+                "display.exposure".into(),
+                "must be finite".into(),
+            );
+        }
+
+        let white_point = *dto.white_point.get_ref();
+        if white_point <= 0.0 || !white_point.is_finite() {
+            return self.invalid(
+                dto.white_point.span(),
+                "display.white_point".into(),
+                "must be positive".into(),
+            );
+        }
+
+        Ok(DisplayTransform {
+            tone_mapper,
+            exposure,
+            white_point,
+        })
+    }
+
+    fn camera(&self, dto: CameraDto, image_size: [u32; 2]) -> Result<CameraModel> {
+        let kind: CameraKind = dto
+            .kind
+            .get_ref()
+            .parse()
+            .or_else(|message| self.invalid(dto.kind.span(), "camera.type".into(), message))?;
+
+        let parameters = [
+            ("f_stop", CameraKind::ThinLens, span_of(&dto.f_stop)),
+            (
+                "focus_distance",
+                CameraKind::ThinLens,
+                span_of(&dto.focus_distance),
+            ),
+            (
+                "aperture_blades",
+                CameraKind::ThinLens,
+                span_of(&dto.aperture_blades),
+            ),
+            (
+                "aperture_rotation",
+                CameraKind::ThinLens,
+                span_of(&dto.aperture_rotation),
+            ),
+            (
+                "ortho_scale",
+                CameraKind::Orthographic,
+                span_of(&dto.ortho_scale),
+            ),
+            ("fov", CameraKind::Fisheye, span_of(&dto.fov)),
+            (
+                "fisheye_mapping",
+                CameraKind::Fisheye,
+                span_of(&dto.fisheye_mapping),
+            ),
+            ("stereo", CameraKind::Equirectangular, span_of(&dto.stereo)),
+            (
+                "eye_separation",
+                CameraKind::Equirectangular,
+                span_of(&dto.eye_separation),
+            ),
+        ];
+        for (key, owner, span) in parameters {
+            if let Some(span) = span
+                && owner != kind
+            {
+                let message = format!("only {owner} cameras have this parameter");
+                return self.invalid(span, format!("camera.{key}"), message);
+            }
+        }
+
+        for (key, value) in [
+            ("f_stop", &dto.f_stop),
+            ("focus_distance", &dto.focus_distance),
+            ("ortho_scale", &dto.ortho_scale),
+            ("fov", &dto.fov),
+        ] {
+            if let Some(value) = value
+                && (*value.get_ref() <= 0.0 || value.get_ref().is_nan())
+            {
+                return self.invalid(
+                    value.span(),
+                    format!("camera.{key}"),
+                    "must be positive".into(),
+                );
+            }
+        }
+        if let Some(fov) = &dto.fov
+            && *fov.get_ref() > TAU
+        {
+            let message = "can't be wider than a full turn (2 pi)".into();
+            return self.invalid(fov.span(), "camera.fov".into(), message);
+        }
+        if let Some(blades) = &dto.aperture_blades
+            && matches!(blades.get_ref(), 1 | 2)
+        {
+            let message = "needs at least 3 blades, 0 for a round aperture".into();
+            return self.invalid(blades.span(), "camera.aperture_blades".into(), message);
+        }
+        if let Some(separation) = &dto.eye_separation
+            && (*separation.get_ref() < 0.0 || separation.get_ref().is_nan())
+        {
+            let message = "can't be negative".into();
+            return self.invalid(separation.span(), "camera.eye_separation".into(), message);
+        }
+
+        let pinhole = PinholeCamera::new(
+            dto.position,
+            dto.rotation,
+            dto.focal_length,
+            dto.sensor_size,
+            image_size,
+        );
+        let mut camera = CameraModel::new(kind, pinhole);
+        match &mut camera {
+            CameraModel::Pinhole(_) => {}
+            CameraModel::ThinLens(camera) => {
+                let aperture = &mut camera.aperture;
+                assign(&mut aperture.f_stop, dto.f_stop);
+                assign(&mut aperture.focus_distance, dto.focus_distance);
+                assign(&mut aperture.blades, dto.aperture_blades);
+                assign(&mut aperture.rotation, dto.aperture_rotation);
+            }
+            CameraModel::Orthographic(camera) => assign(&mut camera.ortho_scale, dto.ortho_scale),
+            CameraModel::Fisheye(camera) => {
+                assign(&mut camera.fov, dto.fov);
+                if let Some(mapping) = &dto.fisheye_mapping {
+                    camera.mapping = mapping.get_ref().parse().or_else(|message| {
+                        self.invalid(mapping.span(), "camera.fisheye_mapping".into(), message)
+                    })?;
+                }
+            }
+            CameraModel::Equirectangular(camera) => {
+                assign(&mut camera.stereo, dto.stereo);
+                assign(&mut camera.eye_separation, dto.eye_separation);
+            }
+        }
+        Ok(camera)
+    }
+
+    fn inline_mesh(
+        &self,
+        mesh: MeshDto,
+        material_id: i32,
+        material_count: usize,
+        span: Range<usize>,
+        i: usize,
+    ) -> Result<Mesh> {
+        let (indices_span, indices) = split_list(mesh.indices, &span);
+        let (normals_span, normals) = split_list(mesh.normals, &span);
+        let (uvs_span, uvs) = split_list(mesh.uvs, &span);
+        let (material_ids_span, material_ids) = split_list(mesh.material_ids, &span);
+        let vertex_count = mesh.positions.len();
+        let triangle_count = indices.len();
+        let key = |field: &str| format!("meshes[{i}].{field}");
+
+        if triangle_count == 0 {
+            return self.invalid(
+                indices_span,
+                key("indices"),
+                "mesh needs either `obj` or `positions` and `indices`".into(),
+            );
+        }
+
+        if let Some(index) = indices
+            .iter()
+            .flatten()
+            .find(|&&v| v as usize >= vertex_count)
+        {
+            return self.invalid(
+                indices_span,
+                key("indices"),
+                format!("vertex index {index} is out of range, mesh has {vertex_count} positions"),
+            );
+        }
+
+        for (field, field_span, count) in [
+            ("normals", normals_span, normals.len()),
+            ("uvs", uvs_span, uvs.len()),
+        ] {
+            if count != 0 && count != vertex_count {
+                return self.invalid(
+                    field_span,
+                    key(field),
+                    format!("expected {vertex_count} entries, one per position, got {count}"),
+                );
+            }
+        }
+
+        let material_ids = if material_ids.is_empty() {
+            vec![material_id; triangle_count]
+        } else if mesh.material.is_some() {
+            return self.invalid(
+                material_ids_span,
+                key("material_ids"),
+                "use either `material` or `material_ids`".into(),
+            );
+        } else if material_ids.len() != triangle_count {
+            return self.invalid(
+                material_ids_span,
+                key("material_ids"),
+                format!(
+                    "expected {triangle_count} entries, one per triangle, got {}",
+                    material_ids.len()
+                ),
+            );
+        } else if let Some(id) =
+            (material_ids.iter()).find(|&&id| id < -1 || id >= material_count as i32)
+        {
+            return self.invalid(
+                material_ids_span,
+                key("material_ids"),
+                format!("unknown material {id}, scene has {material_count} materials"),
+            );
+        } else {
+            material_ids
+        };
+
+        Ok(Mesh {
+            positions: mesh.positions,
+            normals,
+            uvs,
+            indices,
+            material_ids,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::renderer::{Aov, FilterKind, SamplerKind, ToneMapper};
+
+    const SCENE: &str = r#"
+version = 1
+
+[render]
+width = 320
+height = 240
+samples_per_pixel = 8
+sampler = "halton"
+filter = "mitchell"
+time_limit = 30.0
+aovs = ["depth", "normal"]
+denoise = true
+
+[integrator]
+bounces = 3
+
+[environment]
+color = [0.5, 0.5, 0.5]
+
+[[materials]]
+name = "light"
+emission_color = [1.0, 0.9, 0.8]
+emissive_power = 4.0
+
+[[materials]]
+type = "dielectric"
+ior = 1.33
+
+[[spheres]]
+position = [0.0, 2.0, 0.0]
+radius = 0.5
+material = "light"
+
+[[meshes]]
+positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0]]
+indices = [[0, 1, 2], [2, 1, 3]]
+material_ids = [1, -1]
+
+[camera]
+type = "thin-lens"
+f_stop = 2.0
+aperture_blades = 6
+
+[display]
+tone_mapper = "agx"
+exposure = -0.5
+"#;
+
+    fn parse(text: &str) -> Result<SceneFile> {
+        SceneFile::from_toml_str(text, Path::new(""))
+    }
+
+    #[test]
+    fn saved_scene_loads_back() {
+        let file = parse(SCENE).unwrap();
+        assert_eq!(
+            [file.width, file.height, file.settings.samples_per_pixel],
+            [320, 240, 8]
+        );
+        assert_eq!(file.settings.bounces, 3);
+        assert_eq!(file.scene.lights, vec![0]);
+        assert_eq!(
+            file.scene.materials[1].material_type,
+            MaterialType::Dielectric
+        );
+
+        let reloaded = parse(&file.to_toml_string().unwrap()).unwrap();
+        let (scene, other) = (&file.scene, &reloaded.scene);
+
+        assert_eq!(reloaded.settings.bounces, 3);
+        assert_eq!(reloaded.settings.thread_count, 0); // every core of the machine loading it
+        assert_eq!(reloaded.settings.sampler, SamplerKind::Halton);
+        assert_eq!(reloaded.settings.filter, Filter::new(FilterKind::Mitchell));
+        assert_eq!(reloaded.settings.time_limit, Some(Duration::from_secs(30)));
+        assert!(reloaded.settings.denoise);
+        assert_eq!(reloaded.settings.display.tone_mapper, ToneMapper::Agx);
+        assert_eq!(reloaded.settings.display.exposure, -0.5);
+        assert_eq!(
+            reloaded.settings.aovs.iter().collect::<Vec<_>>(),
+            [Aov::Depth, Aov::Normal]
+        );
+        assert_eq!(
+            reloaded.camera.pinhole().position,
+            file.camera.pinhole().position
+        );
+        match reloaded.camera {
+            CameraModel::ThinLens(camera) => {
+                assert_eq!(camera.aperture.f_stop, 2.0);
+                assert_eq!(camera.aperture.blades, 6);
+            }
+            _ => panic!("expected a thin lens camera"),
+        }
+        assert_eq!(other.default_sky_color, scene.default_sky_color);
+        assert_eq!(other.materials.len(), 2);
+        assert_eq!(other.materials[0].emission(), scene.materials[0].emission());
+        assert_eq!(other.materials[1].ior, 1.33);
+        assert_eq!(other.spheres[0].material_id, 0);
+        assert_eq!(other.meshes[0].positions, scene.meshes[0].positions);
+        assert_eq!(other.meshes[0].indices, scene.meshes[0].indices);
+        assert_eq!(other.meshes[0].material_ids, vec![1, -1]);
+    }
+
+    #[test]
+    fn errors_point_to_line_and_key() {
+        let unknown_material = SCENE.replace("material = \"light\"", "material = \"lamp\"");
+        match parse(&unknown_material) {
+            Err(Error::InvalidScene {
+                line: Some(line),
+                key,
+                ..
+            }) => {
+                assert_eq!(key, "spheres[0].material");
+                assert_eq!(
+                    unknown_material.lines().nth(line - 1),
+                    Some("material = \"lamp\"")
+                );
+            }
+            _ => panic!("expected an invalid material reference"),
+        }
+
+        let out_of_range = SCENE.replace("[2, 1, 3]", "[2, 1, 4]");
+        match parse(&out_of_range) {
+            Err(Error::InvalidScene {
+                line: Some(line),
+                key,
+                ..
+            }) => {
+                assert_eq!(key, "meshes[0].indices");
+                let line = out_of_range.lines().nth(line - 1).unwrap();
+                assert!(line.starts_with("indices"), "{line}");
+            }
+            _ => panic!("expected an out of range vertex index"),
+        }
+
+        let wrong_type = SCENE.replace("radius = 0.5", "radius = \"big\"");
+        match parse(&wrong_type) {
+            Err(e @ Error::Format { .. }) => {
+                let message = e.to_string();
+                assert!(message.contains("spheres[0].radius"), "{message}");
+                assert!(message.contains("line 31"), "{message}");
+            }
+            _ => panic!("expected a type error"),
+        }
+
+        let newer = SCENE.replace("version = 1", "version = 99");
+        assert!(matches!(
+            parse(&newer),
+            Err(Error::InvalidScene { line: Some(2), .. })
+        ));
+        assert!(parse(&SCENE.replace("bounces", "bounce")).is_err());
+
+        let forever = SCENE.replace("time_limit = 30.0", "time_limit = 1e30");
+        assert!(matches!(
+            parse(&forever),
+            Err(Error::InvalidScene { key, .. }) if key == "render.time_limit"
+        ));
+
+        let pinhole = SCENE.replace("\"thin-lens\"", "\"pinhole\"");
+        assert!(matches!(
+            parse(&pinhole),
+            Err(Error::InvalidScene { key, .. }) if key == "camera.f_stop"
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use glam::Vec2;
+
+/// Shape of the pixel reconstruction filter
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    #[default]
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3, has small negative lobes
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::BlackmanHarris,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Box => "box",
+            Self::Tent => "tent",
+            Self::Gaussian => "gaussian",
+            Self::Mitchell => "mitchell",
+            Self::BlackmanHarris => "blackman-harris",
+        }
+    }
+
+    /// Radius in pixels used when none is given
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+            Self::Mitchell => 2.0,
+            Self::BlackmanHarris => 1.5,
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(FilterKind::as_str).collect();
+                format!("unknown filter '{s}', expected one of {}", names.join(", "))
+            })
+    }
+}
+
+/// Separable reconstruction filter, every sample is splatted onto all
+/// pixels whose centers are within `radius` pixels on both axes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f32,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(FilterKind::default())
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Self {
+        Self {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// Pixels a splat can reach beyond the pixel the sample lies in
+    #[inline]
+    pub(crate) fn margin(&self) -> u32 {
+        (self.radius - 0.5).ceil().max(0.0) as u32
+    }
+
+    /// Weight of a sample at `offset` pixels from a pixel center
+    #[inline]
+    pub(crate) fn evaluate(&self, offset: Vec2) -> f32 {
+        self.evaluate_1d(offset.x) * self.evaluate_1d(offset.y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let r = self.radius;
+        let x = x.abs();
+        if x > r {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                // sigma of a third of the radius, shifted down to reach zero at the radius
+                let sigma = r / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(r)
+            }
+            FilterKind::Mitchell => mitchell_1d(2.0 * x / r),
+            FilterKind::BlackmanHarris => {
+                let t = PI * x / r;
+                0.35875 + 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() + 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
+
+/// Mitchell-Netravali cubic over [0, 2] with B = C = 1/3
+fn mitchell_1d(x: f32) -> f32 {
+    const B: f32 = 1.0 / 3.0;
+    const C: f32 = 1.0 / 3.0;
+
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B)
+    } else {
+        (-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C)
+    };
+    value / 6.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filters_peak_at_center_and_vanish_at_radius() {
+        for kind in FilterKind::ALL {
+            let filter = Filter::new(kind);
+            let r = filter.radius;
+            let center = filter.evaluate(Vec2::ZERO);
+
+            assert!(center > 0.0, "{kind}");
+            assert!(filter.evaluate(Vec2::new(r * 0.5, 0.0)) <= center, "{kind}");
+            assert_eq!(filter.evaluate(Vec2::new(r + 1e-3, 0.0)), 0.0, "{kind}");
+            assert_eq!(filter.evaluate(Vec2::new(0.0, -r - 1e-3)), 0.0, "{kind}");
+            if kind != FilterKind::Box {
+                assert!(filter.evaluate(Vec2::new(r, 0.0)).abs() < 1e-3, "{kind}");
+            }
+            assert_eq!(kind.as_str().parse::<FilterKind>(), Ok(kind));
+        }
+
+        // negative lobe of the Mitchell filter
+        let mitchell = Filter::new(FilterKind::Mitchell);
+        assert!(mitchell.evaluate(Vec2::new(1.5, 0.0)) < 0.0);
+    }
+}
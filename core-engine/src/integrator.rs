@@ -1,14 +1,20 @@
-use glam::{Vec3, Vec4};
+use glam::{Vec2, Vec3, Vec4};
 
 use crate::Ray;
-use crate::cameras::SharedCamera;
+use crate::aov::AovSample;
+use crate::bsdf::{evaluate_bsdf, sample_bsdf};
+use crate::cameras::Camera;
+use crate::file_formats::ExrImage;
+use crate::lights::{power_heuristic, sample_sphere_light, sphere_light_pdf};
 use crate::sampler::Sampler;
-use crate::scene::{Matrial, Scene};
+use crate::scene::{MaterialType, Matrial, PrimitiveId, Scene, Sphere};
 
 static DEFAULT_MATERIAL: Matrial = Matrial {
+    material_type: MaterialType::Opaque,
     albedo: Vec3::ONE,
     roughness: 0.5,
     metalic: 0.0,
+    ior: 1.5,
     emission_color: Vec3::ZERO,
     emissive_power: 0.0,
 };
@@ -17,117 +23,248 @@ static DEFAULT_MATERIAL: Matrial = Matrial {
 pub struct Integrator {
     pub bounces: usize,
     pub max_compulsory_bounces: usize,
+    pub rays_traced: u64, // camera, bounce and shadow rays so far
 }
 
 #[derive(Default, Debug)]
 struct HitPayload {
-    hit_distance: f32,
     world_position: Vec3,
-    world_normal: Vec3,
+    world_normal: Vec3,     // shading normal, faces against the incoming ray
+    geometric_normal: Vec3, // faces against the incoming ray
+    front_face: bool,       // ray arrived from the outside of the surface
 
-    object_index: Option<usize>,
+    material_id: i32,
+    primitive: Option<PrimitiveId>,
 }
 
+/// Sample values used by one bounce. They are drawn together, whether used or not,
+/// so every bounce of every path reads the same sampler dimensions
+struct BounceSamples {
+    light_pick: f32,
+    light: Vec2,
+    lobe: f32,
+    direction: Vec2,
+    roulette: f32,
+}
+
+impl BounceSamples {
+    fn draw(sampler: &mut dyn Sampler) -> Self {
+        Self {
+            light_pick: sampler.next_1d(),
+            light: sampler.next_2d(),
+            lobe: sampler.next_1d(),
+            direction: sampler.next_2d(),
+            roulette: sampler.next_1d(),
+        }
+    }
+}
+
+/// Offset along the geometric normal to avoid self intersection of bounced rays
+const RAY_EPSILON: f32 = 1e-4;
+
 impl Integrator {
+    /// Radiance per RGB channel, with the first hit and the light split by path length
     pub fn compute_incomming_radience(
         &mut self,
         scene: &Scene,
         x: u32,
         y: u32,
-        camera: &SharedCamera,
-        sampler: &mut Sampler,
-    ) -> Vec4 /* returns radiance per RGB channel */ {
-        let cam = camera.read().unwrap();
-        let mut ray = cam.get_ray(x, y);
-        drop(cam);
+        pixel_offset: Vec2, // position of the sample inside the pixel
+        camera: &dyn Camera,
+        sampler: &mut dyn Sampler,
+    ) -> (Vec4, AovSample) {
+        let lens_sample = sampler.next_2d();
+        let Some(mut ray) = camera.get_ray(x, y, pixel_offset, lens_sample) else {
+            return (Vec4::W, AovSample::default()); // outside of what the projection covers
+        };
 
+        let mut aov = AovSample::default();
         let mut light = Vec3::ZERO;
 
         let mut contribution = Vec3::ONE;
+        // previous scattering event, needed to weight emission found by bsdf sampling
+        let mut last_position = ray.origin;
+        let mut last_bsdf_pdf = 0.0;
+        let mut last_was_delta = true; // camera rays can't be light sampled either
+
         for bounce in 0..self.bounces {
             let payload = self.trace_ray(&ray, scene);
+            self.rays_traced += 1;
 
-            if let Some(i) = payload.object_index {
-                let sphere = scene.spheres.get(i).unwrap();
+            if let Some(primitive) = payload.primitive {
+                let material = get_material(scene, payload.material_id);
+                let u = BounceSamples::draw(sampler);
 
-                let material = if sphere.material_id < 0 {
-                    &DEFAULT_MATERIAL
-                } else {
-                    scene
-                        .materials
-                        .get(sphere.material_id as usize)
-                        .unwrap_or(&DEFAULT_MATERIAL)
-                };
+                if bounce == 0 {
+                    aov = AovSample {
+                        hit: true,
+                        depth: payload.world_position.distance(ray.origin),
+                        position: payload.world_position,
+                        normal: payload.world_normal,
+                        albedo: material.albedo,
+                        material_id: payload.material_id,
+                        object_id: object_id(scene, primitive),
+                        ..aov
+                    };
+                }
+
+                if material.is_emissive() {
+                    let weight = match primitive {
+                        PrimitiveId::Sphere(i) if !last_was_delta && scene.is_light(i) => {
+                            let light_pdf = self.light_pick_probability(scene)
+                                * sphere_light_pdf(&scene.spheres[i], last_position);
+                            power_heuristic(last_bsdf_pdf, light_pdf)
+                        }
+                        _ => 1.0,
+                    };
+                    let emitted = material.emission() * contribution * weight;
+                    aov.add_light(bounce, emitted);
+                    light += emitted;
+                }
 
-                light += material.emission_color * material.emissive_power * contribution;
+                let wo = -ray.direction;
+                // the last bounce has no bsdf sample to share the light with
+                let has_next_bounce = bounce + 1 < self.bounces;
+                if has_next_bounce && material.material_type != MaterialType::Dielectric {
+                    let direct =
+                        contribution * self.sample_direct_light(scene, &payload, material, wo, &u);
+                    aov.add_light(bounce + 1, direct);
+                    light += direct;
+                }
 
-                let normal = payload.world_normal;
-                let wi = sampler.sample_hemisphere_cosine_weighted(normal);
-                let cos_theta = wi.dot(normal).max(0.0);
-                let pdf = cos_theta / std::f32::consts::PI;
-                let brdf = material.albedo / std::f32::consts::PI;
+                let Some(sample) = sample_bsdf(
+                    material,
+                    payload.world_normal,
+                    wo,
+                    payload.front_face,
+                    u.lobe,
+                    u.direction,
+                ) else {
+                    break;
+                };
 
-                contribution *= brdf * cos_theta / pdf;
+                contribution *= sample.weight;
+                last_position = payload.world_position;
+                last_bsdf_pdf = sample.pdf;
+                last_was_delta = sample.is_delta;
 
                 if bounce >= self.max_compulsory_bounces {
                     let p = contribution.x.max(contribution.y.max(contribution.z));
-                    if sampler.next_f32() > p {
+                    if u.roulette > p {
                         break;
                     }
                     contribution /= p;
                 }
 
-                ray.origin = payload.world_position + payload.world_normal * f32::EPSILON;
-                ray.direction = wi;
+                ray.origin = offset_ray_origin(&payload, sample.direction);
+                ray.direction = sample.direction;
             } else {
                 // sky box, or something
                 let sky_color = match &scene.skybox {
                     Some(exr) => exr.sample(ray.direction),
                     None => scene.default_sky_color,
                 };
-                light += sky_color * contribution;
+                let weight = match sampled_environment(scene) {
+                    Some(env) if !last_was_delta => {
+                        let light_pdf = self.light_pick_probability(scene) * env.pdf(ray.direction);
+                        power_heuristic(last_bsdf_pdf, light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                let sky = sky_color * contribution * weight;
+                aov.add_light(bounce, sky);
+                light += sky;
                 break;
             }
         }
-        Vec4::from((light, 1.0))
+        (Vec4::from((light, 1.0)), aov)
     }
 
-    fn trace_ray(&self, ray: &Ray, scene: &Scene) -> HitPayload {
-        // (bx^2 + by^2 + bz^2)t^2 + 2(axbx + ayby + azbz)t + (ax^2 + ay^2 + az^2 - r^2)
-        // a vec ray origin
-        // b vec ray direction
-        // r radius
-        // t hit distance
+    #[inline]
+    fn light_pick_probability(&self, scene: &Scene) -> f32 {
+        let env_count = sampled_environment(scene).is_some() as usize;
+        1.0 / (scene.lights.len() + env_count).max(1) as f32
+    }
 
-        let mut hit_distance = f32::MAX;
-        // let mut closest_sphere : Option<&Sphere> = None;
-        let mut closest_sphere: Option<usize> = None;
+    /// Next event estimation, picks one emissive sphere or the environment uniformly,
+    /// spheres sample their cone of directions, environment samples by luminance
+    fn sample_direct_light(
+        &mut self,
+        scene: &Scene,
+        payload: &HitPayload,
+        material: &Matrial,
+        wo: Vec3,
+        u: &BounceSamples,
+    ) -> Vec3 {
+        let environment = sampled_environment(scene);
+        let light_count = scene.lights.len() + environment.is_some() as usize;
+        if light_count == 0 {
+            return Vec3::ZERO;
+        }
 
-        for (i, sphere) in scene.spheres.iter().enumerate() {
-            let origin = ray.origin - sphere.position;
+        let pick = ((u.light_pick * light_count as f32) as usize).min(light_count - 1);
 
-            let a = ray.direction.dot(ray.direction);
-            let b = 2.0 * ray.direction.dot(origin);
-            let c = origin.dot(origin) - sphere.radius * sphere.radius;
+        // direction, solid angle pdf, primitive the shadow ray must hit and its radiance
+        let (wi, pdf, target, radiance) = if let Some(&light_index) = scene.lights.get(pick) {
+            let sphere = &scene.spheres[light_index];
+            let Some(light_sample) = sample_sphere_light(sphere, payload.world_position, u.light)
+            else {
+                return Vec3::ZERO;
+            };
+            let emission = get_material(scene, sphere.material_id).emission();
+            (
+                light_sample.direction,
+                light_sample.pdf,
+                Some(PrimitiveId::Sphere(light_index)),
+                emission,
+            )
+        } else if let Some(env) = environment {
+            let Some((direction, pdf)) = env.sample_direction(u.light) else {
+                return Vec3::ZERO;
+            };
+            (direction, pdf, None, env.sample(direction))
+        } else {
+            return Vec3::ZERO;
+        };
 
-            let discriminant = b * b - 4.0 * a * c;
+        if wi.dot(payload.geometric_normal) <= 0.0 {
+            return Vec3::ZERO;
+        }
 
-            if discriminant < 0.0 {
-                continue;
-            }
+        let (bsdf_cos, bsdf_pdf) = evaluate_bsdf(material, payload.world_normal, wo, wi);
+        if bsdf_cos.max_element() <= 0.0 {
+            return Vec3::ZERO;
+        }
 
-            let sqrt_d = discriminant.sqrt();
+        // shadow ray, the light itself must be the closest hit (or nothing for the environment)
+        let shadow_ray = Ray {
+            origin: offset_ray_origin(payload, wi),
+            direction: wi,
+        };
+        self.rays_traced += 1;
+        let closest = scene
+            .bvh
+            .intersect(&shadow_ray, f32::MAX, |id| {
+                intersect_primitive(scene, &shadow_ray, id)
+            })
+            .map(|(_, id, _)| id);
+        if closest != target {
+            return Vec3::ZERO;
+        }
 
-            let closest_t = (-b - sqrt_d) / (2.0 * a);
+        let light_pdf = self.light_pick_probability(scene) * pdf;
+        let weight = power_heuristic(light_pdf, bsdf_pdf);
 
-            if closest_t > 0.0 && closest_t < hit_distance {
-                closest_sphere = Some(i);
-                hit_distance = closest_t;
-            }
-        }
+        radiance * bsdf_cos * weight / light_pdf
+    }
 
-        if let Some(sphere_index) = closest_sphere {
-            self.closest_hit(scene, ray, hit_distance, sphere_index)
+    fn trace_ray(&self, ray: &Ray, scene: &Scene) -> HitPayload {
+        let closest = scene
+            .bvh
+            .intersect(ray, f32::MAX, |id| intersect_primitive(scene, ray, id));
+
+        if let Some((hit_distance, primitive, barycentric)) = closest {
+            self.closest_hit(scene, ray, hit_distance, primitive, barycentric)
         } else {
             self.ray_miss(ray)
         }
@@ -138,28 +275,429 @@ impl Integrator {
         scene: &Scene,
         ray: &Ray,
         hit_distance: f32,
-        object_index: usize,
+        primitive: PrimitiveId,
+        barycentric: Vec2,
     ) -> HitPayload {
-        let closest_sphere = scene.spheres.get(object_index).unwrap();
+        let world_position = ray.origin + hit_distance * ray.direction;
 
-        let origin = ray.origin - closest_sphere.position;
-        let hit_point = origin + hit_distance * ray.direction;
+        let (mut geometric_normal, mut shading_normal, material_id) = match primitive {
+            PrimitiveId::Sphere(i) => {
+                let sphere = &scene.spheres[i];
+                let normal = (world_position - sphere.position).normalize();
+                (normal, normal, sphere.material_id)
+            }
+            PrimitiveId::Triangle { mesh, triangle } => {
+                let mesh = &scene.meshes[mesh];
+                let [p0, p1, p2] = mesh.triangle_vertices(triangle);
+                let geometric = (p1 - p0).cross(p2 - p0).normalize();
+                let shading = mesh.shading_normal(triangle, barycentric.x, barycentric.y);
+                (geometric, shading, mesh.material_id(triangle))
+            }
+        };
 
-        let normal = hit_point.normalize();
+        // outward normals (sphere) or winding order (triangle) define the outside,
+        // shading normals always face the incoming ray
+        let front_face = geometric_normal.dot(ray.direction) <= 0.0;
+        if !front_face {
+            geometric_normal = -geometric_normal;
+        }
+        if shading_normal.dot(geometric_normal) < 0.0 {
+            shading_normal = -shading_normal;
+        }
 
         HitPayload {
-            hit_distance: hit_distance,
-            world_position: hit_point + closest_sphere.position,
-            world_normal: normal,
-            object_index: Some(object_index),
+            world_position,
+            world_normal: shading_normal,
+            geometric_normal,
+            front_face,
+            material_id,
+            primitive: Some(primitive),
         }
     }
 
     fn ray_miss(&self, _ray: &Ray) -> HitPayload {
         HitPayload {
-            hit_distance: -1.0,
-            object_index: None,
+            primitive: None,
             ..Default::default()
         }
     }
 }
+
+/// Skybox if it can be light sampled
+#[inline]
+fn sampled_environment(scene: &Scene) -> Option<&ExrImage> {
+    scene
+        .skybox
+        .as_ref()
+        .filter(|skybox| skybox.can_importance_sample())
+}
+
+/// Spheres come first, then the meshes
+fn object_id(scene: &Scene, primitive: PrimitiveId) -> i32 {
+    match primitive {
+        PrimitiveId::Sphere(i) => i as i32,
+        PrimitiveId::Triangle { mesh, .. } => (scene.spheres.len() + mesh) as i32,
+    }
+}
+
+fn get_material(scene: &Scene, material_id: i32) -> &Matrial {
+    if material_id < 0 {
+        &DEFAULT_MATERIAL
+    } else {
+        scene
+            .materials
+            .get(material_id as usize)
+            .unwrap_or(&DEFAULT_MATERIAL)
+    }
+}
+
+/// Distance to the closest surface along the ray
+pub(crate) fn closest_hit_distance(scene: &Scene, ray: &Ray) -> Option<f32> {
+    scene
+        .bvh
+        .intersect(ray, f32::MAX, |id| intersect_primitive(scene, ray, id))
+        .map(|(hit_distance, ..)| hit_distance)
+}
+
+/// Returns hit distance and barycentrics (zero for spheres)
+fn intersect_primitive(scene: &Scene, ray: &Ray, id: PrimitiveId) -> Option<(f32, Vec2)> {
+    match id {
+        PrimitiveId::Sphere(i) => intersect_sphere(ray, &scene.spheres[i]).map(|t| (t, Vec2::ZERO)),
+        PrimitiveId::Triangle { mesh, triangle } => {
+            intersect_triangle(ray, scene.meshes[mesh].triangle_vertices(triangle))
+        }
+    }
+}
+
+/// Moves the origin off the surface to the side the new direction is leaving into
+fn offset_ray_origin(payload: &HitPayload, direction: Vec3) -> Vec3 {
+    let scale = RAY_EPSILON * payload.world_position.abs().max_element().max(1.0);
+    if direction.dot(payload.geometric_normal) >= 0.0 {
+        payload.world_position + payload.geometric_normal * scale
+    } else {
+        payload.world_position - payload.geometric_normal * scale
+    }
+}
+
+/// Returns distance to the nearest intersection in front of the ray,
+/// which is the far side when the ray starts inside the sphere
+fn intersect_sphere(ray: &Ray, sphere: &Sphere) -> Option<f32> {
+    // (bx^2 + by^2 + bz^2)t^2 + 2(axbx + ayby + azbz)t + (ax^2 + ay^2 + az^2 - r^2)
+    // a vec ray origin
+    // b vec ray direction
+    // r radius
+    // t hit distance
+    let origin = ray.origin - sphere.position;
+
+    let a = ray.direction.dot(ray.direction);
+    let b = 2.0 * ray.direction.dot(origin);
+    let c = origin.dot(origin) - sphere.radius * sphere.radius;
+
+    let discriminant = b * b - 4.0 * a * c;
+
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let sqrt_d = discriminant.sqrt();
+
+    let closest_t = (-b - sqrt_d) / (2.0 * a);
+    if closest_t > 0.0 {
+        return Some(closest_t);
+    }
+
+    // origin is inside the sphere
+    let far_t = (-b + sqrt_d) / (2.0 * a);
+    (far_t > 0.0).then_some(far_t)
+}
+
+/// Watertight ray triangle intersection (Woop, Benthin and Wald 2013).
+/// Returns hit distance and barycentrics (u, v) of second and third vertex
+fn intersect_triangle(ray: &Ray, vertices: [Vec3; 3]) -> Option<(f32, Vec2)> {
+    let dir = ray.direction;
+
+    // permute axes so that z is the dominant direction
+    let kz = dir.abs().max_position();
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if dir[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    // shear constants
+    let sx = dir[kx] / dir[kz];
+    let sy = dir[ky] / dir[kz];
+    let sz = 1.0 / dir[kz];
+
+    let [a, b, c] = vertices.map(|v| v - ray.origin);
+
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+
+    // edges passing exactly through the ray are resolved in double precision
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+        v = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+        w = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+    }
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t_scaled = u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz];
+    let t = t_scaled / det;
+
+    if t <= 0.0 || !t.is_finite() {
+        return None;
+    }
+
+    Some((t, Vec2::new(v / det, w / det)))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::sampler::SobolSampler;
+    use crate::scene::Mesh;
+
+    fn closest_brute_force(scene: &Scene, ray: &Ray) -> Option<(f32, PrimitiveId)> {
+        scene
+            .primitive_ids()
+            .filter_map(|id| intersect_primitive(scene, ray, id).map(|(t, _)| (t, id)))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let random_vec3 = |rng: &mut StdRng, extent: f32| {
+            Vec3::new(
+                rng.random_range(-extent..extent),
+                rng.random_range(-extent..extent),
+                rng.random_range(-extent..extent),
+            )
+        };
+
+        let mut scene = Scene::default();
+        for _ in 0..200 {
+            scene.spheres.push(Sphere {
+                position: random_vec3(&mut rng, 10.0),
+                radius: rng.random_range(0.05..1.0),
+                material_id: -1,
+            });
+        }
+
+        let mut mesh = Mesh::default();
+        for i in 0..500 {
+            let center = random_vec3(&mut rng, 10.0);
+            for _ in 0..3 {
+                mesh.positions.push(center + random_vec3(&mut rng, 0.75));
+            }
+            mesh.indices.push([3 * i, 3 * i + 1, 3 * i + 2]);
+            mesh.material_ids.push(-1);
+        }
+        scene.meshes.push(mesh);
+        scene.update_acceleration_structure();
+
+        let check_rays = |scene: &Scene, rng: &mut StdRng| {
+            for _ in 0..2000 {
+                let ray = Ray {
+                    origin: random_vec3(rng, 12.0),
+                    direction: random_vec3(rng, 1.0).normalize(),
+                };
+
+                let expected = closest_brute_force(scene, &ray);
+                let actual = scene
+                    .bvh
+                    .intersect(&ray, f32::MAX, |id| intersect_primitive(scene, &ray, id))
+                    .map(|(t, id, _)| (t, id));
+
+                assert_eq!(
+                    expected, actual,
+                    "mismatch for {:?} {:?}",
+                    ray.origin, ray.direction
+                );
+            }
+        };
+
+        check_rays(&scene, &mut rng);
+
+        // moving geometry only refits
+        for sphere in scene.spheres.iter_mut() {
+            sphere.position += Vec3::new(0.5, -0.25, 1.0);
+        }
+        scene.update_acceleration_structure();
+        check_rays(&scene, &mut rng);
+    }
+
+    #[test]
+    fn triangle_hit_and_barycentrics() {
+        let vertices = [
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+        ];
+        let ray = Ray {
+            origin: Vec3::new(0.25, 0.5, 0.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+
+        let (t, barycentric) = intersect_triangle(&ray, vertices).unwrap();
+        assert!((t - 1.0).abs() < 1e-6);
+        assert!(barycentric.abs_diff_eq(Vec2::new(0.25, 0.5), 1e-6));
+
+        // behind the origin
+        let ray = Ray {
+            origin: Vec3::new(0.25, 0.5, -2.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        assert!(intersect_triangle(&ray, vertices).is_none());
+    }
+
+    #[test]
+    fn shared_edge_is_watertight() {
+        // two triangles of a quad, rays through the diagonal must hit at least one
+        let quad = [
+            [
+                Vec3::new(-1.0, -1.0, 0.0),
+                Vec3::new(1.0, -1.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+            ],
+            [
+                Vec3::new(-1.0, -1.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(-1.0, 1.0, 0.0),
+            ],
+        ];
+
+        for i in 0..=100 {
+            let s = i as f32 / 50.0 - 1.0;
+            let ray = Ray {
+                origin: Vec3::new(s, s, 1.0),
+                direction: Vec3::new(0.0, 0.0, -1.0),
+            };
+            assert!(
+                quad.iter()
+                    .any(|tri| intersect_triangle(&ray, *tri).is_some()),
+                "ray through diagonal at {s} missed both triangles"
+            );
+        }
+    }
+
+    /// Average radiance through a camera looking down at the origin
+    fn estimate_radiance(scene: &Scene, count: u32) -> Vec3 {
+        use crate::cameras::PinholeCamera;
+
+        let camera = PinholeCamera::new(
+            Vec3::new(0.0, 1.0, 3.0),
+            Vec3::new(-0.4, 0.0, 0.0),
+            35.0,
+            35.0,
+            [1, 1],
+        );
+        let mut integrator = Integrator {
+            bounces: 3,
+            max_compulsory_bounces: 3,
+            rays_traced: 0,
+        };
+        let mut sampler = SobolSampler::new(0);
+
+        let mut sum = Vec4::ZERO;
+        for i in 0..count {
+            sampler.start_pixel_sample(0, 0, i);
+            let offset = sampler.next_2d();
+            let (radiance, _) =
+                integrator.compute_incomming_radience(scene, 0, 0, offset, &camera, &mut sampler);
+            sum += radiance;
+        }
+        sum.truncate() / count as f32
+    }
+
+    fn ground_scene() -> Scene {
+        let mut scene = Scene::default();
+        scene.materials.push(Matrial {
+            albedo: Vec3::splat(0.8),
+            roughness: 0.4,
+            metalic: 0.3,
+            ..Default::default()
+        });
+        scene.spheres.push(Sphere {
+            position: Vec3::new(0.0, -100.0, 0.0),
+            radius: 100.0,
+            material_id: 0,
+        });
+        scene
+    }
+
+    #[test]
+    fn light_sampling_matches_bsdf_sampling() {
+        let mut scene = ground_scene();
+        scene.materials.push(Matrial {
+            emission_color: Vec3::ONE,
+            emissive_power: 4.0,
+            ..Default::default()
+        });
+        scene.spheres.push(Sphere {
+            position: Vec3::new(0.5, 1.5, 0.0),
+            radius: 0.6,
+            material_id: 1,
+        });
+        scene.update_acceleration_structure();
+
+        let bsdf_only = estimate_radiance(&scene, 40_000);
+        scene.update_light_list();
+        let with_light_sampling = estimate_radiance(&scene, 40_000);
+
+        assert!(bsdf_only.max_element() > 0.05);
+        assert!(
+            with_light_sampling.abs_diff_eq(bsdf_only, 0.05 * bsdf_only.max_element()),
+            "mis {with_light_sampling} bsdf only {bsdf_only}"
+        );
+    }
+
+    #[test]
+    fn environment_sampling_matches_bsdf_sampling() {
+        let mut scene = ground_scene();
+
+        // dim sky with a large bright patch
+        let (width, height) = (32, 16);
+        let mut skybox = ExrImage {
+            pixels_buffer: vec![Vec3::splat(0.2); width * height],
+            width,
+            height,
+            ..Default::default()
+        };
+        for y in 3..6 {
+            for x in 10..16 {
+                skybox.pixels_buffer[y * width + x] = Vec3::new(20.0, 15.0, 10.0);
+            }
+        }
+        scene.skybox = Some(skybox);
+        scene.update_acceleration_structure();
+
+        let bsdf_only = estimate_radiance(&scene, 100_000);
+        scene.skybox.as_mut().unwrap().build_distribution();
+        let with_env_sampling = estimate_radiance(&scene, 100_000);
+
+        assert!(
+            with_env_sampling.abs_diff_eq(bsdf_only, 0.05 * bsdf_only.max_element()),
+            "mis {with_env_sampling} bsdf only {bsdf_only}"
+        );
+    }
+}
@@ -1,16 +1,30 @@
+pub(crate) mod accelerators;
 pub(crate) mod accumulators;
+pub(crate) mod aov;
+pub(crate) mod bsdf;
 pub(crate) mod concurrency;
+pub(crate) mod denoise;
+pub(crate) mod distributions;
+pub(crate) mod filters;
 pub(crate) mod integrator;
+pub(crate) mod lights;
 pub(crate) mod ray;
 pub(crate) mod sampler;
+pub(crate) mod tiles;
+pub(crate) mod tonemap;
 pub(crate) mod utils;
 
-pub mod file_formats;
 pub mod cameras;
+pub mod distributed;
+pub mod error;
+pub mod file_formats;
 pub mod renderer;
 pub mod scene;
+pub mod session;
 
 use ray::Ray;
 
+pub use error::{Error, Result};
+
 pub use glam::Vec2;
 pub use glam::Vec3;
@@ -0,0 +1,93 @@
+use std::f32::consts::PI;
+
+use glam::{Vec2, Vec3};
+
+use crate::bsdf::ShadingFrame;
+use crate::scene::Sphere;
+
+pub(crate) struct LightSample {
+    pub direction: Vec3,
+    pub pdf: f32, // solid angle pdf, without light selection probability
+}
+
+/// Returns `1 - cos(theta_max)` of the cone subtended by the sphere,
+/// or None when `point` is inside it
+#[inline]
+fn cone_one_minus_cos(sphere: &Sphere, point: Vec3) -> Option<f32> {
+    let dist_sq = (sphere.position - point).length_squared();
+    let radius_sq = sphere.radius * sphere.radius;
+    if dist_sq <= radius_sq {
+        return None;
+    }
+
+    let sin2_theta_max = radius_sq / dist_sq;
+    let cos_theta_max = (1.0 - sin2_theta_max).max(0.0).sqrt();
+    // avoids cancellation for tiny or far away spheres
+    Some(sin2_theta_max / (1.0 + cos_theta_max))
+}
+
+/// Uniformly samples the cone of directions from `point` toward the visible cap of the sphere
+pub(crate) fn sample_sphere_light(sphere: &Sphere, point: Vec3, u: Vec2) -> Option<LightSample> {
+    let one_minus_cos_max = cone_one_minus_cos(sphere, point)?;
+
+    let one_minus_cos = u.x * one_minus_cos_max;
+    let cos_theta = 1.0 - one_minus_cos;
+    let sin_theta = (one_minus_cos * (2.0 - one_minus_cos)).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+
+    let frame = ShadingFrame::from_normal((sphere.position - point).normalize());
+    let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+
+    Some(LightSample {
+        direction: frame.to_world(local),
+        pdf: 1.0 / (2.0 * PI * one_minus_cos_max),
+    })
+}
+
+/// Solid angle pdf of `sample_sphere_light` for any direction inside the cone
+pub(crate) fn sphere_light_pdf(sphere: &Sphere, point: Vec3) -> f32 {
+    match cone_one_minus_cos(sphere, point) {
+        Some(one_minus_cos_max) => 1.0 / (2.0 * PI * one_minus_cos_max),
+        None => 0.0,
+    }
+}
+
+/// Power heuristic with beta = 2 for combining two sampling strategies
+#[inline]
+pub(crate) fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b <= 0.0 { 0.0 } else { a / (a + b) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sphere_samples_stay_inside_cone() {
+        let sphere = Sphere {
+            position: Vec3::new(1.0, 4.0, -2.0),
+            radius: 0.75,
+            material_id: 0,
+        };
+        let point = Vec3::new(0.2, -0.3, 0.5);
+        let to_center = sphere.position - point;
+        let cos_theta_max = (1.0 - sphere.radius.powi(2) / to_center.length_squared()).sqrt();
+
+        for i in 0..16 {
+            for j in 0..16 {
+                let u = Vec2::new(i as f32 / 16.0, j as f32 / 16.0);
+                let sample = sample_sphere_light(&sphere, point, u).unwrap();
+
+                assert!((sample.direction.length() - 1.0).abs() < 1e-5);
+                assert!(sample.direction.dot(to_center.normalize()) >= cos_theta_max - 1e-5);
+                assert_eq!(sample.pdf, sphere_light_pdf(&sphere, point));
+            }
+        }
+
+        // no sampling from inside the light
+        assert!(sample_sphere_light(&sphere, sphere.position, Vec2::ZERO).is_none());
+        assert_eq!(sphere_light_pdf(&sphere, sphere.position), 0.0);
+    }
+}
@@ -1,14 +1,193 @@
-use std::sync::{Arc, RwLock};
+use std::fmt;
+use std::hash::Hasher;
+use std::num::NonZero;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use crossbeam::channel::Receiver;
-use glam::Vec3;
+use glam::{Vec2, Vec3, Vec4};
 
 use crate::accumulators::{Accumulator, TileAccumulator};
 use crate::cameras::{PinholeCamera, SharedCamera};
-use crate::concurrency::Threadpool;
-use crate::integrator::Integrator;
+use crate::concurrency::{RenderJobResult, Threadpool};
+use crate::denoise;
+use crate::file_formats::checkpoint::Checkpoint;
+use crate::file_formats::{ExrImage, save_layered_exr, save_png_image};
+use crate::integrator::{Integrator, closest_hit_distance};
+use crate::sampler::create_sampler;
 use crate::scene::Scene;
+use crate::tiles::{Tile, tile_grid};
+use crate::utils::{StableHasher, flip_rows};
+use crate::{Error, Result};
+
+pub use crate::aov::{Aov, AovSet};
+pub use crate::filters::{Filter, FilterKind};
+pub use crate::sampler::SamplerKind;
+pub use crate::tiles::TileOrder;
+pub use crate::tonemap::{DisplayTransform, ToneMapper};
+
+/// Engine side knobs of a render, image size is chosen per call.
+///
+/// The setters of `RayTracer` apply from the next pass. Those deciding which samples are
+/// taken and how they are weighted need a render that doesn't accumulate afterwards,
+/// or the image mixes passes of both settings
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub thread_count: usize, // workers, 0 uses every core, fixed when the renderer is created
+    pub bounces: usize,
+    pub max_compulsory_bounces: usize, // bounces before russian roulette kicks in
+    pub seed: u32,                     // same seed and scene give a bit identical image
+    pub sampler: SamplerKind,
+    pub samples_per_pixel: u32, // expected count, sizes the strata of the stratified sampler
+    pub filter: Filter,
+    pub noise_threshold: f32, // tiles below it stop taking samples, 0 samples every pass everywhere
+    pub min_samples: u32,     // passes every tile takes before it may stop
+    pub time_limit: Option<Duration>, // time spent in passes, no pass starts that wouldn't fit
+    pub tile_size: u32,       // pixels along the side of a job
+    pub tile_order: TileOrder,
+    pub aovs: AovSet,  // passes recorded next to the beauty image
+    pub denoise: bool, // output is filtered, guided by the albedo, normal and depth of the first hits
+    pub display: DisplayTransform,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            thread_count: 0,
+            bounces: 5,
+            max_compulsory_bounces: 2,
+            seed: 0,
+            sampler: SamplerKind::default(),
+            samples_per_pixel: 64,
+            filter: Filter::default(),
+            noise_threshold: 0.0,
+            min_samples: 16,
+            time_limit: None,
+            tile_size: 64,
+            tile_order: TileOrder::default(),
+            aovs: AovSet::default(),
+            denoise: false,
+            display: DisplayTransform::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Threads `thread_count` stands for on this machine
+    pub fn worker_count(&self) -> usize {
+        match self.thread_count {
+            0 => thread::available_parallelism().map_or(4, NonZero::get),
+            threads => threads,
+        }
+    }
+
+    /// The selected AOVs and the guides of the denoiser when it is on
+    pub(crate) fn recorded_aovs(&self) -> AovSet {
+        let mut aovs = self.aovs;
+        if self.denoise {
+            denoise::GUIDES.into_iter().for_each(|aov| aovs.insert(aov));
+        }
+        aovs
+    }
+}
+
+/// Condition that ended a render, whichever came first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    SampleCount,
+    TimeLimit,
+    NoiseThreshold, // every tile converged
+    Cancelled,
+    Failed, // a pass returned an error, the session keeps it for `take_error`
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::SampleCount => "reached the samples per pixel",
+            Self::TimeLimit => "reached the time limit",
+            Self::NoiseThreshold => "every tile reached the noise threshold",
+            Self::Cancelled => "cancelled",
+            Self::Failed => "failed",
+        })
+    }
+}
+
+/// Samples one tile for one pass. The local workers and remote ones run the same jobs,
+/// which is what makes their images identical
+#[derive(Clone)]
+pub(crate) struct TileJob {
+    settings: RenderSettings,
+    camera: SharedCamera,
+    record_aovs: bool,
+}
+
+impl TileJob {
+    /// Gives up on the rest of the tile once `abort` is set, the pass gets thrown away
+    pub fn render(
+        &self,
+        scene: &Scene,
+        tile: Tile,
+        sample_index: u32,
+        abort: &AtomicBool,
+    ) -> TileAccumulator {
+        let settings = &self.settings;
+        let mut integrator = Integrator {
+            bounces: settings.bounces,
+            max_compulsory_bounces: settings.max_compulsory_bounces,
+            rays_traced: 0,
+        };
+        let mut sampler =
+            create_sampler(settings.sampler, settings.seed, settings.samples_per_pixel);
+        let mut accumulator = self.tile_accumulator(tile);
+        // once per tile, a camera moved meanwhile needs a restart anyway
+        let camera = self.camera.read().unwrap_or_else(PoisonError::into_inner);
+
+        for dy in 0..tile.height {
+            if abort.load(Ordering::Relaxed) {
+                break;
+            }
+            for dx in 0..tile.width {
+                let x = tile.x + dx;
+                let y = tile.y + dy;
+
+                sampler.start_pixel_sample(x, y, sample_index);
+                let film_sample = sampler.next_2d();
+                let (color, aov) = integrator.compute_incomming_radience(
+                    scene,
+                    x,
+                    y,
+                    film_sample,
+                    &*camera,
+                    sampler.as_mut(),
+                );
+
+                let position = Vec2::new(x as f32, y as f32) + film_sample;
+                accumulator.add_sample(position, color);
+                if self.record_aovs {
+                    accumulator.add_aov_sample(x, y, aov);
+                }
+            }
+        }
+
+        accumulator.rays_traced = integrator.rays_traced;
+        accumulator
+    }
+
+    /// Empty accumulator for the samples of `tile`
+    pub fn tile_accumulator(&self, tile: Tile) -> TileAccumulator {
+        TileAccumulator::new(
+            tile.x,
+            tile.y,
+            tile.width,
+            tile.height,
+            self.settings.filter,
+            self.record_aovs,
+        )
+    }
+}
 
 pub struct RayTracer {
     width: u32,
@@ -18,15 +197,23 @@ pub struct RayTracer {
 
     pub active_camera: SharedCamera,
     // pub scene : Arc<Scene>
-    integrator: Integrator,
+    settings: RenderSettings,
+    sample_index: u32,   // samples per pixel accumulated so far
+    elapsed: Duration,   // spent in the passes accumulated so far
+    active_tiles: usize, // tiles that took samples in the last pass
     accumulator: Arc<RwLock<Accumulator>>,
+    abort: Arc<AtomicBool>, // set while tiles in flight should stop early
     threadpool: Option<Threadpool>,
-    threadpool_result_rx: Option<Receiver<TileAccumulator>>,
+    threadpool_result_rx: Option<Receiver<RenderJobResult>>,
     // merger_thread: Option<JoinHandle<()>>,
 }
 
 impl RayTracer {
     pub fn new(width: u32, height: u32) -> Self {
+        Self::with_settings(width, height, RenderSettings::default())
+    }
+
+    pub fn with_settings(width: u32, height: u32, settings: RenderSettings) -> Self {
         let camera = PinholeCamera::new(
             Vec3::new(0.0, 0.0, 2.0),
             Vec3::ZERO,
@@ -35,14 +222,10 @@ impl RayTracer {
             [width, height],
         );
 
-        let integrator = Integrator {
-            bounces: 5,
-            max_compulsory_bounces: 2,
-        };
-        let accumulator = Accumulator::new(width, height);
+        let accumulator = Accumulator::new(width, height, settings.recorded_aovs());
         let shared_acc = Arc::new(RwLock::new(accumulator));
 
-        let (tp, result_rx) = Threadpool::new(4);
+        let (tp, result_rx) = Threadpool::new(settings.worker_count());
 
         Self {
             width: 0,
@@ -51,13 +234,178 @@ impl RayTracer {
             active_camera: Arc::new(RwLock::new(camera)),
             last_render_time: Duration::from_secs(0),
             accumulator: shared_acc,
+            abort: Arc::new(AtomicBool::new(false)),
 
-            integrator,
+            settings,
+            sample_index: 0,
+            elapsed: Duration::ZERO,
+            active_tiles: 0,
             threadpool: Some(tp),
             threadpool_result_rx: Some(result_rx),
         }
     }
 
+    /// Path length at which the integrator stops, russian roulette may end paths earlier
+    pub fn set_bounces(&mut self, bounces: usize) {
+        self.settings.bounces = bounces;
+    }
+
+    /// Equal seeds give bit identical images of the same scene
+    pub fn set_seed(&mut self, seed: u32) {
+        self.settings.seed = seed;
+    }
+
+    /// Pattern the pixel, lens and bounce dimensions are sampled with
+    pub fn set_sampler(&mut self, sampler: SamplerKind) {
+        self.settings.sampler = sampler;
+    }
+
+    /// Reconstruction filter the samples are splatted with
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.settings.filter = filter;
+    }
+
+    /// Passes a render takes at most
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) {
+        self.settings.samples_per_pixel = samples_per_pixel;
+    }
+
+    /// Pixels along the side of a tile job, the image doesn't depend on it
+    pub fn set_tile_size(&mut self, tile_size: u32) {
+        self.settings.tile_size = tile_size.max(1);
+    }
+
+    /// Order tiles are handed to the workers in, the image doesn't depend on it
+    pub fn set_tile_order(&mut self, tile_order: TileOrder) {
+        self.settings.tile_order = tile_order;
+    }
+
+    /// `None` renders without a time limit
+    pub fn set_time_limit(&mut self, time_limit: Option<Duration>) {
+        self.settings.time_limit = time_limit;
+    }
+
+    /// Zero turns adaptive sampling off
+    pub fn set_noise_threshold(&mut self, noise_threshold: f32) {
+        self.settings.noise_threshold = noise_threshold;
+    }
+
+    /// Takes effect from the next render that doesn't accumulate
+    pub fn set_aovs(&mut self, aovs: AovSet) {
+        self.settings.aovs = aovs;
+    }
+
+    /// Guides are recorded from the next render that doesn't accumulate,
+    /// until then the output stays noisy
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.settings.denoise = denoise;
+    }
+
+    /// Applies to the next read of the output, the render carries on
+    pub fn set_display_transform(&mut self, display: DisplayTransform) {
+        self.settings.display = display;
+    }
+
+    pub fn get_settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    pub fn get_bounces(&self) -> usize {
+        self.settings.bounces
+    }
+
+    pub fn get_sampler(&self) -> SamplerKind {
+        self.settings.sampler
+    }
+
+    pub fn get_samples_per_pixel(&self) -> u32 {
+        self.settings.samples_per_pixel
+    }
+
+    pub fn get_filter(&self) -> Filter {
+        self.settings.filter
+    }
+
+    pub fn get_noise_threshold(&self) -> f32 {
+        self.settings.noise_threshold
+    }
+
+    pub fn get_tile_size(&self) -> u32 {
+        self.settings.tile_size
+    }
+
+    pub fn get_tile_order(&self) -> TileOrder {
+        self.settings.tile_order
+    }
+
+    pub fn get_time_limit(&self) -> Option<Duration> {
+        self.settings.time_limit
+    }
+
+    pub fn get_aovs(&self) -> AovSet {
+        self.settings.aovs
+    }
+
+    pub fn get_denoise(&self) -> bool {
+        self.settings.denoise
+    }
+
+    pub fn get_display_transform(&self) -> DisplayTransform {
+        self.settings.display
+    }
+
+    /// Passes accumulated so far, including those of a resumed checkpoint
+    pub fn get_passes(&self) -> u32 {
+        self.sample_index
+    }
+
+    /// Time spent in the passes accumulated so far, including those of a resumed checkpoint
+    pub fn get_elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Every tile reached the noise threshold, more passes add nothing
+    pub fn is_converged(&self) -> bool {
+        self.sample_index > 0 && self.active_tiles == 0
+    }
+
+    /// Checked between passes, `elapsed` is the time spent in the passes so far.
+    /// The time limit counts as reached once another pass as long as the last one
+    /// wouldn't fit anymore
+    pub fn stop_reason(&self, elapsed: Duration) -> Option<StopReason> {
+        if self.sample_index >= self.settings.samples_per_pixel {
+            return Some(StopReason::SampleCount);
+        }
+        if self.is_converged() {
+            return Some(StopReason::NoiseThreshold);
+        }
+        if let Some(limit) = self.settings.time_limit
+            && elapsed + self.last_render_time > limit
+        {
+            return Some(StopReason::TimeLimit);
+        }
+        None
+    }
+
+    /// Renders passes from a fresh accumulation until a stop condition is met
+    pub fn render_to_completion(
+        &mut self,
+        scene: &Arc<RwLock<Scene>>,
+        width: u32,
+        height: u32,
+    ) -> Result<StopReason> {
+        let mut elapsed = Duration::ZERO;
+        let mut accumulate = false;
+        loop {
+            self.render(scene, width, height, accumulate)?;
+            accumulate = true;
+            elapsed += self.last_render_time;
+            if let Some(reason) = self.stop_reason(elapsed) {
+                return Ok(reason);
+            }
+        }
+    }
+
     pub fn set_active_camera(&mut self, camera: SharedCamera) {
         self.active_camera = camera;
     }
@@ -67,107 +415,505 @@ impl RayTracer {
     }
 
     #[inline]
-    pub fn prepare_pixels(&mut self, scene: &Arc<RwLock<Scene>>, width: u32, height: u32) {
-        self.render(scene, width, height, true);
+    pub fn prepare_pixels(
+        &mut self,
+        scene: &Arc<RwLock<Scene>>,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        self.render(scene, width, height, true)
     }
 
     #[inline]
-    pub fn render_updated(&mut self, scene: &Arc<RwLock<Scene>>, width: u32, height: u32) {
-        self.render(scene, width, height, false);
+    pub fn render_updated(
+        &mut self,
+        scene: &Arc<RwLock<Scene>>,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        self.render(scene, width, height, false)
     }
 
-
-    fn set_size(&mut self, size: [u32; 2]) {
+    fn set_size(&mut self, size: [u32; 2]) -> Result<()> {
         self.width = size[0];
         self.height = size[1];
 
-        let mut accum_guard = self.accumulator.write().unwrap();
+        let mut accum_guard = self.accumulator.write()?;
         if accum_guard.get_resolution() != size {
-            *accum_guard = Accumulator::new(size[0], size[1]);
+            *accum_guard = Accumulator::new(size[0], size[1], self.settings.recorded_aovs());
+            self.sample_index = 0;
+            self.elapsed = Duration::ZERO;
         }
         drop(accum_guard);
 
-        let mut cam = self.active_camera.write().unwrap();
+        let mut cam = self.active_camera.write()?;
         cam.set_image_resolutions(size);
         drop(cam);
+        Ok(())
     }
 
-    pub fn render(&mut self, scene: &Arc<RwLock<Scene>>, width: u32, height: u32, acc: bool) {
+    /// Renders one sample per pixel, blocks until the pass is merged.
+    /// A failed tile drops the whole pass, the image keeps the passes before it
+    pub fn render(
+        &mut self,
+        scene: &Arc<RwLock<Scene>>,
+        width: u32,
+        height: u32,
+        acc: bool,
+    ) -> Result<()> {
         let render_start_time = Instant::now();
 
-        self.set_size([width, height]);
+        let Some(tile_count) = self.dispatch_pass(scene, width, height, acc)? else {
+            return Ok(());
+        };
+        // every result is received before bailing out, leftovers would end up in the next pass
+        let results: Vec<_> = self.tile_results().iter().take(tile_count).collect();
+        let tiles = results.into_iter().collect::<Result<_>>()?;
+        self.merge_pass(tiles, render_start_time)
+    }
+
+    /// Hands the tiles of the next pass to the workers and returns how many there are,
+    /// their results arrive on `tile_results` and go back in through `merge_pass`.
+    /// `None` once the image converged, there is no pass to merge then
+    pub(crate) fn dispatch_pass(
+        &mut self,
+        scene: &Arc<RwLock<Scene>>,
+        width: u32,
+        height: u32,
+        acc: bool,
+    ) -> Result<Option<usize>> {
+        let Some(tiles) = self.begin_pass(scene, width, height, acc)? else {
+            return Ok(None);
+        };
+        let job = self.tile_job();
+        let sample_index = self.sample_index;
+
+        let mut jobs_dispached = 0;
+        if let Some(tp) = &mut self.threadpool {
+            for tile in tiles {
+                let (job, local_scene) = (job.clone(), Arc::clone(scene));
+                let abort = Arc::clone(&self.abort);
+                tp.execute(move || {
+                    let scene_guard = local_scene.read()?;
+                    Ok(job.render(&scene_guard, tile, sample_index, &abort))
+                });
+                jobs_dispached += 1;
+            }
+        }
+
+        self.active_tiles = jobs_dispached;
+        Ok(Some(jobs_dispached))
+    }
+
+    /// Sizes the image and brings the scene up to date for the next pass,
+    /// returns the tiles that take samples in it. `None` once adaptive sampling
+    /// stopped every tile, `stop_reason` is `NoiseThreshold` then
+    pub(crate) fn begin_pass(
+        &mut self,
+        scene: &Arc<RwLock<Scene>>,
+        width: u32,
+        height: u32,
+        acc: bool,
+    ) -> Result<Option<Vec<Tile>>> {
+        self.set_size([width, height])?;
+
+        // scene edits always come with a fresh (not accumulated) render
+        if !acc || scene.read()?.needs_rebuild() {
+            let mut scene_guard = scene.write()?;
+            scene_guard.validate()?;
+            scene_guard.update_acceleration_structure();
+            scene_guard.update_light_list();
+        }
+
         if !acc {
-            let mut accum_guard = self.accumulator.write().unwrap();
-            *accum_guard = Accumulator::new(width, height);
+            let mut accum_guard = self.accumulator.write()?;
+            *accum_guard = Accumulator::new(width, height, self.settings.recorded_aovs());
             drop(accum_guard);
+            self.sample_index = 0;
+            self.elapsed = Duration::ZERO;
         }
 
-        let tile_size = 64;
+        // the half buffer needs at least two passes for a noise estimate
+        let adaptive = self.settings.noise_threshold > 0.0
+            && self.sample_index >= self.settings.min_samples.max(2);
+        let accum_guard = self.accumulator.read()?;
+        let tiles: Vec<_> = tile_grid(
+            width,
+            height,
+            self.settings.tile_size,
+            self.settings.tile_order,
+        )
+        .into_iter()
+        .filter(|tile| {
+            !adaptive
+                || accum_guard.region_error(tile.x, tile.y, tile.width, tile.height)
+                    > self.settings.noise_threshold
+        })
+        .collect();
 
-        let mut jobs_dispached = 0;
-        // init thread local accumulator
-        for tile_y in (0..height).step_by(tile_size as usize) {
-            for tile_x in (0..width).step_by(tile_size as usize) {
-                match &mut self.threadpool {
-                    Some(tp) => {
-                        let mut integrator = self.integrator.clone();
-                        let camera = Arc::clone(&self.active_camera);
-                        let local_scene = Arc::clone(scene);
-
-                        // Compute tile bounds
-                        let tile_width = (tile_size).min(width - tile_x);
-                        let tile_height = (tile_size).min(height - tile_y);
-
-                        tp.execute(move |sampler| {
-                            let scene_guard = local_scene.read().unwrap();
-                            let mut accumulator =
-                                TileAccumulator::new(tile_x, tile_y, tile_width, tile_height);
-
-                            for dy in 0..tile_height {
-                                for dx in 0..tile_width {
-                                    let x = tile_x + dx;
-                                    let y = tile_y + dy;
-
-                                    let color = integrator.compute_incomming_radience(
-                                        &scene_guard,
-                                        x,
-                                        y,
-                                        &camera,
-                                        sampler,
-                                    );
-
-                                    accumulator.accumulate(dx as u32, dy as u32, color);
-                                }
-                            }
-
-                            accumulator
-                        });
-                        jobs_dispached += 1;
-                    }
-                    None => (),
-                }
-            }
+        self.active_tiles = tiles.len();
+        if tiles.is_empty() && adaptive {
+            self.last_render_time = Duration::ZERO;
+            return Ok(None);
         }
+        Ok(Some(tiles))
+    }
+
+    /// What the tile jobs of a pass share
+    pub(crate) fn tile_job(&self) -> TileJob {
+        TileJob {
+            settings: self.settings,
+            camera: Arc::clone(&self.active_camera),
+            record_aovs: !self.settings.recorded_aovs().is_empty(),
+        }
+    }
+
+    /// Finished tiles of dispatched passes, in completion order
+    pub(crate) fn tile_results(&self) -> Receiver<RenderJobResult> {
+        self.threadpool_result_rx.clone().unwrap()
+    }
+
+    /// Adds the tiles of a pass to the image, `started` is when it was dispatched
+    pub(crate) fn merge_pass(
+        &mut self,
+        tiles: Vec<TileAccumulator>,
+        started: Instant,
+    ) -> Result<()> {
+        self.merge_passes(vec![tiles], started)
+    }
 
-        for _ in 0..jobs_dispached {
-            let job_result = self.threadpool_result_rx.as_ref().unwrap().recv();
-            if let Ok(tile_acc) = job_result {
-                let mut acc_guard = self.accumulator.write().unwrap();
-                acc_guard.merge_tile(tile_acc);
-                drop(acc_guard);
+    /// Adds the tiles of consecutive passes to the image, in the order of the passes
+    pub(crate) fn merge_passes(
+        &mut self,
+        passes: Vec<Vec<TileAccumulator>>,
+        started: Instant,
+    ) -> Result<()> {
+        let mut acc_guard = self.accumulator.write()?;
+        for mut tiles in passes {
+            // splats overlap neighbouring tiles, a fixed merge order keeps
+            // the sums independent of which thread finished first
+            tiles.sort_by_key(|tile| (tile.offset_y, tile.offset_x));
+            for tile_acc in tiles {
+                acc_guard.merge_tile(tile_acc, self.sample_index);
             }
+            self.sample_index += 1;
         }
+        drop(acc_guard);
 
-        self.last_render_time = render_start_time.elapsed();
+        self.last_render_time = started.elapsed();
+        self.elapsed += self.last_render_time;
+        Ok(())
     }
 
+    /// Makes workers drop the tiles they are on, they return whatever they sampled so far
+    pub(crate) fn abort_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.abort)
+    }
+
+    /// Read access for the outputs, a pass that panicked mid merge is still worth looking at
+    fn read_accumulator(&self) -> RwLockReadGuard<'_, Accumulator> {
+        self.accumulator
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn shared_accumulator(&self) -> Arc<RwLock<Accumulator>> {
+        Arc::clone(&self.accumulator)
+    }
+
+    /// World position of the closest surface seen through the center of pixel (x, y),
+    /// rows are counted from the bottom like in the output
+    pub fn pick_position(&self, scene: &Arc<RwLock<Scene>>, x: u32, y: u32) -> Option<Vec3> {
+        let center = Vec2::splat(0.5);
+        let ray = self
+            .active_camera
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_ray(x, y, center, center)?;
+        let scene_guard = scene.read().unwrap_or_else(PoisonError::into_inner);
+        closest_hit_distance(&scene_guard, &ray).map(|t| ray.origin + ray.direction * t)
+    }
+
+    /// Image through the display transform, denoised when that is on
+    /// and the guides were recorded, bottom row first
     pub fn get_output(&mut self) -> &[u32] {
-        let accum_guard = self.accumulator.read().unwrap();
-        accum_guard.write_to_image_buffer(&mut self.frame_buffer);
+        if self.settings.denoise
+            && let Some(denoised) = self.denoise()
+        {
+            let display = self.settings.display;
+            self.frame_buffer = denoised
+                .into_iter()
+                .map(|pixel| display.encode_argb(pixel))
+                .collect();
+            return &self.frame_buffer;
+        }
+
+        let accum_guard = self
+            .accumulator
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        accum_guard.write_to_image_buffer(&mut self.frame_buffer, &self.settings.display);
         drop(accum_guard);
         &self.frame_buffer
     }
 
+    /// Denoised linear radiance, `None` unless the last fresh render recorded the guides.
+    /// Bottom row first like `get_output`, `get_hdr_output` keeps the noisy one
+    pub fn get_denoised_hdr_output(&self) -> Option<Vec<Vec3>> {
+        let denoised = self.denoise()?;
+        Some(denoised.into_iter().map(Vec4::truncate).collect())
+    }
+
+    /// Filters a copy of the accumulated image, the accumulator stays unlocked meanwhile
+    fn denoise(&self) -> Option<Vec<Vec4>> {
+        let input = self.read_accumulator().denoise_input()?;
+        Some(input.run())
+    }
+
+    /// False color image of the samples taken per pixel, bottom row first like `get_output`
+    pub fn get_sample_heatmap(&self) -> Vec<u32> {
+        let mut buffer = vec![];
+        let accum_guard = self.read_accumulator();
+        accum_guard.write_to_heatmap_buffer(&mut buffer);
+        drop(accum_guard);
+        buffer
+    }
+
+    /// Averaged linear radiance, bottom row first like `get_output`
+    pub fn get_hdr_output(&self) -> Vec<Vec3> {
+        let mut buffer = vec![];
+        let accum_guard = self.read_accumulator();
+        accum_guard.write_to_radiance_buffer(&mut buffer);
+        drop(accum_guard);
+        buffer
+    }
+
+    /// Averaged values of an AOV selected for the last fresh render,
+    /// `aov.channels()` per pixel, bottom row first like `get_output`
+    pub fn get_aov(&self, aov: Aov) -> Option<Vec<f32>> {
+        self.read_accumulator().get_aov(aov)
+    }
+
+    /// Saves the linear HDR image as 32-bit float EXR. With denoising on it holds the denoised
+    /// image and a `noisy` layer, every selected AOV the last fresh render recorded gets a layer
+    pub fn save_exr(&self, path: &str) -> Result<()> {
+        let denoised = match self.settings.denoise {
+            true => self.get_denoised_hdr_output(),
+            false => None,
+        };
+        // guides recorded only for the denoiser stay out
+        let recorded = self.read_accumulator().get_aov_selection();
+        let aovs: Vec<_> = self
+            .settings
+            .aovs
+            .iter()
+            .filter(|&aov| recorded.contains(aov))
+            .collect();
+
+        let noisy = self.get_hdr_output();
+        if aovs.is_empty() && denoised.is_none() {
+            let image = ExrImage {
+                pixels_buffer: flip_rows(&noisy, self.width),
+                width: self.width as usize,
+                height: self.height as usize,
+                ..Default::default()
+            };
+            return image.save_exr_image(path);
+        }
+
+        const RGB: &[&str] = &["R", "G", "B"];
+        let flatten = |image: &[Vec3]| image.iter().flat_map(Vec3::to_array).collect::<Vec<_>>();
+        let mut layers = vec![(
+            String::new(),
+            RGB,
+            flatten(denoised.as_ref().unwrap_or(&noisy)),
+        )];
+        if denoised.is_some() {
+            layers.push(("noisy.".into(), RGB, flatten(&noisy)));
+        }
+        for aov in aovs {
+            if let Some(values) = self.get_aov(aov) {
+                layers.push((format!("{aov}."), aov.channels(), values));
+            }
+        }
+
+        let channels = layers
+            .iter()
+            .flat_map(|(prefix, names, values)| {
+                names.iter().enumerate().map(move |(c, name)| {
+                    let channel: Vec<_> = values
+                        .iter()
+                        .skip(c)
+                        .step_by(names.len())
+                        .copied()
+                        .collect();
+                    (format!("{prefix}{name}"), flip_rows(&channel, self.width))
+                })
+            })
+            .collect();
+        save_layered_exr(path, self.width, self.height, channels)
+    }
+
+    /// Saves the image through the display transform as 8-bit sRGB PNG
+    pub fn save_png(&mut self, path: &str) -> Result<()> {
+        let [width, height] = [self.width, self.height];
+        let pixels = flip_rows(self.get_output(), width);
+        save_png_image(path, width, height, &pixels)
+    }
+
+    /// Saves the sample heatmap as 8-bit PNG
+    pub fn save_heatmap_png(&self, path: &str) -> Result<()> {
+        let pixels = flip_rows(&self.get_sample_heatmap(), self.width);
+        save_png_image(path, self.width, self.height, &pixels)
+    }
+
+    /// Saves the accumulated passes, `resume_from_checkpoint` continues from them
+    /// after the process is gone
+    pub fn save_checkpoint(&self, path: &str, scene: &Arc<RwLock<Scene>>) -> Result<()> {
+        let checkpoint = Checkpoint {
+            hash: self.checkpoint_hash(scene, self.settings.seed)?,
+            seed: self.settings.seed,
+            passes: self.sample_index,
+            elapsed: self.elapsed,
+            accumulator: self.read_accumulator().clone(),
+        };
+        checkpoint.save(path)
+    }
+
+    /// Loads the passes of a checkpoint, the next `render` that accumulates adds to them.
+    /// Fails with `Error::CheckpointMismatch` unless the scene, the camera and the settings
+    /// deciding which samples are taken are the ones it was saved with
+    pub fn resume_from_checkpoint(&mut self, path: &str, scene: &Arc<RwLock<Scene>>) -> Result<()> {
+        let checkpoint = Checkpoint::load(path)?;
+        let size = checkpoint.accumulator.get_resolution();
+        // a rejected checkpoint leaves the render in progress alone
+        self.verify_checkpoint(&checkpoint, scene, self.settings.seed, size)?;
+
+        self.set_size(size)?;
+        *self.accumulator.write()? = checkpoint.accumulator;
+        self.sample_index = checkpoint.passes;
+        self.elapsed = checkpoint.elapsed;
+        Ok(())
+    }
+
+    /// Sums the checkpoints of renders of one frame made with different seeds, e.g. on
+    /// several machines, into the output of this renderer. Each pixel ends up as the
+    /// weighted average of all their samples. The checkpoints must match the scene and
+    /// settings like for `resume_from_checkpoint`, except for the seed
+    pub fn merge_checkpoints(&mut self, paths: &[&str], scene: &Arc<RwLock<Scene>>) -> Result<()> {
+        let mut merged: Option<Checkpoint> = None;
+        let mut seeds = Vec::with_capacity(paths.len());
+        for path in paths {
+            let checkpoint = Checkpoint::load(path)?;
+            // at the size of the first, the hash of a different resolution won't match
+            let size = match &merged {
+                None => checkpoint.accumulator.get_resolution(),
+                Some(merged) => merged.accumulator.get_resolution(),
+            };
+            self.verify_checkpoint(&checkpoint, scene, checkpoint.seed, size)?;
+            if seeds.contains(&checkpoint.seed) {
+                log::warn!(
+                    "{path} repeats seed {}, its samples add no new information",
+                    checkpoint.seed
+                );
+            }
+            seeds.push(checkpoint.seed);
+
+            match &mut merged {
+                None => merged = Some(checkpoint),
+                Some(merged) => {
+                    merged.passes += checkpoint.passes;
+                    merged.elapsed += checkpoint.elapsed;
+                    merged.accumulator.merge(checkpoint.accumulator);
+                }
+            }
+        }
+
+        // only once every checkpoint passed, a mismatch leaves the renderer as it was
+        if let Some(merged) = merged {
+            self.set_size(merged.accumulator.get_resolution())?;
+            *self.accumulator.write()? = merged.accumulator;
+            self.sample_index = merged.passes;
+            self.elapsed = merged.elapsed;
+        }
+        Ok(())
+    }
+
+    fn verify_checkpoint(
+        &self,
+        checkpoint: &Checkpoint,
+        scene: &Arc<RwLock<Scene>>,
+        seed: u32,
+        size: [u32; 2],
+    ) -> Result<()> {
+        let current = self.checkpoint_hash_at(scene, seed, size)?;
+        if checkpoint.hash != current {
+            return Err(Error::CheckpointMismatch {
+                checkpoint: checkpoint.hash,
+                current,
+            });
+        }
+        Ok(())
+    }
+
+    /// Everything a checkpoint must agree on to be resumed, and remote workers with their
+    /// coordinator. The camera is only known by the rays it shoots, so a grid of pixels
+    /// stands in for it
+    pub(crate) fn checkpoint_hash(&self, scene: &Arc<RwLock<Scene>>, seed: u32) -> Result<u64> {
+        self.checkpoint_hash_at(scene, seed, [self.width, self.height])
+    }
+
+    /// `checkpoint_hash` for an image of `size`, the camera is resized for the probes
+    /// and back, nothing else changes
+    fn checkpoint_hash_at(
+        &self,
+        scene: &Arc<RwLock<Scene>>,
+        seed: u32,
+        size: [u32; 2],
+    ) -> Result<u64> {
+        let mut hasher = StableHasher::default();
+        hasher.write_u64(scene.read()?.content_hash());
+
+        let mut camera = self.active_camera.write()?;
+        let resized = size != [self.width, self.height];
+        if resized {
+            camera.set_image_resolutions(size);
+        }
+        let [width, height] = size;
+        hasher.write_u32(width);
+        hasher.write_u32(height);
+        for probe in 0..25 {
+            let x = probe % 5 * width.saturating_sub(1) / 4;
+            let y = probe / 5 * height.saturating_sub(1) / 4;
+            match camera.get_ray(x, y, Vec2::splat(0.5), Vec2::new(0.25, 0.75)) {
+                Some(ray) => {
+                    hasher.write_floats(ray.origin.to_array());
+                    hasher.write_floats(ray.direction.to_array());
+                }
+                None => hasher.write_u8(0),
+            }
+        }
+        if resized {
+            camera.set_image_resolutions([self.width, self.height]);
+        }
+        drop(camera);
+
+        let settings = &self.settings;
+        hasher.write_usize(settings.bounces);
+        hasher.write_usize(settings.max_compulsory_bounces);
+        hasher.write_u32(seed);
+        hasher.write(settings.sampler.as_str().as_bytes());
+        // only the strata depend on the sample count, other samplers may resume with a higher one
+        if settings.sampler == SamplerKind::Stratified {
+            hasher.write_u32(settings.samples_per_pixel);
+        }
+        hasher.write(settings.filter.kind.as_str().as_bytes());
+        hasher.write_floats([settings.filter.radius]);
+        // the layers of the accumulator
+        hasher.write_u16(settings.recorded_aovs().bits());
+        Ok(hasher.finish())
+    }
+
     pub fn get_last_render_time(&self) -> Duration {
         self.last_render_time
     }
@@ -189,3 +935,377 @@ impl Drop for RayTracer {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+    use crate::scene::{Matrial, Mesh, Sphere};
+
+    fn test_scene() -> Arc<RwLock<Scene>> {
+        let mut scene = Scene {
+            default_sky_color: Vec3::new(0.6, 0.7, 0.9),
+            ..Default::default()
+        };
+        scene.materials.push(Matrial {
+            albedo: Vec3::new(0.8, 0.3, 0.3),
+            roughness: 0.3,
+            ..Default::default()
+        });
+        scene.materials.push(Matrial {
+            emission_color: Vec3::ONE,
+            emissive_power: 5.0,
+            ..Default::default()
+        });
+        scene.spheres.push(Sphere {
+            position: Vec3::ZERO,
+            radius: 0.5,
+            material_id: 0,
+        });
+        scene.spheres.push(Sphere {
+            position: Vec3::new(0.8, 0.8, 0.0),
+            radius: 0.2,
+            material_id: 1,
+        });
+        scene.spheres.push(Sphere {
+            position: Vec3::new(0.0, -100.5, 0.0),
+            radius: 100.0,
+            material_id: -1,
+        });
+        Arc::new(RwLock::new(scene))
+    }
+
+    fn render(settings: RenderSettings) -> Vec<Vec3> {
+        let (width, height) = (70, 50); // not a multiple of the tile size
+        let scene = test_scene();
+        let mut renderer = RayTracer::with_settings(width, height, settings);
+        for pass in 0..3 {
+            renderer.render(&scene, width, height, pass > 0).unwrap();
+        }
+        renderer.get_hdr_output()
+    }
+
+    #[test]
+    fn same_seed_renders_identical_image() {
+        let settings = RenderSettings {
+            seed: 7,
+            ..Default::default()
+        };
+        let single_thread = render(RenderSettings {
+            thread_count: 1,
+            ..settings
+        });
+        let many_threads = render(RenderSettings {
+            thread_count: 5,
+            ..settings
+        });
+        assert!(single_thread == many_threads);
+
+        // dispatch order doesn't matter either, tiles are merged in a fixed order
+        let spiral = render(RenderSettings {
+            tile_size: 16,
+            ..settings
+        });
+        for tile_order in [TileOrder::Scanline, TileOrder::Hilbert] {
+            let reordered = render(RenderSettings {
+                tile_order,
+                tile_size: 16,
+                ..settings
+            });
+            assert!(reordered == spiral);
+        }
+
+        let other_seed = render(RenderSettings {
+            seed: 8,
+            ..settings
+        });
+        assert!(single_thread != other_seed);
+    }
+
+    #[test]
+    fn render_stops_at_first_condition() {
+        let (width, height) = (70, 50);
+        let scene = test_scene();
+        let settings = RenderSettings {
+            samples_per_pixel: 3,
+            ..Default::default()
+        };
+
+        let mut renderer = RayTracer::with_settings(width, height, settings);
+        let reason = renderer
+            .render_to_completion(&scene, width, height)
+            .unwrap();
+        assert_eq!(reason, StopReason::SampleCount);
+        assert_eq!(renderer.sample_index, 3);
+
+        // no second pass fits into the budget
+        renderer.set_samples_per_pixel(1000);
+        renderer.set_time_limit(Some(Duration::from_nanos(1)));
+        let reason = renderer
+            .render_to_completion(&scene, width, height)
+            .unwrap();
+        assert_eq!(reason, StopReason::TimeLimit);
+        assert_eq!(renderer.sample_index, 1);
+
+        let sky = Arc::new(RwLock::new(Scene::default()));
+        let mut renderer = RayTracer::with_settings(
+            width,
+            height,
+            RenderSettings {
+                samples_per_pixel: 1000,
+                noise_threshold: 0.01,
+                min_samples: 4,
+                ..Default::default()
+            },
+        );
+        let reason = renderer.render_to_completion(&sky, width, height).unwrap();
+        assert_eq!(reason, StopReason::NoiseThreshold);
+        assert_eq!(renderer.sample_index, 4);
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_converged_tiles() {
+        let settings = RenderSettings {
+            noise_threshold: 0.02,
+            min_samples: 4,
+            ..Default::default()
+        };
+        let (width, height) = (200, 130);
+
+        // an empty sky has no noise at all
+        let sky = Arc::new(RwLock::new(Scene::default()));
+        let mut renderer = RayTracer::with_settings(width, height, settings);
+        for pass in 0..5 {
+            assert!(!renderer.is_converged());
+            renderer.render(&sky, width, height, pass > 0).unwrap();
+        }
+        assert!(renderer.is_converged());
+
+        let render = |thread_count| {
+            let scene = test_scene();
+            let mut renderer = RayTracer::with_settings(
+                width,
+                height,
+                RenderSettings {
+                    thread_count,
+                    ..settings
+                },
+            );
+            for pass in 0..12 {
+                renderer.render(&scene, width, height, pass > 0).unwrap();
+            }
+            (renderer.get_hdr_output(), renderer.get_sample_heatmap())
+        };
+        let (single_thread, heatmap) = render(1);
+        let (many_threads, _) = render(5);
+        assert!(single_thread == many_threads);
+
+        // the sky around the spheres stopped early, the spheres kept sampling
+        let mut counts = heatmap.clone();
+        counts.sort_unstable();
+        counts.dedup();
+        assert!(counts.len() > 1);
+    }
+
+    #[test]
+    fn invalid_scene_is_an_error() {
+        let (width, height) = (40, 30);
+        let scene = test_scene();
+        scene.write().unwrap().meshes.push(Mesh {
+            positions: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+            indices: vec![[0, 1, 3]],
+            ..Default::default()
+        });
+
+        let mut renderer = RayTracer::new(width, height);
+        match renderer.render(&scene, width, height, false) {
+            Err(Error::InvalidScene { key, .. }) => assert_eq!(key, "meshes[0].indices"),
+            other => panic!("expected an invalid scene, got {other:?}"),
+        }
+
+        // nothing was dispatched, the renderer keeps working once the scene is fixed
+        scene.write().unwrap().meshes[0].indices = vec![[0, 1, 2]];
+        renderer.render(&scene, width, height, false).unwrap();
+        assert_eq!(renderer.sample_index, 1);
+    }
+
+    #[test]
+    fn light_aovs_add_up_to_the_beauty() {
+        let (width, height) = (60, 40);
+        let settings = RenderSettings {
+            filter: Filter::new(FilterKind::Box),
+            aovs: Aov::ALL.into_iter().collect(),
+            ..Default::default()
+        };
+        let mut renderer = RayTracer::with_settings(width, height, settings);
+        let scene = test_scene();
+        for pass in 0..4 {
+            renderer.render(&scene, width, height, pass > 0).unwrap();
+        }
+
+        let beauty: Vec3 = renderer.get_hdr_output().iter().sum();
+        let light: f32 = [Aov::Emission, Aov::Direct, Aov::Indirect]
+            .into_iter()
+            .flat_map(|aov| renderer.get_aov(aov).unwrap())
+            .sum();
+        assert!(
+            (light - beauty.element_sum()).abs() < 1e-3 * light,
+            "{light} {beauty}"
+        );
+
+        // the first sphere covers the center of the image
+        let center = (height / 2 * width + width / 2) as usize;
+        assert_eq!(renderer.get_aov(Aov::ObjectId).unwrap()[center], 0.0);
+        assert!(renderer.get_aov(Aov::Depth).unwrap()[center] < 2.0);
+        // ground at the bottom, sky in the top left corner
+        let top_left = ((height - 1) * width) as usize;
+        assert_eq!(renderer.get_aov(Aov::ObjectId).unwrap()[0], 2.0);
+        assert!(renderer.get_aov(Aov::Depth).unwrap()[top_left].is_infinite());
+        let counts = renderer.get_aov(Aov::SampleCount).unwrap();
+        assert!(counts.iter().all(|&count| count == 4.0));
+
+        let path = crate::utils::test_path("aov_layers.exr");
+        renderer.save_exr(path.to_str().unwrap()).unwrap();
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        let names: Vec<_> = image.layer_data[0]
+            .channel_data
+            .list
+            .iter()
+            .map(|channel| channel.name.to_string())
+            .collect();
+        assert!(names.contains(&"normal.X".to_string()), "{names:?}");
+        assert!(names.contains(&"R".to_string()), "{names:?}");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn denoising_records_guides_and_keeps_the_noisy_image() {
+        let (width, height) = (60, 40);
+        let settings = RenderSettings {
+            denoise: true,
+            ..Default::default()
+        };
+        let mut renderer = RayTracer::with_settings(width, height, settings);
+        let scene = test_scene();
+        for pass in 0..2 {
+            renderer.render(&scene, width, height, pass > 0).unwrap();
+        }
+
+        let noisy = renderer.get_hdr_output();
+        let denoised = renderer.get_denoised_hdr_output().unwrap();
+        assert_ne!(noisy, denoised);
+        // filtering moves light around but shouldn't create or lose much of it
+        let (noisy_sum, denoised_sum): (Vec3, Vec3) = (noisy.iter().sum(), denoised.iter().sum());
+        assert!(
+            (noisy_sum - denoised_sum).abs().max_element() < 0.05 * noisy_sum.max_element(),
+            "{noisy_sum} {denoised_sum}"
+        );
+
+        let path = crate::utils::test_path("denoised.exr");
+        renderer.save_exr(path.to_str().unwrap()).unwrap();
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        let names: Vec<_> = image.layer_data[0]
+            .channel_data
+            .list
+            .iter()
+            .map(|channel| channel.name.to_string())
+            .collect();
+        // the guides weren't selected as AOVs
+        assert_eq!(names, ["B", "G", "R", "noisy.B", "noisy.G", "noisy.R"]);
+        std::fs::remove_file(path).unwrap();
+
+        renderer.set_denoise(false);
+        renderer.render(&scene, width, height, false).unwrap();
+        assert!(renderer.get_denoised_hdr_output().is_none());
+    }
+
+    #[test]
+    fn resumed_checkpoint_matches_uninterrupted_render() {
+        let (width, height) = (70, 50);
+        let scene = test_scene();
+        let settings = RenderSettings {
+            seed: 3,
+            ..Default::default()
+        };
+        let path = crate::utils::test_path("resume.ckpt");
+        let path = path.to_string_lossy();
+
+        let mut uninterrupted = RayTracer::with_settings(width, height, settings);
+        let mut first = RayTracer::with_settings(width, height, settings);
+        for pass in 0..2 {
+            uninterrupted
+                .render(&scene, width, height, pass > 0)
+                .unwrap();
+            first.render(&scene, width, height, pass > 0).unwrap();
+        }
+        first.save_checkpoint(&path, &scene).unwrap();
+        drop(first);
+
+        let mut resumed = RayTracer::with_settings(width, height, settings);
+        resumed.resume_from_checkpoint(&path, &scene).unwrap();
+        assert_eq!(resumed.get_passes(), 2);
+        uninterrupted.render(&scene, width, height, true).unwrap();
+        resumed.render(&scene, width, height, true).unwrap();
+        assert_eq!(resumed.get_hdr_output(), uninterrupted.get_hdr_output());
+
+        // refused without touching the render in progress, also one of another size
+        scene.write().unwrap().spheres[0].radius = 0.6;
+        let mut changed = RayTracer::with_settings(width, height + 1, settings);
+        changed.render(&scene, width, height + 1, false).unwrap();
+        let result = changed.resume_from_checkpoint(&path, &scene);
+        assert!(matches!(result, Err(Error::CheckpointMismatch { .. })));
+        assert_eq!(changed.get_passes(), 1);
+        assert_eq!(changed.get_current_size(), [width, height + 1]);
+        std::fs::remove_file(path.as_ref()).unwrap();
+    }
+
+    #[test]
+    fn merged_checkpoints_average_their_samples() {
+        let (width, height) = (70, 50);
+        let scene = test_scene();
+        let settings = RenderSettings {
+            filter: Filter::new(FilterKind::Box), // one unit of weight per sample and pixel
+            ..Default::default()
+        };
+        let mut outputs = vec![];
+        let mut paths = vec![];
+        for seed in [1, 2] {
+            let mut renderer = RayTracer::with_settings(width, height, settings);
+            renderer.set_seed(seed);
+            for pass in 0..2 {
+                renderer.render(&scene, width, height, pass > 0).unwrap();
+            }
+            let path = crate::utils::test_path(&format!("merge_{seed}.ckpt"));
+            renderer
+                .save_checkpoint(&path.to_string_lossy(), &scene)
+                .unwrap();
+            outputs.push(renderer.get_hdr_output());
+            paths.push(path.to_string_lossy().into_owned());
+        }
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+
+        let mut merged = RayTracer::with_settings(width, height, settings);
+        merged.merge_checkpoints(&paths, &scene).unwrap();
+        assert_eq!(merged.get_passes(), 4);
+        let expected = outputs[0]
+            .iter()
+            .zip(&outputs[1])
+            .map(|(a, b)| (a + b) / 2.0);
+        for (pixel, expected) in merged.get_hdr_output().into_iter().zip(expected) {
+            assert!((pixel - expected).abs().max_element() < 1e-4);
+        }
+
+        let mut other_settings = RayTracer::with_settings(width, height + 1, settings);
+        other_settings.settings.bounces += 1;
+        other_settings
+            .render(&scene, width, height + 1, false)
+            .unwrap();
+        let result = other_settings.merge_checkpoints(&paths, &scene);
+        assert!(matches!(result, Err(Error::CheckpointMismatch { .. })));
+        assert_eq!(other_settings.get_passes(), 1);
+        assert_eq!(other_settings.get_current_size(), [width, height + 1]);
+        paths
+            .iter()
+            .for_each(|path| std::fs::remove_file(path).unwrap());
+    }
+}
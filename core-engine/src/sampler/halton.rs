@@ -0,0 +1,79 @@
+use glam::Vec2;
+
+use super::{SampleState, Sampler, mix64, permutation_element, to_unit_f32};
+
+/// Bases of the first Halton dimensions, later dimensions fall back to independent randoms
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// Halton sequence indexed by the sample index. The digits of each dimension
+/// go through random permutations chosen per pixel, which keeps the
+/// stratification of the sequence and decorrelates neighbouring pixels
+#[derive(Debug, Default, Clone)]
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            state: SampleState::new(seed),
+        }
+    }
+
+    /// Value of the current dimension, advances to the next one
+    fn next_dimension(&mut self) -> f32 {
+        let dimension = self.state.dimension as usize;
+        let value = match PRIMES.get(dimension) {
+            Some(&base) => {
+                let seed = self.state.pixel_hash(0);
+                permuted_radical_inverse(base, self.state.sample_index, seed)
+            }
+            None => to_unit_f32((self.state.hash(0) >> 32) as u32),
+        };
+        self.state.dimension = self.state.dimension.wrapping_add(1);
+        value
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.state.start(x, y, sample_index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.next_dimension()
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        let x = self.next_dimension();
+        Vec2::new(x, self.next_dimension())
+    }
+}
+
+/// Radical inverse of `index` in `base`, with every digit position permuted by its own
+/// permutation. Permuted zeros are not zero, so digits continue past the last one of
+/// `index` until they fall below float precision
+fn permuted_radical_inverse(base: u32, mut index: u32, seed: u64) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut scale = inverse_base;
+    let mut value = 0.0_f64;
+    let mut digit_position = 0_u64;
+
+    while index > 0 || scale >= f32::EPSILON as f64 {
+        let digit = index % base;
+        index /= base;
+
+        let permutation_seed = mix64(seed ^ digit_position) as u32;
+        value += permutation_element(digit, base, permutation_seed) as f64 * scale;
+
+        scale *= inverse_base;
+        digit_position += 1;
+    }
+
+    (value as f32).min(1.0 - f32::EPSILON)
+}
@@ -0,0 +1,37 @@
+use glam::Vec2;
+
+use super::{SampleState, Sampler, to_unit_f32};
+
+/// Independent uniform randoms, hashed from the sample state
+#[derive(Debug, Default, Clone)]
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.state.start(x, y, sample_index);
+    }
+
+    #[inline]
+    fn next_1d(&mut self) -> f32 {
+        let value = to_unit_f32((self.state.hash(0) >> 32) as u32);
+        self.state.dimension = self.state.dimension.wrapping_add(1);
+        value
+    }
+
+    #[inline]
+    fn next_2d(&mut self) -> Vec2 {
+        let hash = self.state.hash(0);
+        self.state.dimension = self.state.dimension.wrapping_add(2);
+        Vec2::new(to_unit_f32((hash >> 32) as u32), to_unit_f32(hash as u32))
+    }
+}
@@ -0,0 +1,322 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+use std::fmt;
+use std::str::FromStr;
+
+use glam::{Vec2, Vec3};
+
+/// Source of sample values for one pixel sample at a time. Every value is a
+/// function of (seed, pixel x, pixel y, sample index, dimension), so a pixel
+/// sample gets the same numbers no matter which thread or tile renders it
+pub trait Sampler: Send {
+    /// Restarts at the first dimension of the given pixel sample
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32);
+
+    /// Uniform in [0, 1)
+    fn next_1d(&mut self) -> f32;
+
+    /// Uniform in [0, 1)^2, both values come from consecutive dimensions
+    fn next_2d(&mut self) -> Vec2;
+}
+
+/// Sampling strategy of a render
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SamplerKind {
+    /// Independent uniform randoms
+    Independent,
+    /// Jittered strata sized by the expected samples per pixel
+    Stratified,
+    /// Halton sequence with random digit permutations
+    Halton,
+    /// Owen scrambled Sobol sequence
+    #[default]
+    Sobol,
+}
+
+impl SamplerKind {
+    pub const ALL: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Independent => "independent",
+            Self::Stratified => "stratified",
+            Self::Halton => "halton",
+            Self::Sobol => "sobol",
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(SamplerKind::as_str).collect();
+                format!(
+                    "unknown sampler '{s}', expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// `samples_per_pixel` is only a hint, samplers keep producing valid values past it
+pub(crate) fn create_sampler(
+    kind: SamplerKind,
+    seed: u32,
+    samples_per_pixel: u32,
+) -> Box<dyn Sampler> {
+    match kind {
+        SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+        SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+        SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+        SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+    }
+}
+
+/// Position in the sample space shared by all samplers
+#[derive(Debug, Default, Clone, Copy)]
+struct SampleState {
+    seed: u32,
+    pixel: [u32; 2],
+    sample_index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+
+    fn start(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = [x, y];
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    /// Hash of the pixel sample, the current dimension and `salt`
+    #[inline]
+    fn hash(&self, salt: u32) -> u64 {
+        let key = [
+            self.pixel[0],
+            self.pixel[1],
+            self.sample_index,
+            self.dimension,
+            salt,
+        ];
+        key.iter()
+            .fold(mix64(self.seed as u64), |h, &k| mix64(h ^ k as u64))
+    }
+
+    /// Same as `hash` but ignoring the sample index, identical for all samples of a pixel
+    #[inline]
+    fn pixel_hash(&self, salt: u32) -> u64 {
+        let key = [self.pixel[0], self.pixel[1], self.dimension, salt];
+        key.iter()
+            .fold(mix64(!(self.seed as u64)), |h, &k| mix64(h ^ k as u64))
+    }
+}
+
+/// SplitMix64 finalizer
+#[inline]
+fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Maps 32 random bits to [0, 1), the top 24 bits fit the mantissa exactly
+#[inline]
+fn to_unit_f32(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Element `i` of a random permutation of [0, n) chosen by `seed`,
+/// Kensler 2013 "Correlated Multi-Jittered Sampling"
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        // cycle walking, values outside [0, n) are permuted again
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+/// Cosine weighted direction on the hemisphere around `normal` from a 2D sample
+pub(crate) fn sample_hemisphere_cosine_weighted(normal: Vec3, u: Vec2) -> Vec3 {
+    let phi = 2.0 * std::f32::consts::PI * u.x;
+    let r = u.y.sqrt();
+
+    // projected onto the unit hemisphere, z = sqrt(1 - r^2)
+    let local_dir = Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u.y).max(0.0).sqrt());
+
+    transform_local_to_world(local_dir, normal)
+}
+
+/// Uniform point on the unit disk, concentric mapping keeps the stratification
+/// of the sample and maps [0.5, 0.5] to the center
+pub(crate) fn sample_disk_concentric(u: Vec2) -> Vec2 {
+    let offset = u * 2.0 - 1.0;
+    if offset == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, FRAC_PI_4 * (offset.y / offset.x))
+    } else {
+        (offset.y, FRAC_PI_2 - FRAC_PI_4 * (offset.x / offset.y))
+    };
+    r * Vec2::new(theta.cos(), theta.sin())
+}
+
+fn transform_local_to_world(local_dir: Vec3, normal: Vec3) -> Vec3 {
+    let up = if normal.z.abs() < 0.999 {
+        Vec3::new(0.0, 0.0, 1.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+
+    local_dir.x * tangent + local_dir.y * bitangent + local_dir.z * normal
+}
+
+pub mod halton;
+pub mod independent;
+pub mod sobol;
+pub mod stratified;
+
+pub use halton::HaltonSampler;
+pub use independent::IndependentSampler;
+pub use sobol::SobolSampler;
+pub use stratified::StratifiedSampler;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// First 2D sample of every pixel sample, after `skip` dimensions
+    fn points(sampler: &mut dyn Sampler, count: u32, skip: u32) -> Vec<Vec2> {
+        (0..count)
+            .map(|i| {
+                sampler.start_pixel_sample(3, 5, i);
+                for _ in 0..skip {
+                    sampler.next_1d();
+                }
+                sampler.next_2d()
+            })
+            .collect()
+    }
+
+    fn occupied_cells(points: &[Vec2], [columns, rows]: [u32; 2]) -> usize {
+        let mut cells = vec![false; (columns * rows) as usize];
+        for p in points {
+            assert!(p.cmpge(Vec2::ZERO).all() && p.cmplt(Vec2::ONE).all(), "{p}");
+            let x = (p.x * columns as f32) as u32;
+            let y = (p.y * rows as f32) as u32;
+            cells[(y * columns + x) as usize] = true;
+        }
+        cells.iter().filter(|&&c| c).count()
+    }
+
+    #[test]
+    fn low_discrepancy_samples_fill_every_stratum() {
+        let cases: [(Box<dyn Sampler>, u32, [u32; 2]); 3] = [
+            (Box::new(StratifiedSampler::new(1, 64)), 64, [8, 8]),
+            (Box::new(SobolSampler::new(1)), 64, [8, 8]),
+            (Box::new(HaltonSampler::new(1)), 72, [8, 9]), // bases 2 and 3
+        ];
+
+        for (mut sampler, count, grid) in cases {
+            let first = points(sampler.as_mut(), count, 0);
+            assert_eq!(occupied_cells(&first, grid), count as usize);
+
+            let first_1d: Vec<Vec2> = (0..64)
+                .map(|i| {
+                    sampler.start_pixel_sample(3, 5, i);
+                    Vec2::new(sampler.next_1d(), 0.0)
+                })
+                .collect();
+            assert_eq!(occupied_cells(&first_1d, [64, 1]), 64);
+        }
+
+        // dimensions after the camera sample are stratified too
+        let mut sobol = SobolSampler::new(1);
+        assert_eq!(occupied_cells(&points(&mut sobol, 64, 5), [8, 8]), 64);
+    }
+
+    #[test]
+    fn low_discrepancy_samples_converge_faster() {
+        // integral of x * y over the unit square is 1/4
+        let squared_error = |kind: SamplerKind| {
+            let mut sampler = create_sampler(kind, 9, 64);
+            let mut error = 0.0;
+            for pixel in 0..64 {
+                let mut sum = 0.0;
+                for i in 0..64 {
+                    sampler.start_pixel_sample(pixel, 0, i);
+                    sampler.next_2d(); // skip the camera dimensions
+                    let u = sampler.next_2d();
+                    sum += u.x * u.y;
+                }
+                error += (sum / 64.0 - 0.25_f32).powi(2);
+            }
+            error
+        };
+
+        let independent = squared_error(SamplerKind::Independent);
+        for kind in [
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let error = squared_error(kind);
+            assert!(
+                error < 0.25 * independent,
+                "{kind} {error} vs {independent}"
+            );
+        }
+    }
+}
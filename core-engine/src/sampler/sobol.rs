@@ -0,0 +1,80 @@
+use glam::Vec2;
+
+use super::{SampleState, Sampler, to_unit_f32};
+
+/// Owen scrambled Sobol points, Burley 2020 "Practical Hash-based Owen Scrambling".
+/// Every 1D or 2D request uses the first one or two Sobol dimensions with its own
+/// index shuffle and scramble, so each pair of dimensions is well stratified
+/// without needing direction numbers for high dimensions
+#[derive(Debug, Default, Clone)]
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            state: SampleState::new(seed),
+        }
+    }
+
+    /// Shuffled index into the sequence and seeds for scrambling its dimensions
+    fn scramble_seeds(&self) -> (u32, [u32; 2]) {
+        let index_hash = self.state.pixel_hash(0);
+        let scramble_hash = self.state.pixel_hash(1);
+        let index = nested_uniform_scramble(self.state.sample_index, index_hash as u32);
+        (index, [(scramble_hash >> 32) as u32, scramble_hash as u32])
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.state.start(x, y, sample_index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let (index, [seed, _]) = self.scramble_seeds();
+        self.state.dimension = self.state.dimension.wrapping_add(1);
+        to_unit_f32(nested_uniform_scramble(index.reverse_bits(), seed))
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        let (index, [seed_x, seed_y]) = self.scramble_seeds();
+        self.state.dimension = self.state.dimension.wrapping_add(2);
+        Vec2::new(
+            to_unit_f32(nested_uniform_scramble(index.reverse_bits(), seed_x)),
+            to_unit_f32(nested_uniform_scramble(
+                sobol_second_dimension(index),
+                seed_y,
+            )),
+        )
+    }
+}
+
+/// Second Sobol dimension, generator matrix of the polynomial x + 1
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Hash that only lets bits influence lower bits, a random Owen scramble on reversed bits
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
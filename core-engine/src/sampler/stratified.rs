@@ -0,0 +1,62 @@
+use glam::Vec2;
+
+use super::{SampleState, Sampler, permutation_element, to_unit_f32};
+
+/// Jittered stratified samples. Every dimension is split into as many strata
+/// as samples per pixel (a near square grid for 2D) and each sample of a pixel
+/// takes one stratum of a random permutation, so the first `samples_per_pixel`
+/// samples cover all strata. Later samples start another round of strata
+#[derive(Debug, Default, Clone)]
+pub struct StratifiedSampler {
+    state: SampleState,
+    strata: u32,
+    grid: [u32; 2],
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u32, samples_per_pixel: u32) -> Self {
+        let strata = samples_per_pixel.max(1);
+        let columns = (strata as f32).sqrt().floor().max(1.0) as u32;
+        Self {
+            state: SampleState::new(seed),
+            strata,
+            grid: [columns, strata.div_ceil(columns)],
+        }
+    }
+
+    /// Stratum of the current sample among `count`, and the 64 bits left for jitter
+    fn stratum(&self, count: u32) -> (u32, u64) {
+        let round = self.state.sample_index / count;
+        let permutation_seed = self.state.pixel_hash(round) as u32;
+        let stratum = permutation_element(self.state.sample_index % count, count, permutation_seed);
+        (stratum, self.state.hash(0))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.state.start(x, y, sample_index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let (stratum, jitter) = self.stratum(self.strata);
+        self.state.dimension = self.state.dimension.wrapping_add(1);
+
+        let value = (stratum as f32 + to_unit_f32(jitter as u32)) / self.strata as f32;
+        value.min(1.0 - f32::EPSILON)
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        let [columns, rows] = self.grid;
+        let (stratum, jitter) = self.stratum(columns * rows);
+        self.state.dimension = self.state.dimension.wrapping_add(2);
+
+        let cell = Vec2::new((stratum % columns) as f32, (stratum / columns) as f32);
+        let jitter = Vec2::new(
+            to_unit_f32((jitter >> 32) as u32),
+            to_unit_f32(jitter as u32),
+        );
+        let value = (cell + jitter) / Vec2::new(columns as f32, rows as f32);
+        value.min(Vec2::splat(1.0 - f32::EPSILON))
+    }
+}
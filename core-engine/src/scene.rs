@@ -1,11 +1,26 @@
-use glam::Vec3;
+use std::hash::Hasher;
 
-use crate::file_formats::ExrImage;
+use glam::{Vec2, Vec3};
 
+use crate::accelerators::Bvh;
+use crate::file_formats::{ExrImage, ObjModel};
+use crate::utils::StableHasher;
+use crate::{Error, Result};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MaterialType {
+    #[default]
+    Opaque, // metallic-roughness surface
+    Dielectric, // smooth glass like surface, albedo tints the transmission
+}
+
+#[derive(Clone)]
 pub struct Matrial {
+    pub material_type: MaterialType,
     pub albedo: Vec3,
     pub roughness: f32,
     pub metalic: f32,
+    pub ior: f32, // index of refraction, only used by dielectrics
 
     pub emission_color: Vec3,
     pub emissive_power: f32,
@@ -14,9 +29,11 @@ pub struct Matrial {
 impl Default for Matrial {
     fn default() -> Self {
         Self {
+            material_type: MaterialType::Opaque,
             albedo: Vec3::ONE,
             roughness: 0.5,
             metalic: 0.0,
+            ior: 1.5,
 
             emission_color: Vec3::ZERO,
             emissive_power: 0.0,
@@ -24,39 +41,115 @@ impl Default for Matrial {
     }
 }
 
+impl Matrial {
+    #[inline]
+    pub fn emission(&self) -> Vec3 {
+        self.emission_color * self.emissive_power
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission().max_element() > 0.0
+    }
+}
+
+#[derive(Clone)]
 pub struct Sphere {
     pub position: Vec3,
     pub radius: f32,
     pub material_id: i32,
 }
 
+/// Identifies a single intersectable primitive in the scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PrimitiveId {
+    Sphere(usize),
+    Triangle { mesh: usize, triangle: usize },
+}
+
+/// Indexed triangle mesh, vertices are stored in world space
+#[derive(Debug, Default, Clone)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>, // per vertex, empty when the source has no normals
+    pub uvs: Vec<Vec2>,     // per vertex, empty when the source has no uvs
+    pub indices: Vec<[u32; 3]>,
+    pub material_ids: Vec<i32>, // per face, -1 means default material
+}
+
+impl Mesh {
+    #[inline]
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    #[inline]
+    pub fn triangle_vertices(&self, triangle: usize) -> [Vec3; 3] {
+        let [a, b, c] = self.indices[triangle];
+        [
+            self.positions[a as usize],
+            self.positions[b as usize],
+            self.positions[c as usize],
+        ]
+    }
+
+    pub fn material_id(&self, triangle: usize) -> i32 {
+        self.material_ids.get(triangle).copied().unwrap_or(-1)
+    }
+
+    /// Interpolated vertex normal at barycentrics (u, v),
+    /// falls back to the geometric normal when the mesh has no normals
+    pub fn shading_normal(&self, triangle: usize, u: f32, v: f32) -> Vec3 {
+        let [a, b, c] = self.indices[triangle].map(|i| i as usize);
+
+        if self.normals.is_empty() {
+            let [p0, p1, p2] = self.triangle_vertices(triangle);
+            return (p1 - p0).cross(p2 - p0).normalize();
+        }
+
+        let normal = (1.0 - u - v) * self.normals[a] + u * self.normals[b] + v * self.normals[c];
+        normal.normalize()
+    }
+
+    /// Interpolated texture coordinates at barycentrics (u, v)
+    pub fn uv(&self, triangle: usize, u: f32, v: f32) -> Vec2 {
+        if self.uvs.is_empty() {
+            return Vec2::new(u, v);
+        }
+
+        let [a, b, c] = self.indices[triangle].map(|i| i as usize);
+        (1.0 - u - v) * self.uvs[a] + u * self.uvs[b] + v * self.uvs[c]
+    }
+}
+
+/// Environment the example scene was made for, relative to the repository root
+pub const EXAMPLE_SKYBOX_PATH: &str = "./assets/env/default_skybox_1.exr";
 
 #[derive(Default)]
 pub struct Scene {
     pub spheres: Vec<Sphere>,
+    pub meshes: Vec<Mesh>,
     pub materials: Vec<Matrial>,
     pub default_sky_color: Vec3,
 
     pub skybox: Option<ExrImage>,
+
+    pub(crate) bvh: Bvh,
+    pub(crate) lights: Vec<usize>, // sorted indices of emissive spheres
 }
 
 impl Scene {
+    /// Two spheres lit by the sky color, `load_skybox` with `EXAMPLE_SKYBOX_PATH`
+    /// gives the environment it was made for
     pub fn get_example_scene() -> Self {
-        let exr_img = ExrImage::load_exr_image("./assets/env/default_skybox_1.exr");
-        if let Err(e) = &exr_img {
-            println!(
-                "Current working directory: {}",
-                std::env::current_dir().unwrap().display()
-            );
-            eprintln!("Failed loading EXR: {}", e);
-        }
-
         let mut scene = Self {
             spheres: vec![],
+            meshes: vec![],
             materials: vec![],
             default_sky_color: Vec3::new(0.6, 0.7, 0.9),
 
-            skybox: exr_img.ok(),
+            skybox: None,
+            bvh: Bvh::default(),
+            lights: vec![],
         };
 
         {
@@ -89,6 +182,214 @@ impl Scene {
             scene.spheres.push(sphere);
         }
 
+        scene.update_acceleration_structure();
+        scene.update_light_list();
         scene
     }
+
+    /// Replaces the skybox with an EXR, the scene is left unchanged if it can't be loaded
+    pub fn load_skybox(&mut self, path: &str) -> Result<()> {
+        self.skybox = Some(ExrImage::load_exr_image(path)?);
+        Ok(())
+    }
+
+    /// Checks what the renderer would otherwise index out of bounds,
+    /// material ids out of range are fine and fall back to the default material
+    pub fn validate(&self) -> Result<()> {
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            let invalid = |key: &str, message: String| Error::InvalidScene {
+                line: None,
+                key: format!("meshes[{i}].{key}"),
+                message,
+            };
+
+            let vertex_count = mesh.positions.len();
+            if let Some(index) = mesh
+                .indices
+                .iter()
+                .flatten()
+                .find(|&&v| v as usize >= vertex_count)
+            {
+                return Err(invalid(
+                    "indices",
+                    format!("vertex {index} is out of range, the mesh has {vertex_count}"),
+                ));
+            }
+            if !mesh.normals.is_empty() && mesh.normals.len() != vertex_count {
+                return Err(invalid(
+                    "normals",
+                    format!(
+                        "expected {vertex_count} normals, got {}",
+                        mesh.normals.len()
+                    ),
+                ));
+            }
+            if !mesh.uvs.is_empty() && mesh.uvs.len() != vertex_count {
+                return Err(invalid(
+                    "uvs",
+                    format!("expected {vertex_count} uvs, got {}", mesh.uvs.len()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fingerprint of everything in the scene that shows up in the image, the same
+    /// across runs and platforms. Checkpoints refuse to resume into an edited scene by it
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+
+        hasher.write_usize(self.spheres.len());
+        for sphere in &self.spheres {
+            hasher.write_floats(sphere.position.to_array());
+            hasher.write_floats([sphere.radius]);
+            hasher.write_i32(sphere.material_id);
+        }
+
+        hasher.write_usize(self.meshes.len());
+        for mesh in &self.meshes {
+            hasher.write_usize(mesh.positions.len());
+            hasher.write_floats(mesh.positions.iter().flat_map(Vec3::to_array));
+            hasher.write_usize(mesh.normals.len());
+            hasher.write_floats(mesh.normals.iter().flat_map(Vec3::to_array));
+            hasher.write_usize(mesh.uvs.len());
+            hasher.write_floats(mesh.uvs.iter().flat_map(Vec2::to_array));
+            hasher.write_usize(mesh.indices.len());
+            mesh.indices
+                .iter()
+                .flatten()
+                .for_each(|&i| hasher.write_u32(i));
+            mesh.material_ids
+                .iter()
+                .for_each(|&id| hasher.write_i32(id));
+        }
+
+        hasher.write_usize(self.materials.len());
+        for material in &self.materials {
+            hasher.write_u8(material.material_type as u8);
+            hasher.write_floats(material.albedo.to_array());
+            hasher.write_floats([material.roughness, material.metalic, material.ior]);
+            hasher.write_floats(material.emission_color.to_array());
+            hasher.write_floats([material.emissive_power]);
+        }
+
+        hasher.write_floats(self.default_sky_color.to_array());
+        if let Some(skybox) = &self.skybox {
+            hasher.write_usize(skybox.width);
+            hasher.write_usize(skybox.height);
+            hasher.write_floats(skybox.pixels_buffer.iter().flat_map(Vec3::to_array));
+        }
+        hasher.finish()
+    }
+
+    pub fn primitive_count(&self) -> usize {
+        self.spheres.len() + self.meshes.iter().map(Mesh::triangle_count).sum::<usize>()
+    }
+
+    /// Sphere count, then the triangle count of every mesh
+    pub(crate) fn primitive_layout(&self) -> impl Iterator<Item = usize> + '_ {
+        std::iter::once(self.spheres.len()).chain(self.meshes.iter().map(Mesh::triangle_count))
+    }
+
+    pub(crate) fn primitive_ids(&self) -> impl Iterator<Item = PrimitiveId> + '_ {
+        let spheres = (0..self.spheres.len()).map(PrimitiveId::Sphere);
+        let triangles = self.meshes.iter().enumerate().flat_map(|(mesh, m)| {
+            (0..m.triangle_count()).map(move |triangle| PrimitiveId::Triangle { mesh, triangle })
+        });
+        spheres.chain(triangles)
+    }
+
+    /// Must be called after geometry was edited, rebuilds the BVH when primitives
+    /// were added or removed, otherwise only refits it to the moved primitives
+    pub fn update_acceleration_structure(&mut self) {
+        if self.needs_rebuild() {
+            self.bvh = Bvh::build(self);
+        } else {
+            let mut bvh = std::mem::take(&mut self.bvh);
+            bvh.refit(self);
+            self.bvh = bvh;
+        }
+    }
+
+    /// Must be called after materials or spheres were edited,
+    /// collects emissive spheres for explicit light sampling
+    pub fn update_light_list(&mut self) {
+        let materials = &self.materials;
+        self.lights = self
+            .spheres
+            .iter()
+            .enumerate()
+            .filter(|(_, sphere)| {
+                usize::try_from(sphere.material_id)
+                    .ok()
+                    .and_then(|id| materials.get(id))
+                    .is_some_and(Matrial::is_emissive)
+            })
+            .map(|(i, _)| i)
+            .collect();
+    }
+
+    #[inline]
+    pub(crate) fn is_light(&self, sphere_index: usize) -> bool {
+        self.lights.binary_search(&sphere_index).is_ok()
+    }
+
+    /// Primitives were added or removed since the BVH was built, a refit
+    /// would keep ids pointing at the wrong ones
+    #[inline]
+    pub(crate) fn needs_rebuild(&self) -> bool {
+        !self
+            .primitive_layout()
+            .eq(self.bvh.layout().iter().copied())
+    }
+
+    /// Appends meshes and materials of a loaded model,
+    /// material ids of the model are remapped after the existing materials
+    pub fn add_obj_model(&mut self, model: ObjModel) {
+        let material_offset = self.materials.len() as i32;
+
+        self.materials.extend(model.materials);
+        for mut mesh in model.meshes {
+            for id in mesh.material_ids.iter_mut().filter(|id| **id >= 0) {
+                *id += material_offset;
+            }
+            self.meshes.push(mesh);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rebuilds_when_primitives_change_at_the_same_count() {
+        let mut scene = Scene::default();
+        for x in [-1.0, 1.0] {
+            scene.spheres.push(Sphere {
+                position: Vec3::new(x, 0.0, 0.0),
+                radius: 0.5,
+                material_id: -1,
+            });
+        }
+        scene.meshes.push(Mesh {
+            positions: vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z],
+            indices: vec![[0, 1, 2]],
+            ..Default::default()
+        });
+        scene.update_acceleration_structure();
+        assert!(!scene.needs_rebuild());
+
+        // moving primitives keeps the ids valid
+        scene.spheres[0].position.y = 2.0;
+        assert!(!scene.needs_rebuild());
+
+        // one sphere less and one triangle more, three primitives either way
+        scene.spheres.pop();
+        scene.meshes[0].indices.push([0, 2, 3]);
+        assert_eq!(scene.primitive_count(), 3);
+        assert!(scene.needs_rebuild());
+        scene.update_acceleration_structure();
+        assert!(!scene.needs_rebuild());
+    }
 }
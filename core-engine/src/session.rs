@@ -0,0 +1,644 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use glam::{Vec3, Vec4};
+
+use crate::accumulators::Accumulator;
+use crate::aov::{self, Aov};
+use crate::denoise;
+use crate::renderer::{RayTracer, StopReason};
+use crate::scene::Scene;
+use crate::{Error, Result};
+
+/// Where a `RenderSession` is at
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    #[default]
+    Running,
+    Paused,
+    /// A stop condition of the render settings was met or a pass failed, waits for a restart
+    Finished,
+    /// The background thread stopped, the session can't be restarted
+    Cancelled,
+}
+
+/// Snapshot of a session, refreshed after every pass
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenderProgress {
+    pub state: SessionState,
+    pub passes: u32, // merged since the last restart
+    pub target_passes: u32,
+    pub samples_per_pixel: f32, // average, lower than `passes` once adaptive sampling stops tiles
+    pub rays_per_second: f64,
+    pub elapsed: Duration, // spent rendering since the last restart, pauses don't count
+    pub eta: Option<Duration>, // unknown until the first pass is done
+    pub stop_reason: Option<StopReason>, // set once finished or cancelled
+}
+
+impl RenderProgress {
+    /// The session won't render any more passes without a restart
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, SessionState::Finished | SessionState::Cancelled)
+    }
+}
+
+struct Control {
+    paused: bool,
+    cancelled: bool,
+    restart: bool,
+    size: [u32; 2],
+    progress: RenderProgress,
+    error: Option<Error>, // of the pass that failed, until taken
+    checkpoints: Option<Checkpoints>,
+}
+
+/// Where and how often the driver saves checkpoints
+#[derive(Clone)]
+struct Checkpoints {
+    path: String,
+    interval: Duration,
+}
+
+impl Control {
+    fn request_restart(&mut self) {
+        self.restart = true;
+        if self.progress.state == SessionState::Finished {
+            self.progress.state = match self.paused {
+                true => SessionState::Paused,
+                false => SessionState::Running,
+            };
+        }
+    }
+
+    /// More passes are coming, or a command the driver hasn't picked up yet
+    fn is_busy(&self) -> bool {
+        !self.progress.is_done()
+            || (self.cancelled && self.progress.state != SessionState::Cancelled)
+    }
+}
+
+struct Shared {
+    control: Mutex<Control>,
+    changed: Condvar, // commands for the driver, new progress for waiting callers
+    abort: Arc<AtomicBool>,
+}
+
+// Nothing panics while the session's own locks are held, whatever poisoned them
+// left the data as consistent as any other pass that got cut short
+impl Shared {
+    fn control(&self) -> MutexGuard<'_, Control> {
+        self.control.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, control: MutexGuard<'a, Control>) -> MutexGuard<'a, Control> {
+        self.changed
+            .wait(control)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Renders passes of a `RayTracer` on a background thread until one of the stop
+/// conditions of its settings is met. The accumulated image can be read at any time
+/// while the workers keep going.
+///
+/// The renderer is only locked while a pass is dispatched or merged, `renderer()` gives
+/// access to its settings in between. Changes to the settings, the scene or the camera
+/// need a `restart()` to show up in the image. A renderer that already holds passes,
+/// e.g. resumed from a checkpoint, is continued instead of started over.
+pub struct RenderSession {
+    shared: Arc<Shared>,
+    renderer: Arc<Mutex<RayTracer>>,
+    accumulator: Arc<RwLock<Accumulator>>,
+    driver: Option<JoinHandle<()>>,
+    frame_buffer: Vec<u32>,
+    denoised: Option<(u64, Vec<Vec4>)>, // and the accumulator revision it was filtered from
+}
+
+impl RenderSession {
+    pub fn start(renderer: RayTracer, scene: Arc<RwLock<Scene>>, width: u32, height: u32) -> Self {
+        let resumed = renderer.get_passes() > 0;
+        let shared = Arc::new(Shared {
+            control: Mutex::new(Control {
+                paused: false,
+                cancelled: false,
+                restart: !resumed,
+                size: [width, height],
+                progress: RenderProgress {
+                    passes: renderer.get_passes(),
+                    target_passes: renderer.get_samples_per_pixel(),
+                    elapsed: renderer.get_elapsed(),
+                    ..Default::default()
+                },
+                error: None,
+                checkpoints: None,
+            }),
+            changed: Condvar::new(),
+            abort: renderer.abort_flag(),
+        });
+        let accumulator = renderer.shared_accumulator();
+        let renderer = Arc::new(Mutex::new(renderer));
+
+        let driver = {
+            let shared = Arc::clone(&shared);
+            let renderer = Arc::clone(&renderer);
+            let accumulator = Arc::clone(&accumulator);
+            thread::spawn(move || drive(&shared, &renderer, &accumulator, &scene))
+        };
+
+        Self {
+            shared,
+            renderer,
+            accumulator,
+            driver: Some(driver),
+            frame_buffer: vec![],
+            denoised: None,
+        }
+    }
+
+    /// Takes effect once the pass in flight is merged
+    pub fn pause(&self) {
+        self.command(false, |control| {
+            control.paused = true;
+            if control.progress.state == SessionState::Running {
+                control.progress.state = SessionState::Paused;
+            }
+        });
+    }
+
+    pub fn resume(&self) {
+        self.command(false, |control| {
+            control.paused = false;
+            if control.progress.state == SessionState::Paused {
+                control.progress.state = SessionState::Running;
+            }
+        });
+    }
+
+    /// Stops for good, the pass in flight is dropped and the image keeps the passes merged before
+    pub fn cancel(&self) {
+        self.command(true, |control| control.cancelled = true);
+    }
+
+    /// Drops the accumulated image and starts over, also after the session finished
+    pub fn restart(&self) {
+        self.command(true, Control::request_restart);
+    }
+
+    /// Saves a checkpoint to `path` after the first pass that ends `interval` after the
+    /// last one and when the render finishes. Failures are logged, the render goes on
+    pub fn save_checkpoints(&self, path: &str, interval: Duration) {
+        let path = path.to_string();
+        self.command(false, |control| {
+            control.checkpoints = Some(Checkpoints { path, interval })
+        });
+    }
+
+    /// Restarts at the new image size if it differs
+    pub fn resize(&self, width: u32, height: u32) {
+        let mut control = self.shared.control();
+        if control.size != [width, height] {
+            control.size = [width, height];
+            control.request_restart();
+            self.shared.abort.store(true, Ordering::Relaxed);
+            self.shared.changed.notify_all();
+        }
+    }
+
+    pub fn progress(&self) -> RenderProgress {
+        self.shared.control().progress
+    }
+
+    /// Error of the pass that stopped the session with `StopReason::Failed`,
+    /// the accumulated image keeps the passes merged before it
+    pub fn take_error(&self) -> Option<Error> {
+        self.shared.control().error.take()
+    }
+
+    /// Blocks until the session finishes or gets cancelled
+    pub fn wait(&self) -> RenderProgress {
+        let mut control = self.shared.control();
+        while control.is_busy() {
+            control = self.shared.wait(control);
+        }
+        control.progress
+    }
+
+    /// Blocks until the next pass is merged, the state changes or `timeout` passed
+    pub fn wait_for_progress(&self, timeout: Duration) -> RenderProgress {
+        let control = self.shared.control();
+        if !control.is_busy() {
+            return control.progress;
+        }
+        let (control, _) = self
+            .shared
+            .changed
+            .wait_timeout(control, timeout)
+            .unwrap_or_else(PoisonError::into_inner);
+        control.progress
+    }
+
+    /// Settings of the renderer, blocks while a pass is dispatched or merged.
+    /// Don't render with it while the session runs, the passes would mix
+    pub fn renderer(&self) -> MutexGuard<'_, RayTracer> {
+        self.renderer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Cancels the session and hands back the renderer with the accumulated image
+    pub fn into_renderer(mut self) -> RayTracer {
+        self.stop_driver();
+        let renderer = Arc::clone(&self.renderer);
+        drop(self);
+
+        let renderer = match Arc::try_unwrap(renderer) {
+            Ok(renderer) => renderer
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner),
+            Err(_) => unreachable!("the driver thread has exited"),
+        };
+        renderer.abort_flag().store(false, Ordering::Relaxed);
+        renderer
+    }
+
+    pub fn get_current_size(&self) -> [u32; 2] {
+        self.read_accumulator().get_resolution()
+    }
+
+    /// Passes merged so far through the display transform of the renderer, denoised when
+    /// that is on and the guides were recorded, bottom row first
+    pub fn get_output(&mut self) -> &[u32] {
+        let settings = self.renderer().get_settings();
+        if settings.denoise
+            && let Some(denoised) = self.denoise()
+        {
+            let display = settings.display;
+            self.frame_buffer = denoised
+                .iter()
+                .map(|&pixel| display.encode_argb(pixel))
+                .collect();
+            return &self.frame_buffer;
+        }
+
+        let accum_guard = self
+            .accumulator
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        accum_guard.write_to_image_buffer(&mut self.frame_buffer, &settings.display);
+        drop(accum_guard);
+        &self.frame_buffer
+    }
+
+    /// Denoised linear radiance of the passes merged so far, `None` unless the guides were
+    /// recorded since the last restart. Bottom row first, `get_hdr_output` keeps the noisy one
+    pub fn get_denoised_hdr_output(&mut self) -> Option<Vec<Vec3>> {
+        Some(
+            self.denoise()?
+                .iter()
+                .map(|pixel| pixel.truncate())
+                .collect(),
+        )
+    }
+
+    /// The denoiser has its guides, otherwise turning it on needs a restart
+    pub fn can_denoise(&self) -> bool {
+        denoise::has_guides(self.read_accumulator().get_aov_selection())
+    }
+
+    /// Filters only when a pass was merged since the last call, the accumulator
+    /// stays unlocked while it runs
+    fn denoise(&mut self) -> Option<&[Vec4]> {
+        let accum_guard = self.read_accumulator();
+        let revision = accum_guard.revision();
+        if self
+            .denoised
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != revision)
+        {
+            let input = accum_guard.denoise_input();
+            drop(accum_guard);
+            self.denoised = input.map(|input| (revision, input.run()));
+        }
+        self.denoised.as_ref().map(|(_, pixels)| pixels.as_slice())
+    }
+
+    /// Averaged linear radiance of the passes merged so far, bottom row first
+    pub fn get_hdr_output(&self) -> Vec<Vec3> {
+        let mut buffer = vec![];
+        self.read_accumulator()
+            .write_to_radiance_buffer(&mut buffer);
+        buffer
+    }
+
+    /// Averaged values of a recorded AOV, `aov.channels()` per pixel, bottom row first
+    pub fn get_aov(&self, aov: Aov) -> Option<Vec<f32>> {
+        self.read_accumulator().get_aov(aov)
+    }
+
+    /// False color image of a recorded AOV, bottom row first
+    pub fn get_aov_preview(&self, aov: Aov) -> Option<Vec<u32>> {
+        let display = self.renderer().get_display_transform();
+        self.get_aov(aov)
+            .map(|values| aov::preview(aov, &values, &display))
+    }
+
+    /// False color image of the samples taken per pixel, bottom row first
+    pub fn get_sample_heatmap(&self) -> Vec<u32> {
+        let mut buffer = vec![];
+        self.read_accumulator().write_to_heatmap_buffer(&mut buffer);
+        buffer
+    }
+
+    fn read_accumulator(&self) -> RwLockReadGuard<'_, Accumulator> {
+        self.accumulator
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The abort flag is set together with the command, the driver clears it under the
+    /// same lock before a pass, so no request to drop a pass gets lost
+    fn command(&self, abort: bool, apply: impl FnOnce(&mut Control)) {
+        let mut control = self.shared.control();
+        apply(&mut control);
+        if abort {
+            self.shared.abort.store(true, Ordering::Relaxed);
+        }
+        self.shared.changed.notify_all();
+    }
+
+    fn stop_driver(&mut self) {
+        if let Some(driver) = self.driver.take() {
+            self.cancel();
+            let _ = driver.join();
+        }
+    }
+}
+
+impl Drop for RenderSession {
+    fn drop(&mut self) {
+        self.stop_driver();
+    }
+}
+
+/// Background loop of a session. It never holds the control lock while it waits for the
+/// renderer, callers may keep a `renderer()` guard and still send commands
+fn drive(
+    shared: &Shared,
+    renderer: &Mutex<RayTracer>,
+    accumulator: &RwLock<Accumulator>,
+    scene: &Arc<RwLock<Scene>>,
+) {
+    // a resumed renderer carries on, the driver starts counting where it left off
+    let (mut accumulate, mut elapsed) = {
+        let renderer = renderer.lock().unwrap_or_else(PoisonError::into_inner);
+        (renderer.get_passes() > 0, renderer.get_elapsed())
+    };
+    let mut resumed_elapsed = elapsed; // rays of the resumed passes weren't counted
+    let mut rays_traced = 0;
+    let mut last_checkpoint = Instant::now();
+
+    loop {
+        let (size, checkpoints) = {
+            let mut control = shared.control();
+            loop {
+                if control.cancelled {
+                    control.progress.state = SessionState::Cancelled;
+                    control
+                        .progress
+                        .stop_reason
+                        .get_or_insert(StopReason::Cancelled);
+                    control.progress.eta = None;
+                    shared.changed.notify_all();
+                    return;
+                }
+                if control.restart {
+                    control.restart = false;
+                    control.error = None;
+                    (accumulate, rays_traced) = (false, 0);
+                    (elapsed, resumed_elapsed) = (Duration::ZERO, Duration::ZERO);
+                    control.progress = RenderProgress {
+                        state: match control.paused {
+                            true => SessionState::Paused,
+                            false => SessionState::Running,
+                        },
+                        target_passes: control.progress.target_passes,
+                        ..Default::default()
+                    };
+                    shared.changed.notify_all();
+                }
+                if !control.paused && control.progress.state != SessionState::Finished {
+                    break;
+                }
+                control = shared.wait(control);
+            }
+            shared.abort.store(false, Ordering::Relaxed);
+            (control.size, control.checkpoints.clone())
+        };
+
+        let pass = render_pass(shared, renderer, scene, size, accumulate, elapsed);
+        if let (Ok(Some(pass)), Some(checkpoints)) = (&pass, checkpoints)
+            && (pass.stop_reason.is_some() || last_checkpoint.elapsed() >= checkpoints.interval)
+        {
+            let renderer = renderer.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(e) = renderer.save_checkpoint(&checkpoints.path, scene) {
+                log::warn!("checkpoint {} not saved: {e}", checkpoints.path);
+            }
+            last_checkpoint = Instant::now();
+        }
+        let samples_per_pixel = accumulator
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .mean_sample_count();
+
+        let mut control = shared.control();
+        if control.restart || control.cancelled {
+            continue;
+        }
+        let pass = match pass {
+            Ok(Some(pass)) => pass,
+            Ok(None) => continue,
+            Err(error) => {
+                log::warn!("render pass failed: {error}");
+                control.error = Some(error);
+                control.progress.state = SessionState::Finished;
+                control.progress.stop_reason = Some(StopReason::Failed);
+                control.progress.eta = None;
+                shared.changed.notify_all();
+                continue;
+            }
+        };
+        accumulate = true;
+        rays_traced += pass.rays_traced;
+        elapsed += pass.render_time;
+
+        let progress = &mut control.progress;
+        progress.passes = pass.passes;
+        progress.target_passes = pass.target_passes;
+        progress.samples_per_pixel = samples_per_pixel;
+        progress.rays_per_second =
+            rays_traced as f64 / (elapsed - resumed_elapsed).as_secs_f64().max(1e-9);
+        progress.elapsed = elapsed;
+        let remaining = pass.target_passes.saturating_sub(pass.passes);
+        let mut eta = elapsed.mul_f64(remaining as f64 / pass.passes as f64);
+        if let Some(limit) = pass.time_limit {
+            eta = eta.min(limit.saturating_sub(elapsed));
+        }
+        progress.eta = Some(eta);
+        if pass.stop_reason.is_some() {
+            progress.state = SessionState::Finished;
+            progress.stop_reason = pass.stop_reason;
+            progress.eta = Some(Duration::ZERO);
+        }
+        shared.changed.notify_all();
+    }
+}
+
+/// What the driver needs to know about a merged pass
+struct Pass {
+    passes: u32, // of the renderer, an image that converged merges none
+    rays_traced: u64,
+    render_time: Duration,
+    stop_reason: Option<StopReason>,
+    time_limit: Option<Duration>,
+    target_passes: u32,
+}
+
+/// Dispatches and merges one pass, `None` if a command aborted it before the merge.
+/// The renderer is unlocked while the workers render
+fn render_pass(
+    shared: &Shared,
+    renderer: &Mutex<RayTracer>,
+    scene: &Arc<RwLock<Scene>>,
+    size: [u32; 2],
+    accumulate: bool,
+    elapsed: Duration,
+) -> Result<Option<Pass>> {
+    let lock_renderer = || renderer.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let started = Instant::now();
+    let (tile_count, results, target_passes) = {
+        let mut renderer = lock_renderer();
+        let Some(tile_count) = renderer.dispatch_pass(scene, size[0], size[1], accumulate)? else {
+            return Ok(Some(Pass {
+                passes: renderer.get_passes(),
+                rays_traced: 0,
+                render_time: Duration::ZERO,
+                stop_reason: Some(StopReason::NoiseThreshold),
+                time_limit: renderer.get_time_limit(),
+                target_passes: renderer.get_samples_per_pixel(),
+            }));
+        };
+        (
+            tile_count,
+            renderer.tile_results(),
+            renderer.get_samples_per_pixel(),
+        )
+    };
+    let results: Vec<_> = results.iter().take(tile_count).collect();
+
+    // restarted or cancelled meanwhile, the tiles may be cut short
+    if shared.abort.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    let tiles: Vec<_> = results.into_iter().collect::<Result<_>>()?;
+    let rays_traced = tiles.iter().map(|tile| tile.rays_traced).sum();
+    let mut renderer = lock_renderer();
+    renderer.merge_pass(tiles, started)?;
+    let render_time = renderer.get_last_render_time();
+
+    Ok(Some(Pass {
+        passes: renderer.get_passes(),
+        rays_traced,
+        render_time,
+        stop_reason: renderer.stop_reason(elapsed + render_time),
+        time_limit: renderer.get_time_limit(),
+        target_passes,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::renderer::RenderSettings;
+
+    #[test]
+    fn session_matches_blocking_render() {
+        let settings = RenderSettings {
+            samples_per_pixel: 4,
+            ..Default::default()
+        };
+        let (width, height) = (70, 50);
+        let scene = Arc::new(RwLock::new(Scene::get_example_scene()));
+
+        let mut blocking = RayTracer::with_settings(width, height, settings);
+        for pass in 0..4 {
+            blocking.render(&scene, width, height, pass > 0).unwrap();
+        }
+
+        let session = RenderSession::start(
+            RayTracer::with_settings(width, height, settings),
+            Arc::clone(&scene),
+            width,
+            height,
+        );
+        let progress = session.wait();
+        assert_eq!(progress.state, SessionState::Finished);
+        assert_eq!(progress.stop_reason, Some(StopReason::SampleCount));
+        assert_eq!(progress.passes, 4);
+        assert_eq!(progress.samples_per_pixel, 4.0);
+        assert!(progress.rays_per_second > 0.0);
+        assert!(session.get_hdr_output() == blocking.get_hdr_output());
+
+        // a restart renders the same passes again, a cancelled session stays stopped
+        session.restart();
+        session.cancel();
+        assert_eq!(session.wait().state, SessionState::Cancelled);
+        session.restart();
+        assert_eq!(session.wait().state, SessionState::Cancelled);
+
+        let mut renderer = session.into_renderer();
+        renderer.render(&scene, width, height, false).unwrap();
+        assert_eq!(renderer.get_hdr_output().len(), (width * height) as usize);
+    }
+
+    #[test]
+    fn paused_session_waits_and_resize_starts_over() {
+        let settings = RenderSettings {
+            samples_per_pixel: 8,
+            ..Default::default()
+        };
+        let scene = Arc::new(RwLock::new(Scene::get_example_scene()));
+        let session = RenderSession::start(
+            RayTracer::with_settings(70, 50, settings),
+            Arc::clone(&scene),
+            70,
+            50,
+        );
+
+        // the resize aborts the pass in flight, if any, and drops the merged ones
+        session.pause();
+        session.resize(40, 30);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while session.progress().passes != 0 || session.shared.control().restart {
+            assert!(Instant::now() < deadline, "the restart wasn't picked up");
+            session.wait_for_progress(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(100));
+        let progress = session.progress();
+        assert_eq!(progress.state, SessionState::Paused);
+        assert_eq!(progress.passes, 0);
+
+        session.resume();
+        let progress = session.wait();
+        assert_eq!(progress.state, SessionState::Finished);
+        assert_eq!(progress.passes, 8);
+        assert_eq!(session.get_current_size(), [40, 30]);
+
+        let mut blocking = RayTracer::with_settings(40, 30, settings);
+        for pass in 0..8 {
+            blocking.render(&scene, 40, 30, pass > 0).unwrap();
+        }
+        assert!(session.get_hdr_output() == blocking.get_hdr_output());
+    }
+}
@@ -0,0 +1,160 @@
+use std::f32::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+
+/// Order in which the tiles of a pass are handed to the workers. The image doesn't
+/// depend on it, tiles are merged in a fixed order
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Rows of tiles from the top of the image
+    Scanline,
+    /// Rings around the center, the part usually looked at shows up first
+    #[default]
+    Spiral,
+    /// Along a Hilbert curve, consecutive tiles stay close and share cache
+    Hilbert,
+}
+
+impl TileOrder {
+    pub const ALL: [TileOrder; 3] = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Scanline => "scanline",
+            Self::Spiral => "spiral",
+            Self::Hilbert => "hilbert",
+        }
+    }
+}
+
+impl fmt::Display for TileOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|order| order.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(TileOrder::as_str).collect();
+                format!(
+                    "unknown tile order '{s}', expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Pixel rectangle rendered by one job, tiles at the right and top edges may be smaller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Tiles covering a `width` x `height` image, in dispatch order
+pub(crate) fn tile_grid(width: u32, height: u32, tile_size: u32, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let columns = width.div_ceil(tile_size);
+    let rows = height.div_ceil(tile_size);
+
+    let mut cells: Vec<(u32, u32)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+
+    match order {
+        // rows start at the bottom of the image
+        TileOrder::Scanline => cells.sort_by_key(|&(column, row)| (rows - row, column)),
+        TileOrder::Spiral => {
+            let center = (columns as f32 / 2.0, rows as f32 / 2.0);
+            cells.sort_by_cached_key(|&(column, row)| {
+                let dx = column as f32 + 0.5 - center.0;
+                let dy = row as f32 + 0.5 - center.1;
+                let ring = dx.abs().max(dy.abs()).floor() as u32;
+                // clockwise from the top within a ring
+                let angle = (dx.atan2(dy) + TAU) % TAU;
+                (ring, (angle * 1000.0) as u32)
+            });
+        }
+        TileOrder::Hilbert => {
+            let side = columns.max(rows).next_power_of_two();
+            cells.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+        }
+    }
+
+    cells
+        .into_iter()
+        .map(|(column, row)| {
+            let (x, y) = (column * tile_size, row * tile_size);
+            Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+/// Distance along the Hilbert curve filling a `side` x `side` grid, `side` is a power of two
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+
+        // rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn orders_cover_every_pixel_once() {
+        let (width, height) = (150, 70);
+        for order in TileOrder::ALL {
+            let tiles = tile_grid(width, height, 32, order);
+            assert_eq!(tiles.len(), 5 * 3);
+
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in &tiles {
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&count| count == 1), "{order}");
+        }
+
+        let spiral = tile_grid(width, height, 32, TileOrder::Spiral);
+        assert_eq!((spiral[0].x, spiral[0].y), (64, 32));
+
+        // consecutive tiles of the curve are neighbours
+        let hilbert = tile_grid(64, 64, 8, TileOrder::Hilbert);
+        for pair in hilbert.windows(2) {
+            let distance = pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y);
+            assert_eq!(distance, 8);
+        }
+    }
+}
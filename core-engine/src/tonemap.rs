@@ -0,0 +1,231 @@
+use std::fmt;
+use std::str::FromStr;
+
+use glam::{Mat3, Vec3, Vec4};
+
+use crate::utils::convert_to_argb;
+
+/// Curve compressing linear radiance into the displayable range
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapper {
+    /// Clamps at 1, only useful with a hand picked exposure
+    Linear,
+    /// Reinhard extended, radiance at the white point maps to 1
+    #[default]
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference and output transforms
+    Aces,
+    /// Troy Sobotka's AgX with the default look, desaturates bright colors instead of clipping them
+    Agx,
+    /// John Hable's Uncharted 2 filmic curve
+    Hable,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 5] = [
+        ToneMapper::Linear,
+        ToneMapper::Reinhard,
+        ToneMapper::Aces,
+        ToneMapper::Agx,
+        ToneMapper::Hable,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::Reinhard => "reinhard",
+            Self::Aces => "aces",
+            Self::Agx => "agx",
+            Self::Hable => "hable",
+        }
+    }
+}
+
+impl fmt::Display for ToneMapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ToneMapper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|tone_mapper| tone_mapper.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(ToneMapper::as_str).collect();
+                format!(
+                    "unknown tone mapper '{s}', expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Turns accumulated linear radiance into display pixels: exposure, tone curve and sRGB encoding.
+/// Only applied when reading the image, changing it never restarts a render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    pub tone_mapper: ToneMapper,
+    pub exposure: f32,    // stops, every stop doubles the radiance
+    pub white_point: f32, // radiance mapped to white by Reinhard, ignored by the other curves
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            tone_mapper: ToneMapper::default(),
+            exposure: 0.0,
+            white_point: 16.0,
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// sRGB encoded color in [0, 1] for linear radiance
+    pub fn apply(&self, radiance: Vec3) -> Vec3 {
+        let exposed = radiance.max(Vec3::ZERO) * self.exposure.exp2();
+        let mapped = match self.tone_mapper {
+            ToneMapper::Linear => exposed,
+            ToneMapper::Reinhard => {
+                let white = self.white_point.max(1e-3);
+                exposed * (Vec3::ONE + exposed / (white * white)) / (Vec3::ONE + exposed)
+            }
+            ToneMapper::Aces => aces_fitted(exposed),
+            ToneMapper::Agx => agx(exposed),
+            ToneMapper::Hable => {
+                // exposure bias and linear white of the original
+                const WHITE: f32 = 11.2;
+                hable_partial(exposed * 2.0) / hable_partial(Vec3::splat(WHITE))
+            }
+        };
+
+        srgb_encode(mapped.clamp(Vec3::ZERO, Vec3::ONE))
+    }
+
+    /// Display pixel as 0xAARRGGBB, alpha is coverage and bypasses the transform
+    pub(crate) fn encode_argb(&self, radiance: Vec4) -> u32 {
+        let color = self.apply(radiance.truncate());
+        convert_to_argb(&Vec4::from((color, radiance.w.clamp(0.0, 1.0))))
+    }
+}
+
+/// Piecewise sRGB transfer function of IEC 61966-2-1
+fn srgb_encode(linear: Vec3) -> Vec3 {
+    linear.map(|c| match c <= 0.003_130_8 {
+        true => c * 12.92,
+        false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+    })
+}
+
+fn aces_fitted(color: Vec3) -> Vec3 {
+    // sRGB to the reference rendering transform space, row major
+    const INPUT: [f32; 9] = [
+        0.59719, 0.35458, 0.04823, //
+        0.07600, 0.90834, 0.01566, //
+        0.02840, 0.13383, 0.83777,
+    ];
+    // output device transform space back to sRGB, row major
+    const OUTPUT: [f32; 9] = [
+        1.60475, -0.53108, -0.07367, //
+        -0.10208, 1.10813, -0.00605, //
+        -0.00327, -0.07276, 1.07602,
+    ];
+
+    let v = Mat3::from_cols_array(&INPUT).transpose() * color;
+    let a = v * (v + 0.024_578_6) - 0.000_090_537;
+    let b = v * (0.983_729 * v + 0.432_951) + 0.238_081;
+    Mat3::from_cols_array(&OUTPUT).transpose() * (a / b)
+}
+
+fn agx(color: Vec3) -> Vec3 {
+    // inset into the AgX working space and back out, by columns
+    const INSET: Mat3 = Mat3::from_cols(
+        Vec3::new(0.842_479_06, 0.042_328_24, 0.042_375_655),
+        Vec3::new(0.078_433_6, 0.878_468_6, 0.078_433_6),
+        Vec3::new(0.079_223_745, 0.079_166_13, 0.879_143),
+    );
+    const OUTSET: Mat3 = Mat3::from_cols(
+        Vec3::new(1.196_879, -0.052_896_85, -0.052_971_635),
+        Vec3::new(-0.098_020_88, 1.151_903_1, -0.098_043_45),
+        Vec3::new(-0.099_029_74, -0.098_961_18, 1.151_073_7),
+    );
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+
+    let v = INSET * color.max(Vec3::splat(1e-10));
+    let x = (v
+        .map(f32::log2)
+        .clamp(Vec3::splat(MIN_EV), Vec3::splat(MAX_EV))
+        - MIN_EV)
+        / (MAX_EV - MIN_EV);
+
+    // polynomial fit of the default contrast sigmoid
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+
+    // the curve ends display encoded with a 2.2 power, undo it so sRGB encoding applies once
+    (OUTSET * curve).max(Vec3::ZERO).powf(2.2)
+}
+
+fn hable_partial(x: Vec3) -> Vec3 {
+    const A: f32 = 0.15; // shoulder strength
+    const B: f32 = 0.50; // linear strength
+    const C: f32 = 0.10; // linear angle
+    const D: f32 = 0.20; // toe strength
+    const E: f32 = 0.02; // toe numerator
+    const F: f32 = 0.30; // toe denominator
+
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn curves_map_black_to_black_and_stay_monotonic() {
+        for tone_mapper in ToneMapper::ALL {
+            let transform = DisplayTransform {
+                tone_mapper,
+                ..Default::default()
+            };
+            assert!(
+                transform.apply(Vec3::ZERO).max_element() < 0.01,
+                "{tone_mapper}"
+            );
+
+            let mut previous = 0.0;
+            for i in 1..200 {
+                let value = transform.apply(Vec3::splat(i as f32 * 0.05)).x;
+                assert!(value >= previous, "{tone_mapper} decreases at {i}");
+                assert!(value <= 1.0, "{tone_mapper} exceeds 1 at {i}");
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn reinhard_white_point_and_exposure() {
+        let transform = DisplayTransform {
+            tone_mapper: ToneMapper::Reinhard,
+            exposure: 1.0,
+            white_point: 4.0,
+        };
+        // one stop up puts 2 on the white point
+        assert!((transform.apply(Vec3::splat(2.0)).x - 1.0).abs() < 1e-5);
+
+        let linear = DisplayTransform {
+            tone_mapper: ToneMapper::Linear,
+            ..Default::default()
+        };
+        // both sides of the sRGB curve
+        assert!((linear.apply(Vec3::splat(0.002)).x - 0.02584).abs() < 1e-5);
+        assert!((linear.apply(Vec3::splat(0.5)).x - 0.735_357).abs() < 1e-5);
+    }
+}
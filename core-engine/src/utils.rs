@@ -1,3 +1,5 @@
+use std::hash::Hasher;
+
 use glam::Vec4;
 
 /// Converts 0.0 to 1.0 to u32 in format 0xAARRGGBB
@@ -7,3 +9,82 @@ pub(super) fn convert_to_argb(color: &Vec4) -> u32 /* Results Color */ {
         | (((color.y * 255.0) as u32) << 8)
         | ((color.z * 255.0) as u32)
 }
+
+/// Blue over green and yellow to red as `t` goes from 0 to 1, for false color images
+pub(crate) fn heatmap_color(t: f32) -> Vec4 {
+    const STOPS: [Vec4; 5] = [
+        Vec4::new(0.0, 0.0, 0.5, 1.0),
+        Vec4::new(0.0, 0.5, 1.0, 1.0),
+        Vec4::new(0.0, 0.9, 0.3, 1.0),
+        Vec4::new(1.0, 0.9, 0.0, 1.0),
+        Vec4::new(0.9, 0.0, 0.0, 1.0),
+    ];
+
+    let position = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let index = (position as usize).min(STOPS.len() - 2);
+    STOPS[index].lerp(STOPS[index + 1], position - index as f32)
+}
+
+/// Reverses row order, the renderer stores the bottom row first while images store the top row first
+pub(crate) fn flip_rows<T: Copy>(pixels: &[T], width: u32) -> Vec<T> {
+    pixels
+        .chunks_exact(width.max(1) as usize)
+        .rev()
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// FNV-1a with little endian integers, unlike `DefaultHasher` its values stay the
+/// same across platforms and Rust versions, so they can be stored in files
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl StableHasher {
+    pub fn write_floats(&mut self, values: impl IntoIterator<Item = f32>) {
+        values
+            .into_iter()
+            .for_each(|value| self.write_u32(value.to_bits()));
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+}
+
+/// Path in the temp dir for files a test writes, unique per test process so
+/// parallel runs don't clobber each other. The test removes it when done
+#[cfg(test)]
+pub(crate) fn test_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("insploray_{}_{name}", std::process::id()))
+}
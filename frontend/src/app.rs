@@ -1,6 +1,9 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use insploray::Vec2;
+use insploray::cameras::{CameraKind, CameraModel, FisheyeMapping};
+use insploray::renderer::{Aov, Filter, FilterKind, SamplerKind, StopReason, TileOrder, ToneMapper};
+use insploray::session::SessionState;
 use imgui::{TextureId};
 use winit::application::ApplicationHandler;
 use winit::event::{Event, WindowEvent};
@@ -40,7 +43,7 @@ impl App {
         let frame = match window.surface.get_current_texture() {
             Ok(frame) => frame,
             Err(e) => {
-                eprintln!("dropped frame: {e:?}");
+                log::warn!("dropped frame: {e:?}");
                 return;
             }
         };
@@ -73,25 +76,29 @@ impl App {
                     if ui.is_window_focused() {
 
                         // let camera = self.viewport_renderer.active_camera;
-                        self.viewport.handle_input(
-                            ui,
-                            width,
-                            height,
-                        );
+                        self.viewport.handle_input(ui);
                     }
 
-                    // if !self.first_buffer {
-                        self.viewport.renderer
-                            .prepare_pixels(&self.viewport.scene, width, height);
-                        self.first_buffer = true;
-
-                    // }
-
-                    let [c_w, c_h] = self.viewport.renderer.get_current_size();
-
-                    let dimensions = self.viewport.renderer.get_current_size();
+                    // passes render in the background, the window only restarts on resize
+                    self.viewport.session.resize(width, height);
+                    self.first_buffer = true;
+
+                    let [c_w, c_h] = self.viewport.session.get_current_size();
+
+                    let dimensions = self.viewport.session.get_current_size();
+                    let heatmap;
+                    let aov_preview = self.viewport.view_aov
+                        .and_then(|aov| self.viewport.session.get_aov_preview(aov));
+                    let pixels = if self.viewport.show_heatmap {
+                        heatmap = self.viewport.session.get_sample_heatmap();
+                        &heatmap
+                    } else if let Some(preview) = &aov_preview {
+                        preview
+                    } else {
+                        self.viewport.session.get_output()
+                    };
                     let texture_id = create_texture_from_pixels(
-                        self.viewport.renderer.get_output(),
+                        pixels,
                         dimensions,
                         &window.device,
                         &window.queue,
@@ -103,6 +110,18 @@ impl App {
                         .uv1(Vec2::new(1.0, 0.0))
                         .build(ui);
 
+                    if self.viewport.picking_focus && ui.is_item_clicked() {
+                        // the image is drawn flipped, output rows start at the bottom
+                        let [mouse_x, mouse_y] = ui.io().mouse_pos;
+                        let [left, top] = ui.item_rect_min();
+                        let x = (mouse_x - left).max(0.0) as u32;
+                        let y = c_h.saturating_sub(1 + (mouse_y - top).max(0.0) as u32);
+                        if x < c_w && y < c_h {
+                            self.viewport.focus_at(x, y);
+                        }
+                        self.viewport.picking_focus = false;
+                    }
+
                     frame_id = Some(texture_id);
                 });
 
@@ -112,45 +131,276 @@ impl App {
                 .size([300.0, 200.0], imgui::Condition::FirstUseEver)
                 .position([500.0, 200.0], imgui::Condition::FirstUseEver)
                 .build(|| {
-                    let duration = self.viewport.renderer.get_last_render_time();
-                    ui.text(format!("Last Render : {duration:?}"));
+                    let progress = self.viewport.session.progress();
+                    ui.text(format!("{:?} : {}/{} spp ({:.1} avg)",
+                        progress.state,
+                        progress.passes,
+                        progress.target_passes,
+                        progress.samples_per_pixel,
+                    ));
+                    ui.text(format!("{:.2} Mrays/s, elapsed {:.1?}, ETA {:.1?}",
+                        progress.rays_per_second / 1e6,
+                        progress.elapsed,
+                        progress.eta.unwrap_or_default(),
+                    ));
+                    // the session already logged it
+                    if let Some(e) = self.viewport.session.take_error() {
+                        self.viewport.render_error = Some(e.to_string());
+                    }
+                    if let Some(reason) = progress.stop_reason {
+                        ui.text(format!("Stopped, {reason}"));
+                    }
+                    if progress.stop_reason == Some(StopReason::Failed)
+                        && let Some(e) = &self.viewport.render_error {
+                        ui.text_wrapped(e);
+                    }
                     ui.text(format!("Image : {viewport_size:?}"));
-                    ui.button("Render").then(|| {
-                        self.viewport.renderer
-                            .render(&self.viewport.scene,
-                                viewport_size[0] as u32,
-                                viewport_size[1] as u32,
-                                false
-                            );
-                    });
-
-                    let camera = self.viewport.camera.read().unwrap();
-                    let mut focal_length = camera.focal_length;
-                    let mut sensor_size = camera.sensor_size;
-                    drop(camera);
-
-                    if imgui::Drag::new("Focal Length")
-                        .build(ui, &mut focal_length) && focal_length > 0.0  {
-                        self.viewport.camera.write().unwrap()
-                            .set_focal_length(focal_length);
-                        self.viewport.renderer.render_updated(&self.viewport.scene,
-                            viewport_size[0] as u32,
-                            viewport_size[1] as u32,
-                        );
+                    ui.button("Restart").then(|| self.viewport.session.restart());
+                    ui.same_line();
+                    if progress.state == SessionState::Paused {
+                        ui.button("Resume").then(|| self.viewport.session.resume());
+                    } else {
+                        ui.button("Pause").then(|| self.viewport.session.pause());
+                    }
+
+                    let mut samples_per_pixel = self.viewport.session.renderer().get_samples_per_pixel();
+                    if imgui::Drag::new("Samples")
+                        .range(1, 65536)
+                        .build(ui, &mut samples_per_pixel) {
+                        self.viewport.session.renderer().set_samples_per_pixel(samples_per_pixel.max(1));
+                        self.viewport.session.restart();
+                    }
+
+                    // 0 renders without a time limit
+                    let time_limit = self.viewport.session.renderer().get_time_limit();
+                    let mut seconds = time_limit.map_or(0.0, |limit| limit.as_secs_f32());
+                    if imgui::Drag::new("Time Limit (s)")
+                        .range(0.0, 3600.0)
+                        .speed(0.1)
+                        .build(ui, &mut seconds) {
+                        // typed in values skip the drag range, negative ones mean none
+                        let seconds = seconds.min(3600.0);
+                        let time_limit = Duration::try_from_secs_f32(seconds).ok()
+                            .filter(|limit| !limit.is_zero());
+                        self.viewport.session.renderer().set_time_limit(time_limit);
+                        self.viewport.session.restart();
+                    }
+
+                    // edits a copy, tiles in flight hold the camera lock
+                    let mut camera = self.viewport.camera.read().unwrap().clone();
+                    let mut camera_changed = false;
+
+                    let camera_names = CameraKind::ALL.map(|kind| kind.as_str());
+                    let mut camera_index = CameraKind::ALL.iter()
+                        .position(|&kind| kind == camera.kind())
+                        .unwrap_or(0);
+                    if ui.combo_simple_string("Camera", &mut camera_index, &camera_names) {
+                        let placement = camera.pinhole().clone();
+                        camera = CameraModel::new(CameraKind::ALL[camera_index], placement);
+                        camera_changed = true;
+                    }
+
+                    // the field of view only applies to the perspective cameras
+                    if matches!(camera.kind(), CameraKind::Pinhole | CameraKind::ThinLens) {
+                        let mut focal_length = camera.pinhole().focal_length;
+                        let mut sensor_size = camera.pinhole().sensor_size;
+
+                        if imgui::Drag::new("Focal Length")
+                            .build(ui, &mut focal_length) && focal_length > 0.0  {
+                            camera.pinhole_mut().set_focal_length(focal_length);
+                            camera_changed = true;
+                        }
+
+                        if imgui::Drag::new("Sensor Size")
+                            .build(ui, &mut sensor_size) && sensor_size > 0.0 {
+                            camera.pinhole_mut().set_sensor_size(sensor_size);
+                            camera_changed = true;
+                        }
+                    }
+
+                    match &mut camera {
+                        CameraModel::Pinhole(_) => {}
+                        CameraModel::ThinLens(lens) => {
+                            let aperture = &mut lens.aperture;
+                            camera_changed |= imgui::Drag::new("F-Stop").range(0.5, 64.0)
+                                .speed(0.05)
+                                .build(ui, &mut aperture.f_stop);
+                            camera_changed |= imgui::Drag::new("Focus Distance").range(0.01, f32::MAX)
+                                .speed(0.01)
+                                .build(ui, &mut aperture.focus_distance);
+                            camera_changed |= imgui::Drag::new("Aperture Blades").range(0, 16)
+                                .build(ui, &mut aperture.blades);
+                            camera_changed |= ui.slider_config("Blade Rotation", 0.0, std::f32::consts::TAU)
+                                .build(&mut aperture.rotation);
+
+                            // typed in values skip the drag range
+                            aperture.f_stop = aperture.f_stop.max(0.5);
+                            aperture.focus_distance = aperture.focus_distance.max(0.01);
+
+                            ui.checkbox("Click to Focus", &mut self.viewport.picking_focus);
+                        }
+                        CameraModel::Orthographic(ortho) => {
+                            camera_changed |= imgui::Drag::new("Ortho Scale").range(0.01, f32::MAX)
+                                .speed(0.01)
+                                .build(ui, &mut ortho.ortho_scale);
+                            ortho.ortho_scale = ortho.ortho_scale.max(0.01);
+                        }
+                        CameraModel::Fisheye(fisheye) => {
+                            camera_changed |= ui.slider_config("Field of View", 0.1, std::f32::consts::TAU)
+                                .build(&mut fisheye.fov);
+
+                            let mapping_names = FisheyeMapping::ALL.map(|mapping| mapping.as_str());
+                            let mut mapping_index = FisheyeMapping::ALL.iter()
+                                .position(|&mapping| mapping == fisheye.mapping)
+                                .unwrap_or(0);
+                            if ui.combo_simple_string("Mapping", &mut mapping_index, &mapping_names) {
+                                fisheye.mapping = FisheyeMapping::ALL[mapping_index];
+                                camera_changed = true;
+                            }
+                        }
+                        CameraModel::Equirectangular(panorama) => {
+                            camera_changed |= ui.checkbox("Stereo (over/under)", &mut panorama.stereo);
+                            camera_changed |= imgui::Drag::new("Eye Separation").range(0.0, 1.0)
+                                .speed(0.001)
+                                .build(ui, &mut panorama.eye_separation);
+                        }
+                    }
+                    if camera_changed {
+                        *self.viewport.camera.write().unwrap() = camera;
+                        self.viewport.session.restart();
                     }
 
-                    if imgui::Drag::new("Sensor Size")
-                        .build(ui, &mut sensor_size) && sensor_size > 0.0 {
-                        self.viewport.camera.write().unwrap()
-                            .set_sensor_size(sensor_size);
-                        self.viewport.renderer.render_updated(&self.viewport.scene,
-                            viewport_size[0] as u32,
-                            viewport_size[1] as u32,
-                        );
+                    let sampler = self.viewport.session.renderer().get_sampler();
+                    let sampler_names = SamplerKind::ALL.map(|kind| kind.as_str());
+                    let mut sampler_index = SamplerKind::ALL.iter()
+                        .position(|&kind| kind == sampler)
+                        .unwrap_or(0);
+                    if ui.combo_simple_string("Sampler", &mut sampler_index, &sampler_names) {
+                        self.viewport.session.renderer().set_sampler(SamplerKind::ALL[sampler_index]);
+                        self.viewport.session.restart();
+                    }
+
+                    let mut filter = self.viewport.session.renderer().get_filter();
+                    let filter_names = FilterKind::ALL.map(|kind| kind.as_str());
+                    let mut filter_index = FilterKind::ALL.iter()
+                        .position(|&kind| kind == filter.kind)
+                        .unwrap_or(0);
+                    let mut filter_changed = false;
+                    if ui.combo_simple_string("Filter", &mut filter_index, &filter_names) {
+                        filter = Filter::new(FilterKind::ALL[filter_index]);
+                        filter_changed = true;
+                    }
+                    if imgui::Drag::new("Filter Radius")
+                        .range(0.5, 4.0)
+                        .speed(0.01)
+                        .build(ui, &mut filter.radius) && filter.radius > 0.0 {
+                        filter_changed = true;
+                    }
+                    if filter_changed {
+                        self.viewport.session.renderer().set_filter(filter);
+                        self.viewport.session.restart();
+                    }
+
+                    // 0 keeps sampling every pixel
+                    let mut noise_threshold = self.viewport.session.renderer().get_noise_threshold();
+                    if imgui::Drag::new("Noise Threshold")
+                        .range(0.0, 0.5)
+                        .speed(0.001)
+                        .build(ui, &mut noise_threshold) {
+                        self.viewport.session.renderer().set_noise_threshold(noise_threshold.max(0.0));
+                        self.viewport.session.restart();
+                    }
+                    ui.checkbox("Sample Heatmap", &mut self.viewport.show_heatmap);
+
+                    // only changes how fast the image builds up, no restart needed
+                    let tile_order = self.viewport.session.renderer().get_tile_order();
+                    let order_names = TileOrder::ALL.map(|order| order.as_str());
+                    let mut order_index = TileOrder::ALL.iter()
+                        .position(|&order| order == tile_order)
+                        .unwrap_or(0);
+                    if ui.combo_simple_string("Tile Order", &mut order_index, &order_names) {
+                        self.viewport.session.renderer().set_tile_order(TileOrder::ALL[order_index]);
+                    }
+                    let mut tile_size = self.viewport.session.renderer().get_tile_size();
+                    if imgui::Drag::new("Tile Size")
+                        .range(8, 256)
+                        .build(ui, &mut tile_size) {
+                        self.viewport.session.renderer().set_tile_size(tile_size);
+                    }
+
+                    // recorded from the next restart on
+                    let mut aovs = self.viewport.session.renderer().get_aovs();
+                    if ui.collapsing_header("AOVs", imgui::TreeNodeFlags::empty()) {
+                        let mut aovs_changed = false;
+                        for aov in Aov::ALL {
+                            let mut enabled = aovs.contains(aov);
+                            if ui.checkbox(aov.as_str(), &mut enabled) {
+                                if enabled {
+                                    aovs.insert(aov);
+                                } else {
+                                    aovs.remove(aov);
+                                }
+                                aovs_changed = true;
+                            }
+                        }
+                        if aovs_changed {
+                            self.viewport.session.renderer().set_aovs(aovs);
+                            self.viewport.session.restart();
+                        }
+                    }
+                    let views: Vec<Option<Aov>> = std::iter::once(None)
+                        .chain(aovs.iter().map(Some))
+                        .collect();
+                    let view_names: Vec<&str> = views.iter()
+                        .map(|view| view.map_or("beauty", |aov| aov.as_str()))
+                        .collect();
+                    let mut view_index = views.iter()
+                        .position(|&view| view == self.viewport.view_aov)
+                        .unwrap_or(0);
+                    if ui.combo_simple_string("View", &mut view_index, &view_names) {
+                        self.viewport.view_aov = views[view_index];
+                    }
+                    if self.viewport.view_aov.is_some_and(|aov| !aovs.contains(aov)) {
+                        self.viewport.view_aov = None;
+                    }
+
+                    // guides are only recorded by a fresh render
+                    let mut denoise = self.viewport.session.renderer().get_denoise();
+                    if ui.checkbox("Denoise", &mut denoise) {
+                        self.viewport.session.renderer().set_denoise(denoise);
+                        if denoise && !self.viewport.session.can_denoise() {
+                            self.viewport.session.restart();
+                        }
+                    }
+
+                    // only applied when the image is read, no restart needed
+                    let mut display = self.viewport.session.renderer().get_display_transform();
+                    let mut display_changed = false;
+                    let tone_mapper_names = ToneMapper::ALL.map(|tone_mapper| tone_mapper.as_str());
+                    let mut tone_mapper_index = ToneMapper::ALL.iter()
+                        .position(|&tone_mapper| tone_mapper == display.tone_mapper)
+                        .unwrap_or(0);
+                    if ui.combo_simple_string("Tone Mapper", &mut tone_mapper_index, &tone_mapper_names) {
+                        display.tone_mapper = ToneMapper::ALL[tone_mapper_index];
+                        display_changed = true;
+                    }
+                    display_changed |= imgui::Drag::new("Exposure")
+                        .range(-10.0, 10.0)
+                        .speed(0.01)
+                        .build(ui, &mut display.exposure);
+                    if display.tone_mapper == ToneMapper::Reinhard {
+                        display_changed |= imgui::Drag::new("White Point")
+                            .range(0.1, 100.0)
+                            .speed(0.05)
+                            .build(ui, &mut display.white_point);
+                        display.white_point = display.white_point.max(0.1);
+                    }
+                    if display_changed {
+                        self.viewport.session.renderer().set_display_transform(display);
                     }
                 });
             
-            self.viewport.draw_scene_setting_window(ui, &viewport_size);
+            self.viewport.draw_scene_setting_window(ui);
         }
 
         let mut encoder = window
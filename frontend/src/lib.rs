@@ -1,3 +1,4 @@
 pub mod app;
 pub mod constants;
+pub mod logger;
 pub mod ui;
@@ -0,0 +1,34 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Prints engine and UI log messages to stderr, the window has no log view
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        eprintln!("{level}: {}", record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    // only fails if a logger is already set
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(LevelFilter::Warn);
+}
@@ -1,8 +1,11 @@
 use winit::event_loop::{ControlFlow, EventLoop};
 
 use frontend::app::App;
+use frontend::logger;
 
 fn main() {
+    logger::init();
+
     let event_loop = EventLoop::new().unwrap();
 
     // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't
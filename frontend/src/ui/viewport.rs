@@ -1,42 +1,71 @@
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{PoisonError, RwLock};
 
 use imgui::Ui;
 
-use insploray::renderer::RayTracer;
-use insploray::scene::Scene;
+use insploray::renderer::{Aov, RayTracer};
+use insploray::session::RenderSession;
+use insploray::scene::{Scene, EXAMPLE_SKYBOX_PATH};
 use insploray::cameras::Camera;
-use insploray::cameras::PinholeCamera;
-use insploray::scene::{Sphere, Matrial};
+use insploray::cameras::{CameraModel, PinholeCamera};
+use insploray::scene::{Sphere, Matrial, MaterialType};
+use insploray::file_formats::{ObjModel, SceneFile};
 use insploray::Vec3;
 
+/// Change made in the scene window, applied once the window is drawn
+enum SceneEdit {
+    Sphere(usize, Sphere),
+    AddSphere(Sphere),
+    AddObj(ObjModel),
+    Material(usize, Matrial),
+    AddMaterial(Matrial),
+    SkyColor(Vec3),
+    Load(Box<SceneFile>),
+}
+
 pub struct Viewport {
-    pub renderer : RayTracer,
+    pub session : RenderSession,
     pub scene : Arc<RwLock<Scene>>,
-    pub camera : Arc<RwLock<PinholeCamera>>
+    pub camera : Arc<RwLock<CameraModel>>,
+    pub picking_focus : bool, // next click in the viewport sets the focus distance
+    pub show_heatmap : bool,  // samples per pixel instead of the render
+    pub view_aov : Option<Aov>, // shown instead of the render once recorded
+    pub render_error : Option<String>, // of the pass that failed last
+
+    obj_path : String,
+    scene_path : String,
 }
 
 impl Viewport {
-    pub fn draw_scene_setting_window(&mut self, ui : &Ui, viewport_size: &[f32; 2]) {
-        // /*
-        let mut update = false;
-        if let Ok(mut scene) = self.scene.try_write() {
+    pub fn draw_scene_setting_window(&mut self, ui : &Ui) {
+        // widgets draw from a read guard, tiles in flight keep theirs. The write lock is
+        // only taken for the edits, a restart follows them anyway
+        let mut edits = vec![];
+        let mut save = false;
+        let Ok(scene) = self.scene.read() else {
+            log::warn!("scene lock is poisoned, skipping the scene settings window");
+            return;
+        };
 
         ui.window("Scene Settings")
             .size([300.0, 400.0], imgui::Condition::FirstUseEver)
             .position([200.0, 500.0], imgui::Condition::FirstUseEver)
             .build(|| {
-                for i in 0..scene.spheres.len() {
+                for (i, sphere) in scene.spheres.iter().enumerate() {
                     let _id = ui.push_id_usize(i);
+                    let mut sphere = sphere.clone();
 
-                    update |= ui.input_float3("Position", &mut scene.spheres[i].position)
+                    let mut changed = ui.input_float3("Position", &mut sphere.position)
                         .build();
-                    update |= imgui::Drag::new("Radius").range(0.0, f32::MAX)
+                    changed |= imgui::Drag::new("Radius").range(0.0, f32::MAX)
                         .speed(0.05)
-                        .build(ui, &mut scene.spheres[i].radius);
-                    update |= imgui::Drag::new("Material")
+                        .build(ui, &mut sphere.radius);
+                    changed |= imgui::Drag::new("Material")
                         .range(-1, scene.materials.len() as i32 - 1)
-                        .build(ui, &mut scene.spheres[i].material_id);
+                        .build(ui, &mut sphere.material_id);
+                    if changed {
+                        edits.push(SceneEdit::Sphere(i, sphere));
+                    }
 
                     ui.separator();
                 }
@@ -47,68 +76,183 @@ impl Viewport {
                         radius : 1.0,
                         material_id : -1, 
                     };
-                    scene.spheres.push(sphere);
-                    update |= true;
+                    edits.push(SceneEdit::AddSphere(sphere));
+                }
+                ui.separator();
+                ui.separator();
+
+                for (i, mesh) in scene.meshes.iter().enumerate() {
+                    ui.text(format!("Mesh {i} : {} triangles", mesh.triangle_count()));
+                }
+
+                ui.input_text("OBJ Path", &mut self.obj_path).build();
+                if ui.button("Load OBJ") {
+                    match ObjModel::load_obj_model(&self.obj_path) {
+                        Ok(model) => edits.push(SceneEdit::AddObj(model)),
+                        Err(e) => log::warn!("failed loading OBJ {}: {e}", self.obj_path),
+                    }
                 }
                 ui.separator();
                 ui.separator();
 
-                for i in 0..scene.materials.len() {
+                for (i, material) in scene.materials.iter().enumerate() {
                     let _id = ui.push_id_usize(i);
+                    let mut material = material.clone();
+
+                    let mut changed = false;
+                    let mut type_index = match material.material_type {
+                        MaterialType::Opaque => 0,
+                        MaterialType::Dielectric => 1,
+                    };
+                    if ui.combo_simple_string("Type", &mut type_index, &["Opaque", "Dielectric"]) {
+                        material.material_type = match type_index {
+                            1 => MaterialType::Dielectric,
+                            _ => MaterialType::Opaque,
+                        };
+                        changed = true;
+                    }
 
-                    update |= ui.color_edit3("Albedo", &mut scene.materials[i].albedo);
-                    update |= imgui::Drag::new("Roughness").range(0.0, 1.0)
+                    changed |= ui.color_edit3("Albedo", &mut material.albedo);
+                    changed |= imgui::Drag::new("Roughness").range(0.0, 1.0)
+                        .speed(0.005)
+                        .build(ui, &mut material.roughness);
+                    changed |= imgui::Drag::new("Metalic").range(0.0, 1.0)
                         .speed(0.005)
-                        .build(ui, &mut scene.materials[i].roughness);
-                    update |= imgui::Drag::new("Metalic").range(0.0, 1.0)
+                        .build(ui, &mut material.metalic);
+                    changed |= imgui::Drag::new("IOR").range(1.0, 3.0)
                         .speed(0.005)
-                        .build(ui, &mut scene.materials[i].metalic);
-                    update |= ui.color_edit3("Emission Color", &mut scene.materials[i].emission_color);
-                    update |= imgui::Drag::new("Emissive Power").range(0.0, 1.0)
-                        .build(ui, &mut scene.materials[i].emissive_power);
+                        .build(ui, &mut material.ior);
+                    changed |= ui.color_edit3("Emission Color", &mut material.emission_color);
+                    changed |= imgui::Drag::new("Emissive Power").range(0.0, 1.0)
+                        .build(ui, &mut material.emissive_power);
+                    if changed {
+                        edits.push(SceneEdit::Material(i, material));
+                    }
 
                     ui.separator();
                 }
 
                 if ui.button("Add Materal") {
-                    let material = Matrial::default();
-                    scene.materials.push(material);
-                    update |= true;
+                    edits.push(SceneEdit::AddMaterial(Matrial::default()));
                 }
 
-                update |= ui.color_edit3("Sky color", &mut scene.default_sky_color);
+                let mut sky_color = scene.default_sky_color;
+                if ui.color_edit3("Sky color", &mut sky_color) {
+                    edits.push(SceneEdit::SkyColor(sky_color));
+                }
+                ui.separator();
+                ui.separator();
+
+                ui.input_text("Scene Path", &mut self.scene_path).build();
+                if ui.button("Load Scene") {
+                    match SceneFile::load_scene_file(&self.scene_path) {
+                        Ok(file) => edits.push(SceneEdit::Load(Box::new(file))),
+                        Err(e) => log::warn!("failed loading scene {}: {e}", self.scene_path),
+                    }
+                }
+                ui.same_line();
+                save = ui.button("Save Scene");
             });
         drop(scene);
-        
 
-        if update {
-            self.renderer.render_updated(&self.scene,
-                viewport_size[0] as u32,
-                viewport_size[1] as u32,
-            );
+        if save {
+            self.save_scene();
+        }
+        if edits.is_empty() {
+            return;
         }
+
+        let mut loaded_settings = None;
+        let mut scene = self.scene.write().unwrap_or_else(PoisonError::into_inner);
+        for edit in edits {
+            match edit {
+                SceneEdit::Sphere(i, sphere) => scene.spheres[i] = sphere,
+                SceneEdit::AddSphere(sphere) => scene.spheres.push(sphere),
+                SceneEdit::AddObj(model) => scene.add_obj_model(model),
+                SceneEdit::Material(i, material) => scene.materials[i] = material,
+                SceneEdit::AddMaterial(material) => scene.materials.push(material),
+                SceneEdit::SkyColor(color) => scene.default_sky_color = color,
+                SceneEdit::Load(file) => {
+                    *scene = file.scene;
+
+                    // keeps the viewport resolution, the next render sets it anyway
+                    let mut camera = self.camera.write().unwrap();
+                    *camera = file.camera;
+                    drop(camera);
+
+                    loaded_settings = Some(file.settings);
+                }
+            }
+        }
+        drop(scene);
+
+        if let Some(settings) = loaded_settings {
+            let mut renderer = self.session.renderer();
+            renderer.set_bounces(settings.bounces);
+            renderer.set_sampler(settings.sampler);
+            renderer.set_filter(settings.filter);
+            renderer.set_noise_threshold(settings.noise_threshold);
+            renderer.set_time_limit(settings.time_limit);
+            renderer.set_aovs(settings.aovs);
+            renderer.set_denoise(settings.denoise);
+            renderer.set_display_transform(settings.display);
+        }
+
+        self.session.restart();
     }
-        else {
-            println!("Skipping Scene Setting window in this frame!")
+
+    /// Writes the scene with the camera and the render settings to `scene_path`
+    fn save_scene(&self) {
+        // read before locking the scene, the session may wait for it while holding the renderer
+        let settings = self.session.renderer().get_settings();
+
+        // the file owns its scene, hand it back once written
+        let mut scene = self.scene.write().unwrap_or_else(PoisonError::into_inner);
+        let mut file = SceneFile::from_scene(std::mem::take(&mut *scene));
+        let camera = self.camera.read().unwrap();
+        file.camera = camera.clone();
+        [file.width, file.height] = camera.pinhole().image_size;
+        drop(camera);
+        file.settings.bounces = settings.bounces;
+        file.settings.sampler = settings.sampler;
+        file.settings.filter = settings.filter;
+        file.settings.noise_threshold = settings.noise_threshold;
+        file.settings.time_limit = settings.time_limit;
+        file.settings.aovs = settings.aovs;
+        file.settings.denoise = settings.denoise;
+        file.settings.display = settings.display;
+
+        if let Err(e) = file.save_scene_file(&self.scene_path) {
+            log::warn!("failed saving scene {}: {e}", self.scene_path);
         }
+        *scene = file.scene;
     }
 
-    pub fn handle_input(
-        &mut self,
-        ui : &Ui,
-        width : u32,
-        height : u32,
-    ) {
+    /// Puts the plane of focus through the surface seen at pixel (x, y),
+    /// counted from the bottom left like the render output
+    pub fn focus_at(&mut self, x : u32, y : u32) {
+        let Some(point) = self.session.renderer().pick_position(&self.scene, x, y) else {
+            return;
+        };
+
+        if let CameraModel::ThinLens(lens) = &mut *self.camera.write().unwrap() {
+            lens.focus_on(point);
+        }
+        self.session.restart();
+    }
+
+    pub fn handle_input(&mut self, ui : &Ui) {
         let delta_time = ui.io().delta_time;
         let max_pitch = std::f32::consts::FRAC_PI_2 - 0.01;
         let move_speed = 0.25 * delta_time * 30.0;
         let mouse_sensitivity = 0.0005 * delta_time * 30.0; 
         // radians per pixel
         let camera = self.camera.read().unwrap();
+        let pinhole = camera.pinhole();
 
-        let forward = camera.forward;
-        let up = camera.up;
-        let right = camera.right;
+        let forward = pinhole.forward;
+        let up = pinhole.up;
+        let right = pinhole.right;
 
         let key_moves = [
             (imgui::Key::W,  forward),
@@ -119,8 +263,8 @@ impl Viewport {
             (imgui::Key::Q, -up),
         ];
 
-        let mut new_rotation = camera.rotation;
-        let mut new_position = camera.position;
+        let mut new_rotation = pinhole.rotation;
+        let mut new_position = pinhole.position;
 
         drop(camera);
 
@@ -155,8 +299,7 @@ impl Viewport {
         }
 
         if moved {
-            self.renderer
-                .render_updated(&self.scene, width, height);
+            self.session.restart();
         }
 
     }
@@ -168,23 +311,35 @@ impl Default for Viewport {
     fn default() -> Self {
         let position =Vec3::new(0.0, 0.0, 2.0);
         let camera =  Arc::new(RwLock::new(
-            PinholeCamera::new(
+            CameraModel::Pinhole(PinholeCamera::new(
                 position, 
                 Vec3::ZERO,
                 35.0,
                 55.0,
                 [0,0]
-            )
+            ))
         ));
 
         let mut renderer = RayTracer::new(0, 0);
         renderer.set_active_camera(camera.clone());
-        let scene = Arc::new(RwLock::new(Scene::get_example_scene()));
+        let mut scene = Scene::get_example_scene();
+        if let Err(e) = scene.load_skybox(EXAMPLE_SKYBOX_PATH) {
+            log::warn!("failed loading skybox {EXAMPLE_SKYBOX_PATH}: {e}");
+        }
+        let scene = Arc::new(RwLock::new(scene));
+        // the viewport window sets the size on its first frame
+        let session = RenderSession::start(renderer, scene.clone(), 0, 0);
 
         Self {
             camera,
-            renderer,
-            scene
+            picking_focus : false,
+            show_heatmap : false,
+            view_aov : None,
+            render_error : None,
+            session,
+            scene,
+            obj_path : String::new(),
+            scene_path : String::new(),
         }
     }
 }